@@ -0,0 +1,1042 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::os::raw::c_char;
+use std::str::FromStr;
+
+use libc::size_t;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use solana_program::hash;
+use solana_program::instruction::{AccountMeta, Instruction};
+use solana_program::pubkey::Pubkey;
+
+use crate::borsh_export::{BorshField, BorshPrimitive, BorshSchema, BorshValue, BorshVariant};
+use crate::c_sharp_string::{c_char_ptr_from_string, str_from_c_char_ptr};
+use crate::common_types::{ResultExport, slice_from_ptr, to_raw_parts, VecRawParts};
+use crate::error_export::{ErrorCode, export_result, ExportError};
+use crate::handle_table::{self, Handle, HandleObject, HandleType};
+
+pub const DISCRIMINATOR_LENGTH: usize = 8;
+
+/// Bounds the chain of defined types expanded inside each other.
+const MAX_TYPE_DEPTH: usize = 64;
+
+/// Bounds the size of an expanded schema. Defined types are expanded inline, so types
+/// using other types several times grow exponentially with the nesting.
+const MAX_SCHEMA_NODES: usize = 1 << 16;
+
+/// Anchor IDL, in either the legacy (before Anchor 0.30) or the current layout.
+///
+/// Type descriptions are kept as JSON and turned into a `BorshSchema` when used.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Idl {
+    #[serde(default)]
+    address: Option<String>,
+    #[serde(default)]
+    metadata: Option<IdlMetadata>,
+    #[serde(default)]
+    instructions: Vec<IdlInstruction>,
+    #[serde(default)]
+    accounts: Vec<IdlAccount>,
+    #[serde(default)]
+    types: Vec<IdlTypeDef>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct IdlMetadata {
+    #[serde(default)]
+    address: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct IdlInstruction {
+    name: String,
+    #[serde(default)]
+    discriminator: Option<Vec<u8>>,
+    #[serde(default)]
+    accounts: Vec<IdlInstructionAccount>,
+    #[serde(default)]
+    args: Vec<IdlField>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct IdlInstructionAccount {
+    name: String,
+    #[serde(default, alias = "isMut")]
+    writable: bool,
+    #[serde(default, alias = "isSigner")]
+    signer: bool,
+    #[serde(default, alias = "isOptional")]
+    optional: bool,
+    #[serde(default)]
+    address: Option<String>,
+    /// Set for a group of accounts declared by a nested `Accounts` struct.
+    #[serde(default)]
+    accounts: Option<Vec<IdlInstructionAccount>>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct IdlField {
+    name: String,
+    #[serde(rename = "type")]
+    ty: Value,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct IdlAccount {
+    name: String,
+    #[serde(default)]
+    discriminator: Option<Vec<u8>>,
+    /// Only present in legacy IDLs, current ones describe the account in `types`.
+    #[serde(default, rename = "type")]
+    ty: Option<Value>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct IdlTypeDef {
+    name: String,
+    #[serde(rename = "type")]
+    ty: Value,
+}
+
+impl HandleObject for Idl {
+    const HANDLE_TYPE: HandleType = HandleType::AnchorIdl;
+}
+
+/// State of turning IDL types into one schema.
+#[derive(Default)]
+struct SchemaContext<'a> {
+    /// Defined types being expanded, innermost last. Borsh has no way to encode a type
+    /// containing itself, so a name showing up twice is an error.
+    expanding: Vec<&'a str>,
+    /// Defined types already expanded, with their node counts.
+    resolved: HashMap<&'a str, (BorshSchema, usize)>,
+    nodes: usize,
+}
+
+impl SchemaContext<'_> {
+    fn add_nodes(&mut self, nodes: usize) -> Result<(), ExportError> {
+        self.nodes += nodes;
+        if self.nodes > MAX_SCHEMA_NODES {
+            return Err(invalid_idl("IDL types expand to too large a schema"));
+        }
+        Ok(())
+    }
+}
+
+fn invalid_idl<S: Into<String>>(message: S) -> ExportError {
+    ExportError::new(ErrorCode::InvalidIdl, message)
+}
+
+/// First 8 bytes of `sha256("<namespace>:<name>")`, as Anchor prefixes instruction and account data.
+pub fn anchor_discriminator(namespace: &str, name: &str) -> [u8; DISCRIMINATOR_LENGTH] {
+    let mut discriminator = [0u8; DISCRIMINATOR_LENGTH];
+    discriminator.copy_from_slice(&hash::hash(format!("{}:{}", namespace, name).as_bytes()).to_bytes()[..DISCRIMINATOR_LENGTH]);
+    discriminator
+}
+
+/// Legacy IDLs name instructions in camelCase, while discriminators hash the Rust snake_case name.
+pub fn to_snake_case(name: &str) -> String {
+    let mut snake_case = String::with_capacity(name.len() + 4);
+    let mut previous: Option<char> = None;
+    for c in name.chars() {
+        if c.is_uppercase() {
+            if previous.is_some_and(|previous| previous.is_lowercase() || previous.is_ascii_digit()) {
+                snake_case.push('_');
+            }
+            snake_case.extend(c.to_lowercase());
+        } else {
+            snake_case.push(c);
+        }
+        previous = Some(c);
+    }
+    snake_case
+}
+
+/// Compares names the way Anchor clients do, ignoring case and word separators.
+fn same_name(name: &str, other: &str) -> bool {
+    let normalize = |name: &str| name.chars().filter(|c| *c != '_').flat_map(char::to_lowercase).collect::<String>();
+    name == other || normalize(name) == normalize(other)
+}
+
+fn discriminator_from_idl(discriminator: &[u8]) -> Result<[u8; DISCRIMINATOR_LENGTH], ExportError> {
+    <[u8; DISCRIMINATOR_LENGTH]>::try_from(discriminator)
+        .map_err(|_| invalid_idl(format!("Discriminator has length {}, expected {}", discriminator.len(), DISCRIMINATOR_LENGTH)))
+}
+
+fn primitive_schema(name: &str) -> Result<BorshSchema, ExportError> {
+    let primitive = match name {
+        "bool" => BorshPrimitive::Bool,
+        "u8" => BorshPrimitive::U8,
+        "u16" => BorshPrimitive::U16,
+        "u32" => BorshPrimitive::U32,
+        "u64" => BorshPrimitive::U64,
+        "u128" => BorshPrimitive::U128,
+        "i8" => BorshPrimitive::I8,
+        "i16" => BorshPrimitive::I16,
+        "i32" => BorshPrimitive::I32,
+        "i64" => BorshPrimitive::I64,
+        "i128" => BorshPrimitive::I128,
+        "string" => BorshPrimitive::String,
+        "publicKey" | "pubkey" => BorshPrimitive::Pubkey,
+        "bytes" => return Ok(BorshSchema::Vec(Box::new(BorshSchema::Primitive(BorshPrimitive::U8)))),
+        _ => return Err(invalid_idl(format!("Unsupported IDL type {}", name))),
+    };
+    Ok(BorshSchema::Primitive(primitive))
+}
+
+impl Idl {
+    pub fn program_id(&self) -> Result<Pubkey, ExportError> {
+        let address = self.address.as_ref()
+            .or_else(|| self.metadata.as_ref().and_then(|metadata| metadata.address.as_ref()))
+            .ok_or_else(|| invalid_idl("IDL does not declare a program address"))?;
+        Ok(Pubkey::from_str(address)?)
+    }
+
+    fn instruction(&self, name: &str) -> Result<&IdlInstruction, ExportError> {
+        self.instructions.iter().find(|instruction| instruction.name == name)
+            .or_else(|| self.instructions.iter().find(|instruction| same_name(&instruction.name, name)))
+            .ok_or_else(|| ExportError::invalid_argument(format!("IDL has no instruction {}", name)))
+    }
+
+    fn account(&self, name: &str) -> Result<&IdlAccount, ExportError> {
+        self.accounts.iter().find(|account| account.name == name)
+            .or_else(|| self.accounts.iter().find(|account| same_name(&account.name, name)))
+            .ok_or_else(|| ExportError::invalid_argument(format!("IDL has no account {}", name)))
+    }
+
+    fn type_def(&self, name: &str) -> Result<&IdlTypeDef, ExportError> {
+        self.types.iter().find(|type_def| type_def.name == name)
+            .ok_or_else(|| invalid_idl(format!("IDL does not define type {}", name)))
+    }
+
+    fn instruction_discriminator(&self, instruction: &IdlInstruction) -> Result<[u8; DISCRIMINATOR_LENGTH], ExportError> {
+        match &instruction.discriminator {
+            Some(discriminator) => discriminator_from_idl(discriminator),
+            None => Ok(anchor_discriminator("global", &to_snake_case(&instruction.name))),
+        }
+    }
+
+    fn account_discriminator(&self, account: &IdlAccount) -> Result<[u8; DISCRIMINATOR_LENGTH], ExportError> {
+        match &account.discriminator {
+            Some(discriminator) => discriminator_from_idl(discriminator),
+            None => Ok(anchor_discriminator("account", &account.name)),
+        }
+    }
+
+    fn account_schema(&self, account: &IdlAccount) -> Result<BorshSchema, ExportError> {
+        let mut context = SchemaContext::default();
+        match &account.ty {
+            Some(ty) => self.type_def_schema(ty, &mut context),
+            None => self.defined_schema(&account.name, &mut context),
+        }
+    }
+
+    /// Name of the IDL account whose discriminator starts `data`.
+    pub fn account_name(&self, data: &[u8]) -> Result<&str, ExportError> {
+        for account in &self.accounts {
+            if data.starts_with(&self.account_discriminator(account)?) {
+                return Ok(&account.name);
+            }
+        }
+        Err(ExportError::new(ErrorCode::DiscriminatorMismatch, "Data does not start with the discriminator of any IDL account"))
+    }
+
+    /// Decodes the data of account `name` into JSON, see `json_from_value`.
+    ///
+    /// Bytes after the encoded fields are ignored, accounts are often allocated with spare room.
+    pub fn decode_account(&self, name: &str, data: &[u8]) -> Result<Value, ExportError> {
+        let account = self.account(name)?;
+        if data.len() < DISCRIMINATOR_LENGTH {
+            return Err(ExportError::invalid_length("data", DISCRIMINATOR_LENGTH, data.len()));
+        }
+        let (discriminator, mut fields) = data.split_at(DISCRIMINATOR_LENGTH);
+        if discriminator != self.account_discriminator(account)? {
+            return Err(ExportError::new(
+                ErrorCode::DiscriminatorMismatch,
+                format!("Data does not start with the discriminator of account {}", account.name),
+            ));
+        }
+
+        let schema = self.account_schema(account)?;
+        let value = schema.deserialize(&mut fields)?;
+        json_from_value(&schema, &value)
+    }
+
+    /// Turns an IDL type such as `"u64"`, `{"vec": "u8"}` or `{"defined": "Foo"}` into a schema.
+    pub fn schema(&self, ty: &Value) -> Result<BorshSchema, ExportError> {
+        self.schema_in(ty, &mut SchemaContext::default())
+    }
+
+    fn schema_in<'a>(&'a self, ty: &Value, context: &mut SchemaContext<'a>) -> Result<BorshSchema, ExportError> {
+        let object = match ty {
+            Value::String(name) => {
+                context.add_nodes(1)?;
+                return primitive_schema(name);
+            }
+            Value::Object(object) => object,
+            _ => return Err(invalid_idl(format!("Invalid IDL type {}", ty))),
+        };
+
+        if let Some(inner) = object.get("vec") {
+            context.add_nodes(1)?;
+            Ok(BorshSchema::Vec(Box::new(self.schema_in(inner, context)?)))
+        } else if let Some(inner) = object.get("option") {
+            context.add_nodes(1)?;
+            Ok(BorshSchema::Option(Box::new(self.schema_in(inner, context)?)))
+        } else if let Some(array) = object.get("array") {
+            match array.as_array().map(Vec::as_slice) {
+                Some([inner, Value::Number(len)]) => {
+                    let len = len.as_u64().and_then(|len| u32::try_from(len).ok())
+                        .ok_or_else(|| invalid_idl(format!("Invalid array length {}", len)))?;
+                    context.add_nodes(1)?;
+                    Ok(BorshSchema::Array(Box::new(self.schema_in(inner, context)?), len))
+                }
+                _ => Err(invalid_idl(format!("Unsupported IDL array type {}", array))),
+            }
+        } else if let Some(defined) = object.get("defined") {
+            let name = match defined {
+                Value::String(name) => name,
+                Value::Object(defined) => defined.get("name").and_then(Value::as_str)
+                    .ok_or_else(|| invalid_idl(format!("Invalid IDL type {}", ty)))?,
+                _ => return Err(invalid_idl(format!("Invalid IDL type {}", ty))),
+            };
+            self.defined_schema(name, context)
+        } else {
+            Err(invalid_idl(format!("Unsupported IDL type {}", ty)))
+        }
+    }
+
+    /// Schema of the defined type `name`, expanded once per context.
+    fn defined_schema<'a>(&'a self, name: &str, context: &mut SchemaContext<'a>) -> Result<BorshSchema, ExportError> {
+        let type_def = self.type_def(name)?;
+        let name = type_def.name.as_str();
+        if let Some((schema, nodes)) = context.resolved.get(name) {
+            let (schema, nodes) = (schema.clone(), *nodes);
+            context.add_nodes(nodes)?;
+            return Ok(schema);
+        }
+        if context.expanding.contains(&name) {
+            return Err(invalid_idl(format!("IDL type {} contains itself", name)));
+        }
+        if context.expanding.len() >= MAX_TYPE_DEPTH {
+            return Err(invalid_idl("IDL types nest too deeply"));
+        }
+
+        context.expanding.push(name);
+        let nodes_before = context.nodes;
+        let schema = self.type_def_schema(&type_def.ty, context);
+        context.expanding.pop();
+        let schema = schema?;
+        context.resolved.insert(name, (schema.clone(), context.nodes - nodes_before));
+        Ok(schema)
+    }
+
+    /// Schema of the `type` of a type definition: a struct, an enum or an alias.
+    fn type_def_schema<'a>(&'a self, ty: &Value, context: &mut SchemaContext<'a>) -> Result<BorshSchema, ExportError> {
+        match ty.get("kind").and_then(Value::as_str) {
+            Some("struct") => {
+                context.add_nodes(1)?;
+                Ok(BorshSchema::Struct(self.fields_schema(ty.get("fields"), context)?))
+            }
+            Some("enum") => {
+                let variants = ty.get("variants").and_then(Value::as_array)
+                    .ok_or_else(|| invalid_idl("Enum type without variants"))?;
+                if variants.len() > u8::MAX as usize + 1 {
+                    return Err(invalid_idl("Enum type has more than 256 variants"));
+                }
+                context.add_nodes(1)?;
+                variants
+                    .iter()
+                    .map(|variant| Ok(BorshVariant {
+                        name: variant.get("name").and_then(Value::as_str)
+                            .ok_or_else(|| invalid_idl("Enum variant without a name"))?
+                            .to_string(),
+                        fields: self.fields_schema(variant.get("fields"), context)?,
+                    }))
+                    .collect::<Result<_, _>>()
+                    .map(BorshSchema::Enum)
+            }
+            Some("type") => self.schema_in(
+                ty.get("alias").ok_or_else(|| invalid_idl("Type alias without a target"))?, context),
+            _ => Err(invalid_idl(format!("Unsupported IDL type definition {}", ty))),
+        }
+    }
+
+    /// Named fields are `{"name", "type"}` objects, tuple fields are bare types named by position.
+    fn fields_schema<'a>(&'a self, fields: Option<&Value>, context: &mut SchemaContext<'a>) -> Result<Vec<BorshField>, ExportError> {
+        let fields = match fields {
+            None | Some(Value::Null) => return Ok(Vec::new()),
+            Some(Value::Array(fields)) => fields,
+            Some(fields) => return Err(invalid_idl(format!("Invalid IDL fields {}", fields))),
+        };
+        fields
+            .iter()
+            .enumerate()
+            .map(|(index, field)| match (field.get("name").and_then(Value::as_str), field.get("type")) {
+                (Some(name), Some(ty)) => Ok(BorshField {
+                    name: name.to_string(),
+                    schema: self.schema_in(ty, context)?,
+                }),
+                _ => Ok(BorshField {
+                    name: index.to_string(),
+                    schema: self.schema_in(field, context)?,
+                }),
+            })
+            .collect()
+    }
+
+    fn args_schema(&self, instruction: &IdlInstruction) -> Result<BorshSchema, ExportError> {
+        let mut context = SchemaContext::default();
+        instruction.args
+            .iter()
+            .map(|arg| Ok(BorshField { name: arg.name.clone(), schema: self.schema_in(&arg.ty, &mut context)? }))
+            .collect::<Result<_, _>>()
+            .map(BorshSchema::Struct)
+    }
+
+    /// Discriminator followed by the borsh encoded arguments from the `args` JSON object.
+    pub fn encode_instruction_data(&self, name: &str, args: &Value) -> Result<Vec<u8>, ExportError> {
+        let instruction = self.instruction(name)?;
+        let schema = self.args_schema(instruction)?;
+        let args = value_from_json(&schema, args, "args")?;
+
+        let mut data = self.instruction_discriminator(instruction)?.to_vec();
+        schema.serialize(&args, &mut data)?;
+        Ok(data)
+    }
+
+    /// Account metas of instruction `name`, in IDL order, from the `accounts` JSON object
+    /// mapping account names to base58 addresses.
+    pub fn account_metas(&self, name: &str, accounts: &Value, program_id: &Pubkey) -> Result<Vec<AccountMeta>, ExportError> {
+        let accounts = accounts.as_object()
+            .ok_or_else(|| ExportError::invalid_argument("accounts must be a JSON object"))?;
+        let mut account_metas = Vec::new();
+        collect_account_metas(&self.instruction(name)?.accounts, accounts, program_id, &mut account_metas)?;
+        Ok(account_metas)
+    }
+}
+
+/// Nested account groups may be passed as nested objects or flattened into `accounts`.
+fn collect_account_metas(items: &[IdlInstructionAccount], accounts: &Map<String, Value>, program_id: &Pubkey,
+                         account_metas: &mut Vec<AccountMeta>) -> Result<(), ExportError> {
+    for item in items {
+        if let Some(group) = &item.accounts {
+            let nested = accounts.get(&item.name).and_then(Value::as_object).unwrap_or(accounts);
+            collect_account_metas(group, nested, program_id, account_metas)?;
+            continue;
+        }
+
+        let address = match accounts.get(&item.name) {
+            Some(Value::String(address)) => Some(address.as_str()),
+            None | Some(Value::Null) => item.address.as_deref(),
+            Some(address) => return Err(ExportError::invalid_argument(
+                format!("Account {} must be a base58 string, got {}", item.name, address))),
+        };
+        account_metas.push(match address {
+            Some(address) => AccountMeta {
+                pubkey: Pubkey::from_str(address)?,
+                is_signer: item.signer,
+                is_writable: item.writable,
+            },
+            // Anchor passes the program id in place of an omitted optional account.
+            None if item.optional => AccountMeta::new_readonly(*program_id, false),
+            None => return Err(ExportError::invalid_argument(format!("Missing account {}", item.name))),
+        });
+    }
+    Ok(())
+}
+
+fn json_mismatch(path: &str, expected: &str, json: &Value) -> ExportError {
+    ExportError::invalid_argument(format!("{} must be {}, got {}", path, expected, json))
+}
+
+/// Integers may be JSON numbers or decimal strings, for values beyond the range of a double.
+fn integer_from_json<T: FromStr>(json: &Value, path: &str) -> Result<T, ExportError> {
+    let parsed = match json {
+        Value::Number(number) => number.to_string().parse().ok(),
+        Value::String(number) => number.parse().ok(),
+        _ => None,
+    };
+    parsed.ok_or_else(|| json_mismatch(path, "an integer", json))
+}
+
+fn fields_from_json(fields: &[BorshField], json: &Value, path: &str) -> Result<Vec<(String, BorshValue)>, ExportError> {
+    match json {
+        Value::Object(object) => fields
+            .iter()
+            .map(|field| {
+                let path = format!("{}.{}", path, field.name);
+                let json = object.get(&field.name).unwrap_or(&Value::Null);
+                Ok((field.name.clone(), value_from_json(&field.schema, json, &path)?))
+            })
+            .collect(),
+        Value::Array(values) if values.len() == fields.len() => fields
+            .iter()
+            .zip(values)
+            .map(|(field, json)| {
+                let path = format!("{}.{}", path, field.name);
+                Ok((field.name.clone(), value_from_json(&field.schema, json, &path)?))
+            })
+            .collect(),
+        Value::Null if fields.is_empty() => Ok(Vec::new()),
+        _ => Err(json_mismatch(path, &format!("an object with {} fields", fields.len()), json)),
+    }
+}
+
+/// Builds the value tree for `schema` from JSON in the shape Anchor clients use.
+///
+/// Enums are written as `"Variant"` or `{"variant": {fields}}`, missing struct fields are
+/// treated as `null`, so they can only be omitted for `option` types.
+pub fn value_from_json(schema: &BorshSchema, json: &Value, path: &str) -> Result<BorshValue, ExportError> {
+    Ok(match schema {
+        BorshSchema::Primitive(primitive) => match primitive {
+            BorshPrimitive::Bool => BorshValue::Bool(json.as_bool().ok_or_else(|| json_mismatch(path, "a boolean", json))?),
+            BorshPrimitive::U8 | BorshPrimitive::U16 | BorshPrimitive::U32 | BorshPrimitive::U64 | BorshPrimitive::U128 =>
+                BorshValue::Unsigned(integer_from_json(json, path)?),
+            BorshPrimitive::I8 | BorshPrimitive::I16 | BorshPrimitive::I32 | BorshPrimitive::I64 | BorshPrimitive::I128 =>
+                BorshValue::Signed(integer_from_json(json, path)?),
+            BorshPrimitive::String => BorshValue::String(
+                json.as_str().ok_or_else(|| json_mismatch(path, "a string", json))?.to_string()),
+            BorshPrimitive::Pubkey => BorshValue::Pubkey(Pubkey::from_str(
+                json.as_str().ok_or_else(|| json_mismatch(path, "a base58 string", json))?)?),
+        },
+        BorshSchema::Option(inner) => match json {
+            Value::Null => BorshValue::Option(None),
+            json => BorshValue::Option(Some(Box::new(value_from_json(inner, json, path)?))),
+        },
+        BorshSchema::Vec(inner) | BorshSchema::Array(inner, _) => BorshValue::Sequence(
+            json.as_array()
+                .ok_or_else(|| json_mismatch(path, "an array", json))?
+                .iter()
+                .enumerate()
+                .map(|(index, json)| value_from_json(inner, json, &format!("{}[{}]", path, index)))
+                .collect::<Result<_, _>>()?
+        ),
+        BorshSchema::Struct(fields) => BorshValue::Struct(fields_from_json(fields, json, path)?),
+        BorshSchema::Enum(variants) => {
+            let (name, fields) = match json {
+                Value::String(name) => (name, &Value::Null),
+                Value::Object(object) if object.len() == 1 => object.iter().next().expect("object has one entry"),
+                _ => return Err(json_mismatch(path, "an enum variant", json)),
+            };
+            let (variant, schema) = variants.iter().enumerate()
+                .find(|(_, variant)| same_name(&variant.name, name))
+                .ok_or_else(|| ExportError::invalid_argument(format!("{} has no variant {}", path, name)))?;
+            BorshValue::Enum {
+                variant: variant as u8,
+                fields: fields_from_json(&schema.fields, fields, &format!("{}.{}", path, name))?,
+            }
+        }
+    })
+}
+
+/// Tuple fields are named by their position, see `Idl::fields_schema`.
+fn is_tuple(fields: &[BorshField]) -> bool {
+    !fields.is_empty() && fields.iter().enumerate().all(|(index, field)| field.name == index.to_string())
+}
+
+fn json_from_fields(fields: &[BorshField], values: &[(String, BorshValue)]) -> Result<Value, ExportError> {
+    let values = fields
+        .iter()
+        .zip(values)
+        .map(|(field, (name, value))| Ok((name.clone(), json_from_value(&field.schema, value)?)))
+        .collect::<Result<Vec<_>, ExportError>>()?;
+    Ok(if is_tuple(fields) {
+        Value::Array(values.into_iter().map(|(_, value)| value).collect())
+    } else {
+        Value::Object(values.into_iter().collect())
+    })
+}
+
+/// Turns a value tree decoded with `schema` into JSON accepted back by `value_from_json`.
+///
+/// 64 and 128 bit integers become decimal strings, as they do not fit in a double. Enums
+/// become `{"Variant": {fields}}`, tuple structs and variants become arrays.
+pub fn json_from_value(schema: &BorshSchema, value: &BorshValue) -> Result<Value, ExportError> {
+    let mismatch = || ExportError::new(
+        ErrorCode::BorshSchemaMismatch,
+        format!("{:?} value does not match {} schema", value.kind(), schema.describe()),
+    );
+    Ok(match (schema, value) {
+        (BorshSchema::Primitive(BorshPrimitive::U64 | BorshPrimitive::U128), BorshValue::Unsigned(value)) =>
+            Value::String(value.to_string()),
+        (BorshSchema::Primitive(BorshPrimitive::I64 | BorshPrimitive::I128), BorshValue::Signed(value)) =>
+            Value::String(value.to_string()),
+        (BorshSchema::Primitive(_), BorshValue::Unsigned(value)) => Value::from(u64::try_from(*value).map_err(|_| mismatch())?),
+        (BorshSchema::Primitive(_), BorshValue::Signed(value)) => Value::from(i64::try_from(*value).map_err(|_| mismatch())?),
+        (BorshSchema::Primitive(_), BorshValue::Bool(value)) => Value::Bool(*value),
+        (BorshSchema::Primitive(_), BorshValue::String(value)) => Value::String(value.clone()),
+        (BorshSchema::Primitive(_), BorshValue::Pubkey(value)) => Value::String(value.to_string()),
+        (BorshSchema::Option(_), BorshValue::Option(None)) => Value::Null,
+        (BorshSchema::Option(inner), BorshValue::Option(Some(value))) => json_from_value(inner, value)?,
+        (BorshSchema::Vec(inner) | BorshSchema::Array(inner, _), BorshValue::Sequence(values)) => Value::Array(
+            values.iter().map(|value| json_from_value(inner, value)).collect::<Result<_, _>>()?
+        ),
+        (BorshSchema::Struct(fields), BorshValue::Struct(values)) => json_from_fields(fields, values)?,
+        (BorshSchema::Enum(variants), BorshValue::Enum { variant, fields: values }) => {
+            let variant = variants.get(*variant as usize).ok_or_else(mismatch)?;
+            let mut object = Map::new();
+            object.insert(variant.name.clone(), json_from_fields(&variant.fields, values)?);
+            Value::Object(object)
+        }
+        _ => return Err(mismatch()),
+    })
+}
+
+fn json_from_c_char_ptr(json: *const c_char) -> Result<Value, ExportError> {
+    Ok(serde_json::from_str(str_from_c_char_ptr(json)?)?)
+}
+
+#[no_mangle]
+extern "C" fn anchor_idl_parse(json: *const c_char) -> ResultExport<Handle> {
+    export_result(|| {
+        let idl: Idl = serde_json::from_str(str_from_c_char_ptr(json)?)
+            .map_err(|error| invalid_idl(error.to_string()))?;
+        Ok(handle_table::insert(idl))
+    })
+}
+
+/// Program address declared by the IDL.
+#[no_mangle]
+extern "C" fn anchor_idl_program_id(idl: Handle) -> ResultExport<Handle> {
+    export_result(|| {
+        let program_id = handle_table::with(idl, |idl: &mut Idl| idl.program_id())??;
+        Ok(handle_table::insert(program_id))
+    })
+}
+
+#[no_mangle]
+extern "C" fn anchor_idl_instruction_discriminator(idl: Handle, name: *const c_char) -> ResultExport<VecRawParts> {
+    export_result(|| {
+        let name = str_from_c_char_ptr(name)?;
+        handle_table::with(idl, |idl: &mut Idl| {
+            let discriminator = idl.instruction_discriminator(idl.instruction(name)?)?;
+            Ok(to_raw_parts(discriminator.to_vec()))
+        })?
+    })
+}
+
+/// Instruction data for `name`, with the arguments taken from the `args` JSON object.
+#[no_mangle]
+extern "C" fn anchor_idl_encode_instruction_data(idl: Handle, name: *const c_char, args: *const c_char) -> ResultExport<VecRawParts> {
+    export_result(|| {
+        let name = str_from_c_char_ptr(name)?;
+        let args = json_from_c_char_ptr(args)?;
+        handle_table::with(idl, |idl: &mut Idl| Ok(to_raw_parts(idl.encode_instruction_data(name, &args)?)))?
+    })
+}
+
+/// Builds instruction `name` from the `accounts` JSON object, mapping account names to base58
+/// addresses, and the `args` JSON object. A zero `program_id` uses the IDL's program address.
+#[no_mangle]
+extern "C" fn anchor_idl_instruction_new(idl: Handle, name: *const c_char, accounts: *const c_char,
+                                         args: *const c_char, program_id: Handle) -> ResultExport<Handle> {
+    export_result(|| {
+        let name = str_from_c_char_ptr(name)?;
+        let accounts = json_from_c_char_ptr(accounts)?;
+        let args = json_from_c_char_ptr(args)?;
+        let program_id = match program_id {
+            0 => None,
+            program_id => Some(handle_table::get::<Pubkey>(program_id)?),
+        };
+
+        let instruction = handle_table::with(idl, |idl: &mut Idl| {
+            let program_id = match program_id {
+                Some(program_id) => program_id,
+                None => idl.program_id()?,
+            };
+            Ok::<_, ExportError>(Instruction {
+                program_id,
+                accounts: idl.account_metas(name, &accounts, &program_id)?,
+                data: idl.encode_instruction_data(name, &args)?,
+            })
+        })??;
+        Ok(handle_table::insert(instruction))
+    })
+}
+
+/// Discriminator that starts the data of account `name`, e.g. for `memcmp` filters.
+#[no_mangle]
+extern "C" fn anchor_idl_account_discriminator(idl: Handle, name: *const c_char) -> ResultExport<VecRawParts> {
+    export_result(|| {
+        let name = str_from_c_char_ptr(name)?;
+        handle_table::with(idl, |idl: &mut Idl| {
+            let discriminator = idl.account_discriminator(idl.account(name)?)?;
+            Ok(to_raw_parts(discriminator.to_vec()))
+        })?
+    })
+}
+
+/// Name of the IDL account type `data` belongs to, going by its discriminator.
+#[no_mangle]
+extern "C" fn anchor_idl_account_name(idl: Handle, data: *const u8, len: size_t) -> ResultExport<*mut c_char> {
+    export_result(|| {
+        let data = slice_from_ptr(data, len, "data")?;
+        let name = handle_table::with(idl, |idl: &mut Idl| idl.account_name(data).map(str::to_string))??;
+        c_char_ptr_from_string(name)
+    })
+}
+
+/// Decodes the data of account `name` into a JSON object, to be released with `free_cstring`.
+#[no_mangle]
+extern "C" fn anchor_idl_decode_account(idl: Handle, name: *const c_char, data: *const u8, len: size_t) -> ResultExport<*mut c_char> {
+    export_result(|| {
+        let name = str_from_c_char_ptr(name)?;
+        let data = slice_from_ptr(data, len, "data")?;
+        let json = handle_table::with(idl, |idl: &mut Idl| idl.decode_account(name, data))??;
+        c_char_ptr_from_string(json.to_string())
+    })
+}
+
+#[no_mangle]
+extern "C" fn free_anchor_idl(idl: Handle) -> ResultExport<u8> {
+    export_result(|| {
+        handle_table::remove::<Idl>(idl)?;
+        Ok(true as u8)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    const PROGRAM_ID: &str = "Fg6PaFpoGXkYsidMpWTK6W2BeZ7FEfcYkg476zPFsLnS";
+
+    /// IDL in the layout of Anchor before 0.30: camelCase names, `isMut`/`isSigner` and no discriminators.
+    const LEGACY_IDL: &str = r#"{
+        "version": "0.1.0",
+        "name": "counter",
+        "instructions": [
+            {
+                "name": "initializeCounter",
+                "accounts": [
+                    {"name": "counter", "isMut": true, "isSigner": false},
+                    {"name": "authority", "isMut": true, "isSigner": true},
+                    {"name": "systemProgram", "isMut": false, "isSigner": false}
+                ],
+                "args": [
+                    {"name": "start", "type": "u64"},
+                    {"name": "label", "type": {"option": "string"}}
+                ]
+            },
+            {
+                "name": "increment",
+                "accounts": [
+                    {
+                        "name": "common",
+                        "accounts": [
+                            {"name": "counter", "isMut": true, "isSigner": false},
+                            {"name": "authority", "isMut": false, "isSigner": true}
+                        ]
+                    },
+                    {"name": "referrer", "isMut": false, "isSigner": false, "isOptional": true},
+                    {"name": "clock", "isMut": false, "isSigner": false}
+                ],
+                "args": [{"name": "step", "type": {"defined": "Step"}}]
+            }
+        ],
+        "accounts": [
+            {
+                "name": "Counter",
+                "type": {
+                    "kind": "struct",
+                    "fields": [
+                        {"name": "authority", "type": "publicKey"},
+                        {"name": "count", "type": "u64"},
+                        {"name": "history", "type": {"vec": "i16"}}
+                    ]
+                }
+            }
+        ],
+        "types": [
+            {
+                "name": "Step",
+                "type": {
+                    "kind": "enum",
+                    "variants": [
+                        {"name": "One"},
+                        {"name": "By", "fields": ["u32"]},
+                        {"name": "Scaled", "fields": [
+                            {"name": "factor", "type": "u8"},
+                            {"name": "times", "type": {"array": ["u8", 2]}}
+                        ]}
+                    ]
+                }
+            }
+        ],
+        "metadata": {"address": "Fg6PaFpoGXkYsidMpWTK6W2BeZ7FEfcYkg476zPFsLnS"}
+    }"#;
+
+    /// IDL in the layout of Anchor 0.30: snake_case names, explicit discriminators and fixed addresses.
+    const IDL: &str = r#"{
+        "address": "Fg6PaFpoGXkYsidMpWTK6W2BeZ7FEfcYkg476zPFsLnS",
+        "metadata": {"name": "counter", "version": "0.1.0", "spec": "0.1.0"},
+        "instructions": [
+            {
+                "name": "initialize_counter",
+                "discriminator": [67, 89, 100, 87, 231, 172, 35, 124],
+                "accounts": [
+                    {"name": "counter", "writable": true},
+                    {"name": "authority", "writable": true, "signer": true},
+                    {"name": "system_program", "address": "11111111111111111111111111111111"}
+                ],
+                "args": [
+                    {"name": "start", "type": "u64"},
+                    {"name": "config", "type": {"defined": {"name": "Config"}}}
+                ]
+            }
+        ],
+        "accounts": [
+            {"name": "Counter", "discriminator": [255, 176, 4, 245, 188, 253, 124, 25]}
+        ],
+        "types": [
+            {
+                "name": "Counter",
+                "type": {
+                    "kind": "struct",
+                    "fields": [
+                        {"name": "authority", "type": "pubkey"},
+                        {"name": "count", "type": "u64"},
+                        {"name": "config", "type": {"defined": {"name": "Config"}}}
+                    ]
+                }
+            },
+            {
+                "name": "Config",
+                "type": {
+                    "kind": "struct",
+                    "fields": [
+                        {"name": "max", "type": {"option": "u32"}},
+                        {"name": "paused", "type": "bool"}
+                    ]
+                }
+            }
+        ]
+    }"#;
+
+    const INITIALIZE_COUNTER_DISCRIMINATOR: [u8; 8] = [67, 89, 100, 87, 231, 172, 35, 124];
+    const INCREMENT_DISCRIMINATOR: [u8; 8] = [11, 18, 104, 9, 104, 174, 59, 33];
+    const COUNTER_DISCRIMINATOR: [u8; 8] = [255, 176, 4, 245, 188, 253, 124, 25];
+
+    fn parse(json: &str) -> Idl {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn idl_with_types(types: Value) -> Idl {
+        serde_json::from_value(json!({
+            "instructions": [{"name": "create", "accounts": [], "args": [{"name": "node", "type": {"defined": "Node"}}]}],
+            "types": types,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn program_id_is_read_from_either_layout() {
+        let program_id = Pubkey::from_str(PROGRAM_ID).unwrap();
+        assert_eq!(parse(LEGACY_IDL).program_id().unwrap(), program_id);
+        assert_eq!(parse(IDL).program_id().unwrap(), program_id);
+    }
+
+    #[test]
+    fn instruction_discriminators_hash_the_snake_case_name() {
+        assert_eq!(anchor_discriminator("global", "initialize_counter"), INITIALIZE_COUNTER_DISCRIMINATOR);
+        assert_eq!(anchor_discriminator("global", "increment"), INCREMENT_DISCRIMINATOR);
+        assert_eq!(to_snake_case("initializeCounter"), "initialize_counter");
+
+        let legacy = parse(LEGACY_IDL);
+        let instruction = legacy.instruction("initializeCounter").unwrap();
+        assert_eq!(legacy.instruction_discriminator(instruction).unwrap(), INITIALIZE_COUNTER_DISCRIMINATOR);
+        let instruction = legacy.instruction("increment").unwrap();
+        assert_eq!(legacy.instruction_discriminator(instruction).unwrap(), INCREMENT_DISCRIMINATOR);
+
+        let idl = parse(IDL);
+        let instruction = idl.instruction("initializeCounter").unwrap();
+        assert_eq!(idl.instruction_discriminator(instruction).unwrap(), INITIALIZE_COUNTER_DISCRIMINATOR);
+    }
+
+    #[test]
+    fn legacy_args_are_encoded_after_the_discriminator() {
+        let idl = parse(LEGACY_IDL);
+
+        let data = idl.encode_instruction_data("initializeCounter", &json!({"start": "5", "label": "hi"})).unwrap();
+        let mut expected = INITIALIZE_COUNTER_DISCRIMINATOR.to_vec();
+        expected.extend_from_slice(&5u64.to_le_bytes());
+        expected.extend_from_slice(&[1, 2, 0, 0, 0, b'h', b'i']);
+        assert_eq!(data, expected);
+
+        let data = idl.encode_instruction_data("initializeCounter", &json!({"start": 5})).unwrap();
+        assert_eq!(data[8..], [5, 0, 0, 0, 0, 0, 0, 0, 0]);
+
+        let steps = [
+            (json!({"step": "One"}), vec![0]),
+            (json!({"step": {"by": [7]}}), vec![1, 7, 0, 0, 0]),
+            (json!({"step": {"Scaled": {"factor": 3, "times": [1, 2]}}}), vec![2, 3, 1, 2]),
+        ];
+        for (args, encoded) in steps {
+            let data = idl.encode_instruction_data("increment", &args).unwrap();
+            assert_eq!(data[..8], INCREMENT_DISCRIMINATOR);
+            assert_eq!(data[8..], encoded, "{}", args);
+        }
+
+        assert!(idl.encode_instruction_data("increment", &json!({"step": "Two"})).is_err());
+        assert!(idl.encode_instruction_data("increment", &json!({"step": {"Scaled": {"factor": 3, "times": [1]}}})).is_err());
+        assert!(idl.encode_instruction_data("initializeCounter", &json!({"start": 256, "label": 1})).is_err());
+    }
+
+    #[test]
+    fn args_are_encoded_with_defined_types() {
+        let idl = parse(IDL);
+        let data = idl.encode_instruction_data("initialize_counter",
+            &json!({"start": "18446744073709551615", "config": {"max": 9, "paused": true}})).unwrap();
+        let mut expected = INITIALIZE_COUNTER_DISCRIMINATOR.to_vec();
+        expected.extend_from_slice(&u64::MAX.to_le_bytes());
+        expected.extend_from_slice(&[1, 9, 0, 0, 0, 1]);
+        assert_eq!(data, expected);
+    }
+
+    #[test]
+    fn account_metas_follow_idl_order() {
+        let program_id = Pubkey::from_str(PROGRAM_ID).unwrap();
+        let counter = Pubkey::new_unique();
+        let authority = Pubkey::new_unique();
+
+        let idl = parse(IDL);
+        let metas = idl.account_metas("initialize_counter",
+            &json!({"authority": authority.to_string(), "counter": counter.to_string()}), &program_id).unwrap();
+        assert_eq!(metas, vec![
+            AccountMeta::new(counter, false),
+            AccountMeta::new(authority, true),
+            AccountMeta::new_readonly(solana_program::system_program::id(), false),
+        ]);
+        assert!(idl.account_metas("initialize_counter", &json!({"counter": counter.to_string()}), &program_id).is_err());
+    }
+
+    #[test]
+    fn nested_and_optional_account_metas() {
+        let program_id = Pubkey::from_str(PROGRAM_ID).unwrap();
+        let counter = Pubkey::new_unique();
+        let authority = Pubkey::new_unique();
+        let referrer = Pubkey::new_unique();
+        let clock = solana_program::sysvar::clock::id();
+        let idl = parse(LEGACY_IDL);
+
+        let nested = json!({
+            "clock": clock.to_string(),
+            "common": {"authority": authority.to_string(), "counter": counter.to_string()},
+        });
+        let flattened = json!({
+            "clock": clock.to_string(),
+            "authority": authority.to_string(),
+            "counter": counter.to_string(),
+            "referrer": null,
+        });
+        for accounts in [nested, flattened] {
+            assert_eq!(idl.account_metas("increment", &accounts, &program_id).unwrap(), vec![
+                AccountMeta::new(counter, false),
+                AccountMeta::new_readonly(authority, true),
+                AccountMeta::new_readonly(program_id, false),
+                AccountMeta::new_readonly(clock, false),
+            ]);
+        }
+
+        let accounts = json!({
+            "clock": clock.to_string(),
+            "common": {"authority": authority.to_string(), "counter": counter.to_string()},
+            "referrer": referrer.to_string(),
+        });
+        assert_eq!(idl.account_metas("increment", &accounts, &program_id).unwrap()[2], AccountMeta::new_readonly(referrer, false));
+
+        let missing_clock = json!({"common": {"authority": authority.to_string(), "counter": counter.to_string()}});
+        assert!(idl.account_metas("increment", &missing_clock, &program_id).is_err());
+    }
+
+    fn counter_data(discriminator: &[u8], authority: &Pubkey, count: u64) -> Vec<u8> {
+        let mut data = discriminator.to_vec();
+        data.extend_from_slice(authority.as_ref());
+        data.extend_from_slice(&count.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn known_accounts_decode_to_json() {
+        let discriminator = anchor_discriminator("account", "Counter");
+        assert_eq!(discriminator, COUNTER_DISCRIMINATOR);
+        let authority = Pubkey::new_unique();
+
+        let legacy = parse(LEGACY_IDL);
+        let mut data = counter_data(&discriminator, &authority, u64::MAX);
+        data.extend_from_slice(&[2, 0, 0, 0, 0xff, 0xff, 3, 0]);
+        assert_eq!(legacy.account_name(&data).unwrap(), "Counter");
+        assert_eq!(legacy.decode_account("Counter", &data).unwrap(), json!({
+            "authority": authority.to_string(),
+            "count": "18446744073709551615",
+            "history": [-1, 3],
+        }));
+
+        let idl = parse(IDL);
+        let mut data = counter_data(&discriminator, &authority, 42);
+        data.extend_from_slice(&[1, 7, 0, 0, 0, 0]);
+        // Spare room after the encoded fields is ignored.
+        data.extend_from_slice(&[0; 16]);
+        assert_eq!(idl.account_name(&data).unwrap(), "Counter");
+        assert_eq!(idl.decode_account("Counter", &data).unwrap(), json!({
+            "authority": authority.to_string(),
+            "count": "42",
+            "config": {"max": 7, "paused": false},
+        }));
+    }
+
+    #[test]
+    fn unknown_discriminators_and_short_data_are_rejected() {
+        let authority = Pubkey::new_unique();
+        for idl in [parse(LEGACY_IDL), parse(IDL)] {
+            let mut unknown = counter_data(&anchor_discriminator("account", "Config"), &authority, 1);
+            unknown.extend_from_slice(&[0; 8]);
+            assert_eq!(idl.account_name(&unknown).unwrap_err().code(), ErrorCode::DiscriminatorMismatch);
+            assert_eq!(idl.decode_account("Counter", &unknown).unwrap_err().code(), ErrorCode::DiscriminatorMismatch);
+
+            let short = &COUNTER_DISCRIMINATOR[..7];
+            assert_eq!(idl.account_name(short).unwrap_err().code(), ErrorCode::DiscriminatorMismatch);
+            assert_eq!(idl.decode_account("Counter", short).unwrap_err().code(), ErrorCode::InvalidLength);
+
+            let truncated = counter_data(&COUNTER_DISCRIMINATOR, &authority, 1);
+            assert!(idl.decode_account("Counter", &truncated[..truncated.len() - 1]).is_err());
+            assert!(idl.decode_account("Counter", &truncated).is_err());
+            assert!(idl.decode_account("Missing", &truncated).is_err());
+        }
+    }
+
+    #[test]
+    fn self_referencing_types_are_rejected() {
+        let direct = idl_with_types(json!([
+            {"name": "Node", "type": {"kind": "struct", "fields": [{"name": "next", "type": {"option": {"defined": "Node"}}}]}},
+        ]));
+        let mutual = idl_with_types(json!([
+            {"name": "Node", "type": {"kind": "struct", "fields": [{"name": "leaves", "type": {"vec": {"defined": "Leaf"}}}]}},
+            {"name": "Leaf", "type": {"kind": "enum", "variants": [{"name": "Branch", "fields": [{"defined": "Node"}]}]}},
+        ]));
+        let alias = idl_with_types(json!([
+            {"name": "Node", "type": {"kind": "type", "alias": {"defined": "Node"}}},
+        ]));
+        for idl in [direct, mutual, alias] {
+            let error = idl.encode_instruction_data("create", &json!({"node": null})).unwrap_err();
+            assert_eq!(error.code(), ErrorCode::InvalidIdl);
+            assert!(error.message().contains("contains itself"), "{}", error.message());
+        }
+    }
+
+    #[test]
+    fn exponentially_expanding_types_are_rejected() {
+        let mut types = vec![json!({"name": "T0", "type": {"kind": "struct", "fields": ["u8", "u8"]}})];
+        for level in 1..32 {
+            let inner = json!({"defined": format!("T{}", level - 1)});
+            types.push(json!({"name": format!("T{}", level), "type": {"kind": "struct", "fields": [inner, inner]}}));
+        }
+        types.push(json!({"name": "Node", "type": {"kind": "type", "alias": {"defined": "T31"}}}));
+        let error = idl_with_types(Value::Array(types)).encode_instruction_data("create", &json!({})).unwrap_err();
+        assert_eq!(error.code(), ErrorCode::InvalidIdl);
+    }
+}
@@ -1,11 +1,18 @@
-use std::error::Error;
-use std::ffi::CString;
-use std::ptr::null_mut;
 use bip39::{Language, Mnemonic, MnemonicType, Seed};
 use libc::size_t;
 use std::os::raw::c_char;
-use crate::c_sharp_string::str_from_c_char_ptr;
-use crate::common_types::{ResultExport, to_raw_parts, VecRawParts};
+use crate::c_sharp_string::{c_char_ptr_from_string, str_from_c_char_ptr};
+use crate::common_types::{ResultExport, slice_from_ptr, to_raw_parts, VecRawParts};
+use crate::error_export::{export_result, ExportError};
+use crate::handle_table::{self, Handle, HandleObject, HandleType};
+
+impl HandleObject for Mnemonic {
+    const HANDLE_TYPE: HandleType = HandleType::Mnemonic;
+}
+
+impl HandleObject for Seed {
+    const HANDLE_TYPE: HandleType = HandleType::Seed;
+}
 
 pub fn language_from_str(lang: &str) -> Option<Language> {
     match lang {
@@ -32,104 +39,84 @@ pub fn mnemonic_type_from_str(mtype: &str) -> Option<MnemonicType>{
     }
 }
 
-#[no_mangle]
-extern "C"  fn mnemonic_new(mtype: *const c_char, lang: *const c_char) -> *mut Mnemonic {
+fn language_from_ptr(lang: *const c_char) -> Result<Language, ExportError> {
+    let lang = str_from_c_char_ptr(lang)?;
+    language_from_str(lang).ok_or_else(|| ExportError::invalid_argument(format!("Unknown language: {}", lang)))
+}
 
-    let mnemonic_type = mnemonic_type_from_str(str_from_c_char_ptr(mtype).unwrap()).unwrap();
-    let language = language_from_str(str_from_c_char_ptr(lang).unwrap()).unwrap();
+fn mnemonic_type_from_ptr(mtype: *const c_char) -> Result<MnemonicType, ExportError> {
+    let mtype = str_from_c_char_ptr(mtype)?;
+    mnemonic_type_from_str(mtype).ok_or_else(|| ExportError::invalid_argument(format!("Unknown mnemonic type: {}", mtype)))
+}
+
+#[no_mangle]
+extern "C"  fn mnemonic_new(mtype: *const c_char, lang: *const c_char) -> ResultExport<Handle> {
+    export_result(|| {
+        let mnemonic_type = mnemonic_type_from_ptr(mtype)?;
+        let language = language_from_ptr(lang)?;
 
-    Box::into_raw(Box::new(Mnemonic::new(mnemonic_type, language)))
+        Ok(handle_table::insert(Mnemonic::new(mnemonic_type, language)))
+    })
 }
 
 
 #[no_mangle]
-extern "C"  fn from_entropy(entropy: *const u8, entropy_length: size_t , lang: *const c_char) -> ResultExport<*mut Mnemonic> {
-    let entropy = unsafe{ std::slice::from_raw_parts(entropy, entropy_length as usize)};
-    let lang = language_from_str(str_from_c_char_ptr(lang).unwrap()).unwrap();
-
-    match Mnemonic::from_entropy(entropy, lang) {
-        Ok(mnemonic) => ResultExport{
-            is_error: 0,
-            result: Box::into_raw(Box::new(mnemonic)),
-            error: null_mut()
-        },
-        Err(error) => ResultExport{
-            is_error: 1,
-            result: null_mut(),
-            error: CString::new(error.to_string()).unwrap().into_raw()
-        }
-    }
+extern "C"  fn from_entropy(entropy: *const u8, entropy_length: size_t , lang: *const c_char) -> ResultExport<Handle> {
+    export_result(|| {
+        let entropy = slice_from_ptr(entropy, entropy_length, "entropy")?;
+        let lang = language_from_ptr(lang)?;
+
+        Ok(handle_table::insert(Mnemonic::from_entropy(entropy, lang)?))
+    })
 }
 
 
 #[no_mangle]
-extern "C"  fn from_phrase(phrase: *const c_char, lang: *const c_char) -> ResultExport<*mut Mnemonic>{
-    let language = language_from_str(str_from_c_char_ptr(lang).unwrap()).unwrap();
-
-    match Mnemonic::from_phrase(str_from_c_char_ptr(phrase).unwrap(), language) {
-        Ok(mnemonic) => ResultExport{
-            is_error: 0,
-            result: Box::into_raw(Box::new(mnemonic)),
-            error: null_mut()
-        },
-        Err(error) => ResultExport{
-            is_error: 1,
-            result: null_mut(),
-            error: CString::new(error.to_string()).unwrap().into_raw()
-        }
-    }
+extern "C"  fn from_phrase(phrase: *const c_char, lang: *const c_char) -> ResultExport<Handle>{
+    export_result(|| {
+        let language = language_from_ptr(lang)?;
+
+        Ok(handle_table::insert(Mnemonic::from_phrase(str_from_c_char_ptr(phrase)?, language)?))
+    })
 }
 
 
 #[no_mangle]
 extern "C"  fn validate(phrase: *const c_char, lang: *const c_char) -> ResultExport<u8>{
-    let lang = language_from_str(str_from_c_char_ptr(lang).unwrap()).unwrap();
-    let phrase = str_from_c_char_ptr(phrase).unwrap();
-
-    match Mnemonic::validate(phrase, lang) {
-        Ok(..) => ResultExport{
-            is_error: 0,
-            result: true as u8,
-            error: null_mut()
-        },
-        Err(error) => ResultExport{
-            is_error: 1,
-            result: false as u8,
-            error: CString::new(error.to_string()).unwrap().into_raw()
-        }
-    }
+    export_result(|| {
+        let lang = language_from_ptr(lang)?;
+        let phrase = str_from_c_char_ptr(phrase)?;
+
+        Mnemonic::validate(phrase, lang)?;
+        Ok(true as u8)
+    })
 }
 
 
+/// Release the returned string with `free_secret_cstring`.
 #[no_mangle]
-extern "C"  fn phrase(mnemonic: *mut Mnemonic) -> *mut c_char {
-    let mnemonic = unsafe {
-        assert!(!mnemonic.is_null());
-        &mut *mnemonic
-    };
-    CString::new(mnemonic.phrase()).unwrap().into_raw()
+extern "C"  fn phrase(mnemonic: Handle) -> ResultExport<*mut c_char> {
+    export_result(|| {
+        handle_table::with(mnemonic, |mnemonic: &mut Mnemonic| c_char_ptr_from_string(mnemonic.phrase()))?
+    })
 }
 
 
+/// Release the returned string with `free_secret_cstring`.
 #[no_mangle]
-extern "C"  fn into_phrase(mnemonic: *mut Mnemonic) -> *mut c_char {
-    let mnemonic = unsafe {
-        assert!(!mnemonic.is_null());
-        &mut *mnemonic
-    };
-
-    CString::new(mnemonic.clone().into_phrase()).unwrap().into_raw()
+extern "C"  fn into_phrase(mnemonic: Handle) -> ResultExport<*mut c_char> {
+    export_result(|| {
+        handle_table::with(mnemonic, |mnemonic: &mut Mnemonic| c_char_ptr_from_string(mnemonic.phrase()))?
+    })
 }
 
 
+/// Release the returned buffer with `free_secret_vec`.
 #[no_mangle]
-extern "C"  fn entropy(mnemonic: *mut Mnemonic) -> VecRawParts {
-
-    let mnemonic = unsafe {
-        assert!(!mnemonic.is_null());
-        &mut *mnemonic
-    };
-    to_raw_parts(mnemonic.entropy().to_vec())
+extern "C"  fn entropy(mnemonic: Handle) -> ResultExport<VecRawParts> {
+    export_result(|| {
+        handle_table::with(mnemonic, |mnemonic: &mut Mnemonic| to_raw_parts(mnemonic.entropy().to_vec()))
+    })
 }
 
 // #[no_mangle]
@@ -144,43 +131,37 @@ extern "C"  fn entropy(mnemonic: *mut Mnemonic) -> VecRawParts {
 // }
 
 #[no_mangle]
-extern "C" fn seed_new(mnemonic: *mut Mnemonic, password: *const c_char) -> *mut Seed {
-
-    let mnemonic = unsafe {
-        assert!(!mnemonic.is_null());
-        &mut *mnemonic
-    };
+extern "C" fn seed_new(mnemonic: Handle, password: *const c_char) -> ResultExport<Handle> {
+    export_result(|| {
+        let password = str_from_c_char_ptr(password)?;
+        let seed = handle_table::with(mnemonic, |mnemonic: &mut Mnemonic| Seed::new(mnemonic, password))?;
 
-    let password = str_from_c_char_ptr(password).unwrap();
-    Box::into_raw(Box::new(Seed::new(mnemonic, password)))
+        Ok(handle_table::insert(seed))
+    })
 }
 
+/// Release the returned buffer with `free_secret_vec`.
 #[no_mangle]
-extern "C" fn as_bytes(seed: *mut Seed) -> VecRawParts{
-    let seed = unsafe {
-        assert!(!seed.is_null());
-        &mut *seed
-    };
-
-    to_raw_parts(seed.as_bytes().to_vec())
+extern "C" fn as_bytes(seed: Handle) -> ResultExport<VecRawParts>{
+    export_result(|| {
+        handle_table::with(seed, |seed: &mut Seed| to_raw_parts(seed.as_bytes().to_vec()))
+    })
 }
 
+/// The phrase and entropy are overwritten when the mnemonic is dropped.
 #[no_mangle]
-extern "C" fn free_mnemonic(mnemonic: *mut Mnemonic){
-    if mnemonic.is_null() {
-        return;
-    }
-    unsafe {
-        Box::from_raw(mnemonic);
-    }
+extern "C" fn free_mnemonic(mnemonic: Handle) -> ResultExport<u8> {
+    export_result(|| {
+        handle_table::remove::<Mnemonic>(mnemonic)?;
+        Ok(true as u8)
+    })
 }
 
+/// The seed bytes are overwritten when the seed is dropped.
 #[no_mangle]
-extern "C" fn free_seed(seed: *mut Seed){
-    if seed.is_null() {
-        return;
-    }
-    unsafe {
-        Box::from_raw(seed);
-    }
-}
\ No newline at end of file
+extern "C" fn free_seed(seed: Handle) -> ResultExport<u8> {
+    export_result(|| {
+        handle_table::remove::<Seed>(seed)?;
+        Ok(true as u8)
+    })
+}
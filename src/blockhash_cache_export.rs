@@ -0,0 +1,292 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::os::raw::c_char;
+use std::time::{Duration, Instant};
+
+use solana_program::clock::{DEFAULT_MS_PER_SLOT, MAX_PROCESSING_AGE};
+use solana_program::hash::Hash;
+
+use crate::c_sharp_string::str_from_c_char_ptr;
+use crate::common_types::{ExportDefault, OptionExport, ResultExport};
+use crate::error_export::export_result;
+use crate::handle_table::{self, Handle, HandleObject, HandleType};
+use crate::rpc_export::{Commitment, option_export, parse_response, RpcBlockhash, WithContext};
+
+/// Blocks a cached blockhash must stay valid for beyond the current block height, so a
+/// transaction built with it still has time to land.
+pub const DEFAULT_SAFETY_MARGIN_BLOCKS: u64 = 30;
+
+#[repr(C)]
+pub struct CachedBlockhashExport {
+    pub blockhash: Handle,
+    pub last_valid_block_height: u64,
+    /// Time since the blockhash was put into the cache.
+    pub age_ms: u64,
+}
+
+impl ExportDefault for CachedBlockhashExport {
+    fn export_default() -> Self {
+        Self {
+            blockhash: 0,
+            last_valid_block_height: 0,
+            age_ms: 0,
+        }
+    }
+}
+
+struct CachedBlockhash {
+    blockhash: Hash,
+    last_valid_block_height: u64,
+    fetched_at: Instant,
+}
+
+impl CachedBlockhash {
+    /// Block height when the blockhash was fetched plus the blocks produced since,
+    /// assuming `DEFAULT_MS_PER_SLOT` per block.
+    fn estimated_block_height(&self) -> u64 {
+        let fetched_block_height = self.last_valid_block_height.saturating_sub(MAX_PROCESSING_AGE as u64);
+        fetched_block_height.saturating_add(self.fetched_at.elapsed().as_millis() as u64 / DEFAULT_MS_PER_SLOT)
+    }
+}
+
+/// Latest blockhash per commitment level. The handle table locks the cache for each call,
+/// so it can be shared between threads.
+pub struct BlockhashCache {
+    safety_margin_blocks: u64,
+    max_age: Option<Duration>,
+    entries: HashMap<Commitment, CachedBlockhash>,
+}
+
+impl HandleObject for BlockhashCache {
+    const HANDLE_TYPE: HandleType = HandleType::BlockhashCache;
+}
+
+impl BlockhashCache {
+    /// Keeps the entry that stays valid the longest.
+    fn insert(&mut self, commitment: Commitment, blockhash: Hash, last_valid_block_height: u64) {
+        let commitment = commitment.effective();
+        if self.entries.get(&commitment).is_some_and(|cached| cached.last_valid_block_height > last_valid_block_height) {
+            return;
+        }
+        self.entries.insert(commitment, CachedBlockhash {
+            blockhash,
+            last_valid_block_height,
+            fetched_at: Instant::now(),
+        });
+    }
+
+    /// The cached entry, if it is still valid for `safety_margin_blocks` past
+    /// `block_height` and not older than `max_age`. A zero `block_height` is estimated.
+    fn get(&self, commitment: Commitment, block_height: u64) -> Option<&CachedBlockhash> {
+        let cached = self.entries.get(&commitment.effective())?;
+        let block_height = match block_height {
+            0 => cached.estimated_block_height(),
+            block_height => block_height,
+        };
+        let expired = cached.last_valid_block_height.checked_sub(self.safety_margin_blocks)
+            .is_none_or(|last_usable_block_height| block_height > last_usable_block_height)
+            || self.max_age.is_some_and(|max_age| cached.fetched_at.elapsed() > max_age);
+        (!expired).then_some(cached)
+    }
+}
+
+/// A zero `safety_margin_blocks` uses `DEFAULT_SAFETY_MARGIN_BLOCKS`; a zero `max_age_ms`
+/// puts no limit on the age of an entry.
+#[no_mangle]
+extern "C" fn blockhash_cache_new(safety_margin_blocks: u64, max_age_ms: u64) -> ResultExport<Handle> {
+    export_result(|| {
+        Ok(handle_table::insert(BlockhashCache {
+            safety_margin_blocks: match safety_margin_blocks {
+                0 => DEFAULT_SAFETY_MARGIN_BLOCKS,
+                blocks => blocks,
+            },
+            max_age: (max_age_ms != 0).then(|| Duration::from_millis(max_age_ms)),
+            entries: HashMap::new(),
+        }))
+    })
+}
+
+#[no_mangle]
+extern "C" fn blockhash_cache_insert(cache: Handle, commitment: u8, blockhash: Handle,
+                                     last_valid_block_height: u64) -> ResultExport<u8> {
+    export_result(|| {
+        let commitment = Commitment::try_from(commitment)?;
+        let blockhash = handle_table::get::<Hash>(blockhash)?;
+        handle_table::with(cache, |cache: &mut BlockhashCache| cache.insert(commitment, blockhash, last_valid_block_height))?;
+        Ok(true as u8)
+    })
+}
+
+/// Caches the blockhash of a `getLatestBlockhash` response made with `commitment`.
+#[no_mangle]
+extern "C" fn blockhash_cache_insert_response(cache: Handle, commitment: u8, response: *const c_char) -> ResultExport<u8> {
+    export_result(|| {
+        let commitment = Commitment::try_from(commitment)?;
+        let result: WithContext<RpcBlockhash> = parse_response(str_from_c_char_ptr(response)?)?;
+        let blockhash = result.blockhash()?;
+        handle_table::with(cache, |cache: &mut BlockhashCache| {
+            cache.insert(commitment, blockhash, result.value.last_valid_block_height)
+        })?;
+        Ok(true as u8)
+    })
+}
+
+/// Cached blockhash that is still safe to build a transaction with at `block_height`, or
+/// empty if a new one must be fetched. A zero `block_height` is estimated from the time
+/// since the fetch, assuming `DEFAULT_MS_PER_SLOT` per block.
+#[no_mangle]
+extern "C" fn blockhash_cache_get(cache: Handle, commitment: u8, block_height: u64) -> ResultExport<OptionExport<CachedBlockhashExport>> {
+    export_result(|| {
+        let commitment = Commitment::try_from(commitment)?;
+        let cached = handle_table::with(cache, |cache: &mut BlockhashCache| {
+            cache.get(commitment, block_height).map(|cached| (
+                cached.blockhash,
+                cached.last_valid_block_height,
+                cached.fetched_at.elapsed().as_millis() as u64,
+            ))
+        })?;
+        Ok(option_export(cached.map(|(blockhash, last_valid_block_height, age_ms)| CachedBlockhashExport {
+            blockhash: handle_table::insert(blockhash),
+            last_valid_block_height,
+            age_ms,
+        })))
+    })
+}
+
+/// Same check as `blockhash_cache_get`, without handing out the blockhash.
+#[no_mangle]
+extern "C" fn blockhash_cache_is_usable(cache: Handle, commitment: u8, block_height: u64) -> ResultExport<u8> {
+    export_result(|| {
+        let commitment = Commitment::try_from(commitment)?;
+        handle_table::with(cache, |cache: &mut BlockhashCache| cache.get(commitment, block_height).is_some() as u8)
+    })
+}
+
+#[no_mangle]
+extern "C" fn blockhash_cache_clear(cache: Handle) -> ResultExport<u8> {
+    export_result(|| {
+        handle_table::with(cache, |cache: &mut BlockhashCache| cache.entries.clear())?;
+        Ok(true as u8)
+    })
+}
+
+#[no_mangle]
+extern "C" fn free_blockhash_cache(cache: Handle) -> ResultExport<u8> {
+    export_result(|| {
+        handle_table::remove::<BlockhashCache>(cache)?;
+        Ok(true as u8)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache(safety_margin_blocks: u64, max_age: Option<Duration>) -> BlockhashCache {
+        BlockhashCache {
+            safety_margin_blocks,
+            max_age,
+            entries: HashMap::new(),
+        }
+    }
+
+    fn cached_blockhash(cache: &BlockhashCache, commitment: Commitment, block_height: u64) -> Option<Hash> {
+        cache.get(commitment, block_height).map(|cached| cached.blockhash)
+    }
+
+    fn backdate(cache: &mut BlockhashCache, commitment: Commitment, elapsed: Duration) {
+        let cached = cache.entries.get_mut(&commitment.effective()).unwrap();
+        cached.fetched_at = Instant::now().checked_sub(elapsed).unwrap();
+    }
+
+    #[test]
+    fn longer_validity_is_kept() {
+        let (older, newer) = (Hash::new_unique(), Hash::new_unique());
+        let mut cache = cache(DEFAULT_SAFETY_MARGIN_BLOCKS, None);
+        cache.insert(Commitment::Confirmed, newer, 300);
+        cache.insert(Commitment::Confirmed, older, 250);
+        assert_eq!(cached_blockhash(&cache, Commitment::Confirmed, 100), Some(newer));
+
+        let latest = Hash::new_unique();
+        cache.insert(Commitment::Confirmed, latest, 301);
+        assert_eq!(cached_blockhash(&cache, Commitment::Confirmed, 100), Some(latest));
+    }
+
+    #[test]
+    fn safety_margin_is_kept_before_last_valid_block_height() {
+        let blockhash = Hash::new_unique();
+        let mut cache = cache(DEFAULT_SAFETY_MARGIN_BLOCKS, None);
+        cache.insert(Commitment::Confirmed, blockhash, 300);
+        assert_eq!(cached_blockhash(&cache, Commitment::Confirmed, 300 - DEFAULT_SAFETY_MARGIN_BLOCKS), Some(blockhash));
+        assert_eq!(cached_blockhash(&cache, Commitment::Confirmed, 300 - DEFAULT_SAFETY_MARGIN_BLOCKS + 1), None);
+        assert_eq!(cached_blockhash(&cache, Commitment::Confirmed, 400), None);
+        // Still there for an earlier height, e.g. from a lagging node.
+        assert_eq!(cached_blockhash(&cache, Commitment::Confirmed, 200), Some(blockhash));
+    }
+
+    #[test]
+    fn max_age_expires_entries() {
+        let blockhash = Hash::new_unique();
+        let mut cache = cache(DEFAULT_SAFETY_MARGIN_BLOCKS, Some(Duration::from_secs(20)));
+        cache.insert(Commitment::Confirmed, blockhash, 300);
+        assert_eq!(cached_blockhash(&cache, Commitment::Confirmed, 100), Some(blockhash));
+        backdate(&mut cache, Commitment::Confirmed, Duration::from_secs(21));
+        assert_eq!(cached_blockhash(&cache, Commitment::Confirmed, 100), None);
+
+        let mut unlimited = self::cache(DEFAULT_SAFETY_MARGIN_BLOCKS, None);
+        unlimited.insert(Commitment::Confirmed, blockhash, 300);
+        backdate(&mut unlimited, Commitment::Confirmed, Duration::from_secs(3600));
+        assert_eq!(cached_blockhash(&unlimited, Commitment::Confirmed, 100), Some(blockhash));
+    }
+
+    #[test]
+    fn default_and_finalized_share_an_entry() {
+        let (finalized, confirmed) = (Hash::new_unique(), Hash::new_unique());
+        let mut cache = cache(DEFAULT_SAFETY_MARGIN_BLOCKS, None);
+        cache.insert(Commitment::Default, finalized, 300);
+        cache.insert(Commitment::Confirmed, confirmed, 310);
+        assert_eq!(cached_blockhash(&cache, Commitment::Finalized, 100), Some(finalized));
+        assert_eq!(cached_blockhash(&cache, Commitment::Default, 100), Some(finalized));
+        assert_eq!(cached_blockhash(&cache, Commitment::Confirmed, 100), Some(confirmed));
+        assert_eq!(cached_blockhash(&cache, Commitment::Processed, 100), None);
+
+        cache.insert(Commitment::Finalized, confirmed, 320);
+        assert_eq!(cached_blockhash(&cache, Commitment::Default, 100), Some(confirmed));
+    }
+
+    #[test]
+    fn zero_block_height_is_estimated() {
+        let blockhash = Hash::new_unique();
+        let last_valid_block_height = MAX_PROCESSING_AGE as u64 + 1_000;
+        let mut cache = cache(DEFAULT_SAFETY_MARGIN_BLOCKS, None);
+        cache.insert(Commitment::Confirmed, blockhash, last_valid_block_height);
+        assert_eq!(cached_blockhash(&cache, Commitment::Confirmed, 0), Some(blockhash));
+
+        // Fetched at 1_000, so expired once the blocks produced since reach the margin.
+        let blocks_left = MAX_PROCESSING_AGE as u64 - DEFAULT_SAFETY_MARGIN_BLOCKS;
+        backdate(&mut cache, Commitment::Confirmed, Duration::from_millis((blocks_left - 1) * DEFAULT_MS_PER_SLOT));
+        assert_eq!(cached_blockhash(&cache, Commitment::Confirmed, 0), Some(blockhash));
+        backdate(&mut cache, Commitment::Confirmed, Duration::from_millis((blocks_left + 1) * DEFAULT_MS_PER_SLOT));
+        assert_eq!(cached_blockhash(&cache, Commitment::Confirmed, 0), None);
+    }
+
+    #[test]
+    fn estimate_does_not_overflow() {
+        let blockhash = Hash::new_unique();
+        let mut cache = cache(u64::MAX, None);
+        cache.insert(Commitment::Confirmed, blockhash, u64::MAX);
+        backdate(&mut cache, Commitment::Confirmed, Duration::from_secs(3600));
+        assert_eq!(cached_blockhash(&cache, Commitment::Confirmed, 0), None);
+        assert_eq!(cached_blockhash(&cache, Commitment::Confirmed, u64::MAX), None);
+
+        let mut cache = self::cache(1, None);
+        cache.insert(Commitment::Confirmed, blockhash, u64::MAX);
+        assert_eq!(cached_blockhash(&cache, Commitment::Confirmed, 0), Some(blockhash));
+
+        // Below MAX_PROCESSING_AGE the fetch height saturates at zero.
+        cache.insert(Commitment::Processed, blockhash, 5);
+        assert_eq!(cached_blockhash(&cache, Commitment::Processed, 0), Some(blockhash));
+        cache.insert(Commitment::Processed, blockhash, 0);
+        assert_eq!(cached_blockhash(&cache, Commitment::Processed, 0), Some(blockhash));
+    }
+}
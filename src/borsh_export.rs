@@ -0,0 +1,1022 @@
+use std::convert::TryFrom;
+use std::os::raw::c_char;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use libc::size_t;
+use solana_program::pubkey::Pubkey;
+
+use crate::c_sharp_string::{c_char_ptr_from_string, str_from_c_char_ptr};
+use crate::common_types::{ExportDefault, OptionExport, ResultExport, slice_from_ptr, to_raw_parts, VecRawParts};
+use crate::error_export::{ErrorCode, export_result, ExportError};
+use crate::handle_table::{self, Handle, HandleObject, HandleType};
+
+/// Primitive types accepted by `borsh_schema_primitive`. Values are part of the C ABI.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BorshPrimitive {
+    Bool = 0,
+    U8 = 1,
+    U16 = 2,
+    U32 = 3,
+    U64 = 4,
+    U128 = 5,
+    I8 = 6,
+    I16 = 7,
+    I32 = 8,
+    I64 = 9,
+    I128 = 10,
+    String = 11,
+    Pubkey = 12,
+}
+
+impl TryFrom<u8> for BorshPrimitive {
+    type Error = ExportError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Ok(match value {
+            0 => BorshPrimitive::Bool,
+            1 => BorshPrimitive::U8,
+            2 => BorshPrimitive::U16,
+            3 => BorshPrimitive::U32,
+            4 => BorshPrimitive::U64,
+            5 => BorshPrimitive::U128,
+            6 => BorshPrimitive::I8,
+            7 => BorshPrimitive::I16,
+            8 => BorshPrimitive::I32,
+            9 => BorshPrimitive::I64,
+            10 => BorshPrimitive::I128,
+            11 => BorshPrimitive::String,
+            12 => BorshPrimitive::Pubkey,
+            _ => return Err(ExportError::invalid_argument(format!("Unknown borsh primitive {}", value))),
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BorshField {
+    pub name: String,
+    pub schema: BorshSchema,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BorshVariant {
+    pub name: String,
+    pub fields: Vec<BorshField>,
+}
+
+/// Layout of a borsh encoded value, described at runtime.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BorshSchema {
+    Primitive(BorshPrimitive),
+    Option(Box<BorshSchema>),
+    /// Length prefixed sequence.
+    Vec(Box<BorshSchema>),
+    /// Fixed length sequence, encoded without a length prefix.
+    Array(Box<BorshSchema>, u32),
+    Struct(Vec<BorshField>),
+    /// Encoded as the one byte variant index followed by the variant's fields.
+    Enum(Vec<BorshVariant>),
+}
+
+/// Kind of a `BorshValue`, as returned by `borsh_value_kind`. Values are part of the C ABI.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BorshValueKind {
+    Invalid = 0,
+    Bool = 1,
+    Unsigned = 2,
+    Signed = 3,
+    String = 4,
+    Pubkey = 5,
+    Option = 6,
+    Sequence = 7,
+    Struct = 8,
+    Enum = 9,
+}
+
+impl ExportDefault for BorshValueKind {
+    fn export_default() -> Self {
+        BorshValueKind::Invalid
+    }
+}
+
+/// Value tree serialized against a `BorshSchema`.
+///
+/// Integers are stored widened and range checked against the schema when serialized.
+/// Struct and enum fields are matched to the schema by name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BorshValue {
+    Bool(bool),
+    Unsigned(u128),
+    Signed(i128),
+    String(String),
+    Pubkey(Pubkey),
+    Option(Option<Box<BorshValue>>),
+    /// Elements of a `Vec` or `Array`.
+    Sequence(Vec<BorshValue>),
+    Struct(Vec<(String, BorshValue)>),
+    Enum { variant: u8, fields: Vec<(String, BorshValue)> },
+}
+
+impl HandleObject for BorshSchema {
+    const HANDLE_TYPE: HandleType = HandleType::BorshSchema;
+}
+
+impl HandleObject for BorshValue {
+    const HANDLE_TYPE: HandleType = HandleType::BorshValue;
+}
+
+fn schema_mismatch(schema: &BorshSchema, value: &BorshValue) -> ExportError {
+    ExportError::new(
+        ErrorCode::BorshSchemaMismatch,
+        format!("{:?} value does not match {} schema", value.kind(), schema.describe()),
+    )
+}
+
+fn unsigned<T: TryFrom<u128>>(schema: &BorshSchema, value: &BorshValue) -> Result<T, ExportError> {
+    value.as_u128()
+        .and_then(|value| T::try_from(value).ok())
+        .ok_or_else(|| schema_mismatch(schema, value))
+}
+
+fn signed<T: TryFrom<i128>>(schema: &BorshSchema, value: &BorshValue) -> Result<T, ExportError> {
+    value.as_i128()
+        .and_then(|value| T::try_from(value).ok())
+        .ok_or_else(|| schema_mismatch(schema, value))
+}
+
+fn write<T: BorshSerialize>(value: &T, out: &mut Vec<u8>) -> Result<(), ExportError> {
+    Ok(value.serialize(out)?)
+}
+
+fn read<T: BorshDeserialize>(data: &mut &[u8]) -> Result<T, ExportError> {
+    Ok(T::deserialize(data)?)
+}
+
+fn serialize_fields(schema: &BorshSchema, fields: &[BorshField], values: &[(String, BorshValue)],
+                    out: &mut Vec<u8>) -> Result<(), ExportError> {
+    if let Some((name, _)) = values.iter().find(|(name, _)| !fields.iter().any(|field| &field.name == name)) {
+        return Err(ExportError::new(
+            ErrorCode::BorshSchemaMismatch,
+            format!("Field {} is not part of {} schema", name, schema.describe()),
+        ));
+    }
+    for field in fields {
+        let (_, value) = values.iter().find(|(name, _)| name == &field.name).ok_or_else(|| ExportError::new(
+            ErrorCode::BorshSchemaMismatch,
+            format!("Missing field {} of {} schema", field.name, schema.describe()),
+        ))?;
+        field.schema.serialize(value, out)?;
+    }
+    Ok(())
+}
+
+fn deserialize_fields(fields: &[BorshField], data: &mut &[u8]) -> Result<Vec<(String, BorshValue)>, ExportError> {
+    fields
+        .iter()
+        .map(|field| Ok((field.name.clone(), field.schema.deserialize(data)?)))
+        .collect()
+}
+
+impl BorshSchema {
+    /// Short human readable form used in error messages, e.g. `vec<u8>`.
+    pub fn describe(&self) -> String {
+        match self {
+            BorshSchema::Primitive(primitive) => format!("{:?}", primitive).to_lowercase(),
+            BorshSchema::Option(inner) => format!("option<{}>", inner.describe()),
+            BorshSchema::Vec(inner) => format!("vec<{}>", inner.describe()),
+            BorshSchema::Array(inner, len) => format!("[{}; {}]", inner.describe(), len),
+            BorshSchema::Struct(_) => "struct".to_string(),
+            BorshSchema::Enum(_) => "enum".to_string(),
+        }
+    }
+
+    pub fn serialize(&self, value: &BorshValue, out: &mut Vec<u8>) -> Result<(), ExportError> {
+        match (self, value) {
+            (BorshSchema::Primitive(primitive), value) => match primitive {
+                BorshPrimitive::Bool => match value {
+                    BorshValue::Bool(value) => write(value, out),
+                    _ => Err(schema_mismatch(self, value)),
+                },
+                BorshPrimitive::U8 => write(&unsigned::<u8>(self, value)?, out),
+                BorshPrimitive::U16 => write(&unsigned::<u16>(self, value)?, out),
+                BorshPrimitive::U32 => write(&unsigned::<u32>(self, value)?, out),
+                BorshPrimitive::U64 => write(&unsigned::<u64>(self, value)?, out),
+                BorshPrimitive::U128 => write(&unsigned::<u128>(self, value)?, out),
+                BorshPrimitive::I8 => write(&signed::<i8>(self, value)?, out),
+                BorshPrimitive::I16 => write(&signed::<i16>(self, value)?, out),
+                BorshPrimitive::I32 => write(&signed::<i32>(self, value)?, out),
+                BorshPrimitive::I64 => write(&signed::<i64>(self, value)?, out),
+                BorshPrimitive::I128 => write(&signed::<i128>(self, value)?, out),
+                BorshPrimitive::String => match value {
+                    BorshValue::String(value) => write(value, out),
+                    _ => Err(schema_mismatch(self, value)),
+                },
+                BorshPrimitive::Pubkey => match value {
+                    BorshValue::Pubkey(value) => write(&value.to_bytes(), out),
+                    _ => Err(schema_mismatch(self, value)),
+                },
+            },
+            (BorshSchema::Option(inner), BorshValue::Option(value)) => match value {
+                Some(value) => {
+                    out.push(1);
+                    inner.serialize(value, out)
+                }
+                None => {
+                    out.push(0);
+                    Ok(())
+                }
+            },
+            (BorshSchema::Vec(inner), BorshValue::Sequence(values)) => {
+                let len = u32::try_from(values.len())
+                    .map_err(|_| ExportError::invalid_argument("Sequence is too long for borsh"))?;
+                write(&len, out)?;
+                values.iter().try_for_each(|value| inner.serialize(value, out))
+            }
+            (BorshSchema::Array(inner, len), BorshValue::Sequence(values)) => {
+                if values.len() != *len as usize {
+                    return Err(ExportError::new(
+                        ErrorCode::BorshSchemaMismatch,
+                        format!("Sequence of {} values does not match {} schema", values.len(), self.describe()),
+                    ));
+                }
+                values.iter().try_for_each(|value| inner.serialize(value, out))
+            }
+            (BorshSchema::Struct(fields), BorshValue::Struct(values)) => serialize_fields(self, fields, values, out),
+            (BorshSchema::Enum(variants), BorshValue::Enum { variant, fields: values }) => {
+                let fields = &variants.get(*variant as usize).ok_or_else(|| ExportError::new(
+                    ErrorCode::BorshSchemaMismatch,
+                    format!("Enum has no variant {}", variant),
+                ))?.fields;
+                out.push(*variant);
+                serialize_fields(self, fields, values, out)
+            }
+            (_, value) => Err(schema_mismatch(self, value)),
+        }
+    }
+
+    /// Reads one value from the front of `data`, advancing it past the consumed bytes.
+    pub fn deserialize(&self, data: &mut &[u8]) -> Result<BorshValue, ExportError> {
+        Ok(match self {
+            BorshSchema::Primitive(primitive) => match primitive {
+                BorshPrimitive::Bool => BorshValue::Bool(read(data)?),
+                BorshPrimitive::U8 => BorshValue::Unsigned(read::<u8>(data)? as u128),
+                BorshPrimitive::U16 => BorshValue::Unsigned(read::<u16>(data)? as u128),
+                BorshPrimitive::U32 => BorshValue::Unsigned(read::<u32>(data)? as u128),
+                BorshPrimitive::U64 => BorshValue::Unsigned(read::<u64>(data)? as u128),
+                BorshPrimitive::U128 => BorshValue::Unsigned(read(data)?),
+                BorshPrimitive::I8 => BorshValue::Signed(read::<i8>(data)? as i128),
+                BorshPrimitive::I16 => BorshValue::Signed(read::<i16>(data)? as i128),
+                BorshPrimitive::I32 => BorshValue::Signed(read::<i32>(data)? as i128),
+                BorshPrimitive::I64 => BorshValue::Signed(read::<i64>(data)? as i128),
+                BorshPrimitive::I128 => BorshValue::Signed(read(data)?),
+                BorshPrimitive::String => BorshValue::String(read(data)?),
+                BorshPrimitive::Pubkey => BorshValue::Pubkey(Pubkey::new_from_array(read(data)?)),
+            },
+            BorshSchema::Option(inner) => match read::<u8>(data)? {
+                0 => BorshValue::Option(None),
+                1 => BorshValue::Option(Some(Box::new(inner.deserialize(data)?))),
+                tag => return Err(ExportError::new(ErrorCode::Serialization, format!("Invalid option tag {}", tag))),
+            },
+            BorshSchema::Vec(inner) => {
+                let len = read::<u32>(data)?;
+                // Not preallocated, `len` comes from untrusted input.
+                let mut values = Vec::new();
+                for _ in 0..len {
+                    values.push(inner.deserialize(data)?);
+                }
+                BorshValue::Sequence(values)
+            }
+            BorshSchema::Array(inner, len) => BorshValue::Sequence(
+                (0..*len).map(|_| inner.deserialize(data)).collect::<Result<_, _>>()?
+            ),
+            BorshSchema::Struct(fields) => BorshValue::Struct(deserialize_fields(fields, data)?),
+            BorshSchema::Enum(variants) => {
+                let variant = read::<u8>(data)?;
+                let fields = &variants.get(variant as usize).ok_or_else(|| ExportError::new(
+                    ErrorCode::Serialization,
+                    format!("Invalid enum variant {}", variant),
+                ))?.fields;
+                BorshValue::Enum { variant, fields: deserialize_fields(fields, data)? }
+            }
+        })
+    }
+
+    /// Deserializes `data`, which must hold exactly one value.
+    pub fn deserialize_all(&self, mut data: &[u8]) -> Result<BorshValue, ExportError> {
+        let value = self.deserialize(&mut data)?;
+        if !data.is_empty() {
+            return Err(ExportError::new(
+                ErrorCode::Serialization,
+                format!("{} bytes left after deserializing {}", data.len(), self.describe()),
+            ));
+        }
+        Ok(value)
+    }
+}
+
+impl BorshValue {
+    pub fn kind(&self) -> BorshValueKind {
+        match self {
+            BorshValue::Bool(_) => BorshValueKind::Bool,
+            BorshValue::Unsigned(_) => BorshValueKind::Unsigned,
+            BorshValue::Signed(_) => BorshValueKind::Signed,
+            BorshValue::String(_) => BorshValueKind::String,
+            BorshValue::Pubkey(_) => BorshValueKind::Pubkey,
+            BorshValue::Option(_) => BorshValueKind::Option,
+            BorshValue::Sequence(_) => BorshValueKind::Sequence,
+            BorshValue::Struct(_) => BorshValueKind::Struct,
+            BorshValue::Enum { .. } => BorshValueKind::Enum,
+        }
+    }
+
+    pub fn as_u128(&self) -> Option<u128> {
+        match self {
+            BorshValue::Unsigned(value) => Some(*value),
+            BorshValue::Signed(value) => u128::try_from(*value).ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_i128(&self) -> Option<i128> {
+        match self {
+            BorshValue::Signed(value) => Some(*value),
+            BorshValue::Unsigned(value) => i128::try_from(*value).ok(),
+            _ => None,
+        }
+    }
+
+    fn wrong_kind(&self, expected: BorshValueKind) -> ExportError {
+        ExportError::invalid_argument(format!("Expected a {:?} value, got {:?}", expected, self.kind()))
+    }
+
+    fn fields(&self) -> Option<&Vec<(String, BorshValue)>> {
+        match self {
+            BorshValue::Struct(fields) | BorshValue::Enum { fields, .. } => Some(fields),
+            _ => None,
+        }
+    }
+}
+
+fn integer_from_ptr(bytes: *const u8, len: size_t) -> Result<[u8; 16], ExportError> {
+    let bytes = slice_from_ptr(bytes, len, "bytes")?;
+    <[u8; 16]>::try_from(bytes).map_err(|_| ExportError::invalid_length("bytes", 16, bytes.len()))
+}
+
+/// Runs `f` on the value behind `value`, with `item` resolved first so both may be the same handle.
+fn add_to_value(value: Handle, item: Handle, f: impl FnOnce(&mut BorshValue, BorshValue) -> Result<(), ExportError>) -> Result<(), ExportError> {
+    let item = handle_table::get::<BorshValue>(item)?;
+    handle_table::with(value, |value: &mut BorshValue| f(value, item))?
+}
+
+#[no_mangle]
+extern "C" fn borsh_schema_primitive(primitive: u8) -> ResultExport<Handle> {
+    export_result(|| Ok(handle_table::insert(BorshSchema::Primitive(BorshPrimitive::try_from(primitive)?))))
+}
+
+#[no_mangle]
+extern "C" fn borsh_schema_option(inner: Handle) -> ResultExport<Handle> {
+    export_result(|| {
+        let inner = handle_table::get::<BorshSchema>(inner)?;
+        Ok(handle_table::insert(BorshSchema::Option(Box::new(inner))))
+    })
+}
+
+#[no_mangle]
+extern "C" fn borsh_schema_vec(inner: Handle) -> ResultExport<Handle> {
+    export_result(|| {
+        let inner = handle_table::get::<BorshSchema>(inner)?;
+        Ok(handle_table::insert(BorshSchema::Vec(Box::new(inner))))
+    })
+}
+
+#[no_mangle]
+extern "C" fn borsh_schema_array(inner: Handle, len: u32) -> ResultExport<Handle> {
+    export_result(|| {
+        let inner = handle_table::get::<BorshSchema>(inner)?;
+        Ok(handle_table::insert(BorshSchema::Array(Box::new(inner), len)))
+    })
+}
+
+/// Creates a struct without fields; add them in encoding order with `borsh_schema_struct_add_field`.
+#[no_mangle]
+extern "C" fn borsh_schema_struct_new() -> ResultExport<Handle> {
+    export_result(|| Ok(handle_table::insert(BorshSchema::Struct(Vec::new()))))
+}
+
+#[no_mangle]
+extern "C" fn borsh_schema_struct_add_field(schema: Handle, name: *const c_char, field: Handle) -> ResultExport<u8> {
+    export_result(|| {
+        let name = str_from_c_char_ptr(name)?.to_string();
+        let field = handle_table::get::<BorshSchema>(field)?;
+        handle_table::with(schema, |schema: &mut BorshSchema| match schema {
+            BorshSchema::Struct(fields) if fields.iter().any(|existing| existing.name == name) =>
+                Err(ExportError::invalid_argument(format!("Duplicate field {}", name))),
+            BorshSchema::Struct(fields) => {
+                fields.push(BorshField { name, schema: field });
+                Ok(true as u8)
+            }
+            _ => Err(ExportError::invalid_argument(format!("Cannot add a field to a {} schema", schema.describe()))),
+        })?
+    })
+}
+
+/// Creates an enum without variants; add them in index order with `borsh_schema_enum_add_variant`.
+#[no_mangle]
+extern "C" fn borsh_schema_enum_new() -> ResultExport<Handle> {
+    export_result(|| Ok(handle_table::insert(BorshSchema::Enum(Vec::new()))))
+}
+
+/// `fields` is a struct schema holding the variant's fields, or 0 for a variant without fields.
+#[no_mangle]
+extern "C" fn borsh_schema_enum_add_variant(schema: Handle, name: *const c_char, fields: Handle) -> ResultExport<u8> {
+    export_result(|| {
+        let name = str_from_c_char_ptr(name)?.to_string();
+        let fields = match fields {
+            0 => Vec::new(),
+            fields => match handle_table::get::<BorshSchema>(fields)? {
+                BorshSchema::Struct(fields) => fields,
+                fields => return Err(ExportError::invalid_argument(
+                    format!("Variant fields must be a struct schema, got {}", fields.describe()))),
+            },
+        };
+        handle_table::with(schema, |schema: &mut BorshSchema| match schema {
+            BorshSchema::Enum(variants) if variants.len() > u8::MAX as usize =>
+                Err(ExportError::invalid_argument("Enum already has 256 variants")),
+            BorshSchema::Enum(variants) => {
+                variants.push(BorshVariant { name, fields });
+                Ok(true as u8)
+            }
+            _ => Err(ExportError::invalid_argument(format!("Cannot add a variant to a {} schema", schema.describe()))),
+        })?
+    })
+}
+
+#[no_mangle]
+extern "C" fn free_borsh_schema(schema: Handle) -> ResultExport<u8> {
+    export_result(|| {
+        handle_table::remove::<BorshSchema>(schema)?;
+        Ok(true as u8)
+    })
+}
+
+#[no_mangle]
+extern "C" fn borsh_value_bool(value: u8) -> ResultExport<Handle> {
+    export_result(|| Ok(handle_table::insert(BorshValue::Bool(value != 0))))
+}
+
+#[no_mangle]
+extern "C" fn borsh_value_u64(value: u64) -> ResultExport<Handle> {
+    export_result(|| Ok(handle_table::insert(BorshValue::Unsigned(value as u128))))
+}
+
+#[no_mangle]
+extern "C" fn borsh_value_i64(value: i64) -> ResultExport<Handle> {
+    export_result(|| Ok(handle_table::insert(BorshValue::Signed(value as i128))))
+}
+
+/// `bytes` holds the value as 16 little endian bytes.
+#[no_mangle]
+extern "C" fn borsh_value_u128(bytes: *const u8, len: size_t) -> ResultExport<Handle> {
+    export_result(|| Ok(handle_table::insert(BorshValue::Unsigned(u128::from_le_bytes(integer_from_ptr(bytes, len)?)))))
+}
+
+/// `bytes` holds the value as 16 little endian bytes.
+#[no_mangle]
+extern "C" fn borsh_value_i128(bytes: *const u8, len: size_t) -> ResultExport<Handle> {
+    export_result(|| Ok(handle_table::insert(BorshValue::Signed(i128::from_le_bytes(integer_from_ptr(bytes, len)?)))))
+}
+
+#[no_mangle]
+extern "C" fn borsh_value_string(value: *const c_char) -> ResultExport<Handle> {
+    export_result(|| Ok(handle_table::insert(BorshValue::String(str_from_c_char_ptr(value)?.to_string()))))
+}
+
+#[no_mangle]
+extern "C" fn borsh_value_pubkey(pubkey: Handle) -> ResultExport<Handle> {
+    export_result(|| Ok(handle_table::insert(BorshValue::Pubkey(handle_table::get::<Pubkey>(pubkey)?))))
+}
+
+/// Sequence of unsigned values, for `vec<u8>` and `[u8; N]` schemas.
+#[no_mangle]
+extern "C" fn borsh_value_bytes(bytes: *const u8, len: size_t) -> ResultExport<Handle> {
+    export_result(|| {
+        let bytes = slice_from_ptr(bytes, len, "bytes")?;
+        Ok(handle_table::insert(BorshValue::Sequence(
+            bytes.iter().map(|byte| BorshValue::Unsigned(*byte as u128)).collect()
+        )))
+    })
+}
+
+#[no_mangle]
+extern "C" fn borsh_value_none() -> ResultExport<Handle> {
+    export_result(|| Ok(handle_table::insert(BorshValue::Option(None))))
+}
+
+#[no_mangle]
+extern "C" fn borsh_value_some(inner: Handle) -> ResultExport<Handle> {
+    export_result(|| {
+        let inner = handle_table::get::<BorshValue>(inner)?;
+        Ok(handle_table::insert(BorshValue::Option(Some(Box::new(inner)))))
+    })
+}
+
+#[no_mangle]
+extern "C" fn borsh_value_sequence_new() -> ResultExport<Handle> {
+    export_result(|| Ok(handle_table::insert(BorshValue::Sequence(Vec::new()))))
+}
+
+#[no_mangle]
+extern "C" fn borsh_value_struct_new() -> ResultExport<Handle> {
+    export_result(|| Ok(handle_table::insert(BorshValue::Struct(Vec::new()))))
+}
+
+#[no_mangle]
+extern "C" fn borsh_value_enum_new(variant: u8) -> ResultExport<Handle> {
+    export_result(|| Ok(handle_table::insert(BorshValue::Enum { variant, fields: Vec::new() })))
+}
+
+/// Appends a copy of `item` to a sequence.
+#[no_mangle]
+extern "C" fn borsh_value_push(sequence: Handle, item: Handle) -> ResultExport<u8> {
+    export_result(|| {
+        add_to_value(sequence, item, |sequence, item| match sequence {
+            BorshValue::Sequence(values) => {
+                values.push(item);
+                Ok(())
+            }
+            _ => Err(sequence.wrong_kind(BorshValueKind::Sequence)),
+        })?;
+        Ok(true as u8)
+    })
+}
+
+/// Sets field `name` of a struct or enum value to a copy of `item`.
+#[no_mangle]
+extern "C" fn borsh_value_set_field(value: Handle, name: *const c_char, item: Handle) -> ResultExport<u8> {
+    export_result(|| {
+        let name = str_from_c_char_ptr(name)?.to_string();
+        add_to_value(value, item, |value, item| match value {
+            BorshValue::Struct(fields) | BorshValue::Enum { fields, .. } => {
+                match fields.iter_mut().find(|(existing, _)| existing == &name) {
+                    Some((_, existing)) => *existing = item,
+                    None => fields.push((name, item)),
+                }
+                Ok(())
+            }
+            _ => Err(value.wrong_kind(BorshValueKind::Struct)),
+        })?;
+        Ok(true as u8)
+    })
+}
+
+#[no_mangle]
+extern "C" fn borsh_value_kind(value: Handle) -> ResultExport<BorshValueKind> {
+    export_result(|| handle_table::with(value, |value: &mut BorshValue| value.kind()))
+}
+
+#[no_mangle]
+extern "C" fn borsh_value_as_bool(value: Handle) -> ResultExport<u8> {
+    export_result(|| {
+        handle_table::with(value, |value: &mut BorshValue| match value {
+            BorshValue::Bool(value) => Ok(*value as u8),
+            _ => Err(value.wrong_kind(BorshValueKind::Bool)),
+        })?
+    })
+}
+
+#[no_mangle]
+extern "C" fn borsh_value_as_u64(value: Handle) -> ResultExport<u64> {
+    export_result(|| {
+        handle_table::with(value, |value: &mut BorshValue| {
+            value.as_u128()
+                .and_then(|value| u64::try_from(value).ok())
+                .ok_or_else(|| ExportError::invalid_argument(format!("{:?} value does not fit in a u64", value.kind())))
+        })?
+    })
+}
+
+#[no_mangle]
+extern "C" fn borsh_value_as_i64(value: Handle) -> ResultExport<i64> {
+    export_result(|| {
+        handle_table::with(value, |value: &mut BorshValue| {
+            value.as_i128()
+                .and_then(|value| i64::try_from(value).ok())
+                .ok_or_else(|| ExportError::invalid_argument(format!("{:?} value does not fit in an i64", value.kind())))
+        })?
+    })
+}
+
+/// Returns the value as 16 little endian bytes.
+#[no_mangle]
+extern "C" fn borsh_value_as_u128(value: Handle) -> ResultExport<VecRawParts> {
+    export_result(|| {
+        handle_table::with(value, |value: &mut BorshValue| {
+            value.as_u128()
+                .map(|value| to_raw_parts(value.to_le_bytes().to_vec()))
+                .ok_or_else(|| ExportError::invalid_argument(format!("{:?} value does not fit in a u128", value.kind())))
+        })?
+    })
+}
+
+/// Returns the value as 16 little endian bytes.
+#[no_mangle]
+extern "C" fn borsh_value_as_i128(value: Handle) -> ResultExport<VecRawParts> {
+    export_result(|| {
+        handle_table::with(value, |value: &mut BorshValue| {
+            value.as_i128()
+                .map(|value| to_raw_parts(value.to_le_bytes().to_vec()))
+                .ok_or_else(|| ExportError::invalid_argument(format!("{:?} value does not fit in an i128", value.kind())))
+        })?
+    })
+}
+
+#[no_mangle]
+extern "C" fn borsh_value_as_string(value: Handle) -> ResultExport<*mut c_char> {
+    export_result(|| {
+        let value = handle_table::get::<BorshValue>(value)?;
+        match value {
+            BorshValue::String(value) => c_char_ptr_from_string(value),
+            _ => Err(value.wrong_kind(BorshValueKind::String)),
+        }
+    })
+}
+
+#[no_mangle]
+extern "C" fn borsh_value_as_pubkey(value: Handle) -> ResultExport<Handle> {
+    export_result(|| {
+        let pubkey = handle_table::with(value, |value: &mut BorshValue| match value {
+            BorshValue::Pubkey(pubkey) => Ok(*pubkey),
+            _ => Err(value.wrong_kind(BorshValueKind::Pubkey)),
+        })??;
+        Ok(handle_table::insert(pubkey))
+    })
+}
+
+/// Returns a sequence of values that all fit in a byte as a byte buffer.
+#[no_mangle]
+extern "C" fn borsh_value_as_bytes(value: Handle) -> ResultExport<VecRawParts> {
+    export_result(|| {
+        handle_table::with(value, |value: &mut BorshValue| match value {
+            BorshValue::Sequence(values) => values
+                .iter()
+                .map(|value| value.as_u128()
+                    .and_then(|value| u8::try_from(value).ok())
+                    .ok_or_else(|| ExportError::invalid_argument(format!("{:?} value does not fit in a byte", value.kind()))))
+                .collect::<Result<Vec<u8>, _>>()
+                .map(to_raw_parts),
+            _ => Err(value.wrong_kind(BorshValueKind::Sequence)),
+        })?
+    })
+}
+
+/// Returns a copy of the value inside an option, if it has one.
+#[no_mangle]
+extern "C" fn borsh_value_option(value: Handle) -> ResultExport<OptionExport<Handle>> {
+    export_result(|| {
+        let inner = handle_table::with(value, |value: &mut BorshValue| match value {
+            BorshValue::Option(inner) => Ok(inner.as_deref().cloned()),
+            _ => Err(value.wrong_kind(BorshValueKind::Option)),
+        })??;
+        Ok(match inner {
+            Some(inner) => OptionExport { has_value: true as u8, value: handle_table::insert(inner) },
+            None => OptionExport::export_default(),
+        })
+    })
+}
+
+#[no_mangle]
+extern "C" fn borsh_value_enum_variant(value: Handle) -> ResultExport<u8> {
+    export_result(|| {
+        handle_table::with(value, |value: &mut BorshValue| match value {
+            BorshValue::Enum { variant, .. } => Ok(*variant),
+            _ => Err(value.wrong_kind(BorshValueKind::Enum)),
+        })?
+    })
+}
+
+/// Number of elements of a sequence, or of fields of a struct or enum value.
+#[no_mangle]
+extern "C" fn borsh_value_len(value: Handle) -> ResultExport<u32> {
+    export_result(|| {
+        handle_table::with(value, |value: &mut BorshValue| match value {
+            BorshValue::Sequence(values) => Ok(values.len() as u32),
+            _ => value.fields()
+                .map(|fields| fields.len() as u32)
+                .ok_or_else(|| value.wrong_kind(BorshValueKind::Sequence)),
+        })?
+    })
+}
+
+/// Returns a copy of the element or field at `index`, see `borsh_value_len`.
+#[no_mangle]
+extern "C" fn borsh_value_get(value: Handle, index: u32) -> ResultExport<Handle> {
+    export_result(|| {
+        let item = handle_table::with(value, |value: &mut BorshValue| {
+            let item = match value {
+                BorshValue::Sequence(values) => values.get(index as usize),
+                _ => value.fields()
+                    .ok_or_else(|| value.wrong_kind(BorshValueKind::Sequence))?
+                    .get(index as usize)
+                    .map(|(_, item)| item),
+            };
+            item.cloned().ok_or_else(|| ExportError::invalid_argument(format!("Index {} is out of range", index)))
+        })??;
+        Ok(handle_table::insert(item))
+    })
+}
+
+#[no_mangle]
+extern "C" fn borsh_value_field_name(value: Handle, index: u32) -> ResultExport<*mut c_char> {
+    export_result(|| {
+        let name = handle_table::with(value, |value: &mut BorshValue| {
+            value.fields()
+                .ok_or_else(|| value.wrong_kind(BorshValueKind::Struct))?
+                .get(index as usize)
+                .map(|(name, _)| name.clone())
+                .ok_or_else(|| ExportError::invalid_argument(format!("Index {} is out of range", index)))
+        })??;
+        c_char_ptr_from_string(name)
+    })
+}
+
+/// Returns a copy of field `name` of a struct or enum value.
+#[no_mangle]
+extern "C" fn borsh_value_field(value: Handle, name: *const c_char) -> ResultExport<Handle> {
+    export_result(|| {
+        let name = str_from_c_char_ptr(name)?;
+        let item = handle_table::with(value, |value: &mut BorshValue| {
+            value.fields()
+                .ok_or_else(|| value.wrong_kind(BorshValueKind::Struct))?
+                .iter()
+                .find(|(existing, _)| existing == name)
+                .map(|(_, item)| item.clone())
+                .ok_or_else(|| ExportError::invalid_argument(format!("No field {}", name)))
+        })??;
+        Ok(handle_table::insert(item))
+    })
+}
+
+#[no_mangle]
+extern "C" fn borsh_serialize(schema: Handle, value: Handle) -> ResultExport<VecRawParts> {
+    export_result(|| {
+        let schema = handle_table::get::<BorshSchema>(schema)?;
+        let value = handle_table::get::<BorshValue>(value)?;
+        let mut out = Vec::new();
+        schema.serialize(&value, &mut out)?;
+        Ok(to_raw_parts(out))
+    })
+}
+
+/// Decodes `data`, which must hold exactly one value of `schema`, into a value tree.
+#[no_mangle]
+extern "C" fn borsh_deserialize(schema: Handle, data: *const u8, len: size_t) -> ResultExport<Handle> {
+    export_result(|| {
+        let schema = handle_table::get::<BorshSchema>(schema)?;
+        let data = slice_from_ptr(data, len, "data")?;
+        Ok(handle_table::insert(schema.deserialize_all(data)?))
+    })
+}
+
+#[no_mangle]
+extern "C" fn free_borsh_value(value: Handle) -> ResultExport<u8> {
+    export_result(|| {
+        handle_table::remove::<BorshValue>(value)?;
+        Ok(true as u8)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(BorshSerialize)]
+    struct Inner {
+        flag: bool,
+        name: String,
+    }
+
+    #[derive(BorshSerialize)]
+    enum Choice {
+        #[allow(dead_code)]
+        Empty,
+        Tuple(u64, String),
+        Named { delta: i8, inner: Inner },
+    }
+
+    #[derive(BorshSerialize)]
+    struct Everything {
+        b: bool,
+        u8: u8,
+        u16: u16,
+        u32: u32,
+        u64: u64,
+        u128: u128,
+        i8: i8,
+        i16: i16,
+        i32: i32,
+        i64: i64,
+        i128: i128,
+        string: String,
+        pubkey: [u8; 32],
+        some: Option<u16>,
+        none: Option<u16>,
+        vec: Vec<u32>,
+        array: [i16; 3],
+        choice: Choice,
+        other_choice: Choice,
+        nested: Vec<Inner>,
+    }
+
+    fn primitive(primitive: BorshPrimitive) -> BorshSchema {
+        BorshSchema::Primitive(primitive)
+    }
+
+    fn field(name: &str, schema: BorshSchema) -> BorshField {
+        BorshField { name: name.to_string(), schema }
+    }
+
+    fn value(name: &str, value: BorshValue) -> (String, BorshValue) {
+        (name.to_string(), value)
+    }
+
+    fn inner_schema() -> BorshSchema {
+        BorshSchema::Struct(vec![field("flag", primitive(BorshPrimitive::Bool)), field("name", primitive(BorshPrimitive::String))])
+    }
+
+    fn inner_value(flag: bool, name: &str) -> BorshValue {
+        BorshValue::Struct(vec![value("flag", BorshValue::Bool(flag)), value("name", BorshValue::String(name.to_string()))])
+    }
+
+    fn everything_schema() -> BorshSchema {
+        let choice = BorshSchema::Enum(vec![
+            BorshVariant { name: "Empty".to_string(), fields: vec![] },
+            BorshVariant {
+                name: "Tuple".to_string(),
+                fields: vec![field("0", primitive(BorshPrimitive::U64)), field("1", primitive(BorshPrimitive::String))],
+            },
+            BorshVariant {
+                name: "Named".to_string(),
+                fields: vec![field("delta", primitive(BorshPrimitive::I8)), field("inner", inner_schema())],
+            },
+        ]);
+        BorshSchema::Struct(vec![
+            field("b", primitive(BorshPrimitive::Bool)),
+            field("u8", primitive(BorshPrimitive::U8)),
+            field("u16", primitive(BorshPrimitive::U16)),
+            field("u32", primitive(BorshPrimitive::U32)),
+            field("u64", primitive(BorshPrimitive::U64)),
+            field("u128", primitive(BorshPrimitive::U128)),
+            field("i8", primitive(BorshPrimitive::I8)),
+            field("i16", primitive(BorshPrimitive::I16)),
+            field("i32", primitive(BorshPrimitive::I32)),
+            field("i64", primitive(BorshPrimitive::I64)),
+            field("i128", primitive(BorshPrimitive::I128)),
+            field("string", primitive(BorshPrimitive::String)),
+            field("pubkey", primitive(BorshPrimitive::Pubkey)),
+            field("some", BorshSchema::Option(Box::new(primitive(BorshPrimitive::U16)))),
+            field("none", BorshSchema::Option(Box::new(primitive(BorshPrimitive::U16)))),
+            field("vec", BorshSchema::Vec(Box::new(primitive(BorshPrimitive::U32)))),
+            field("array", BorshSchema::Array(Box::new(primitive(BorshPrimitive::I16)), 3)),
+            field("choice", choice.clone()),
+            field("other_choice", choice),
+            field("nested", BorshSchema::Vec(Box::new(inner_schema()))),
+        ])
+    }
+
+    fn everything() -> (Everything, BorshValue) {
+        let pubkey = Pubkey::new_unique();
+        let native = Everything {
+            b: true,
+            u8: u8::MAX,
+            u16: 0x1234,
+            u32: 0xdead_beef,
+            u64: u64::MAX - 1,
+            u128: u128::MAX,
+            i8: i8::MIN,
+            i16: -2,
+            i32: i32::MIN + 1,
+            i64: -1_000_000_000_000,
+            i128: i128::MIN,
+            string: "héllo".to_string(),
+            pubkey: pubkey.to_bytes(),
+            some: Some(7),
+            none: None,
+            vec: vec![1, 2, u32::MAX],
+            array: [-1, 0, 1],
+            choice: Choice::Tuple(42, "tuple".to_string()),
+            other_choice: Choice::Named { delta: -5, inner: Inner { flag: false, name: "inner".to_string() } },
+            nested: vec![Inner { flag: true, name: String::new() }, Inner { flag: false, name: "x".to_string() }],
+        };
+        let value = BorshValue::Struct(vec![
+            value("b", BorshValue::Bool(true)),
+            value("u8", BorshValue::Unsigned(u8::MAX as u128)),
+            value("u16", BorshValue::Unsigned(0x1234)),
+            value("u32", BorshValue::Unsigned(0xdead_beef)),
+            value("u64", BorshValue::Unsigned((u64::MAX - 1) as u128)),
+            value("u128", BorshValue::Unsigned(u128::MAX)),
+            value("i8", BorshValue::Signed(i8::MIN as i128)),
+            value("i16", BorshValue::Signed(-2)),
+            value("i32", BorshValue::Signed((i32::MIN + 1) as i128)),
+            value("i64", BorshValue::Signed(-1_000_000_000_000)),
+            value("i128", BorshValue::Signed(i128::MIN)),
+            value("string", BorshValue::String("héllo".to_string())),
+            value("pubkey", BorshValue::Pubkey(pubkey)),
+            value("some", BorshValue::Option(Some(Box::new(BorshValue::Unsigned(7))))),
+            value("none", BorshValue::Option(None)),
+            value("vec", BorshValue::Sequence(vec![
+                BorshValue::Unsigned(1), BorshValue::Unsigned(2), BorshValue::Unsigned(u32::MAX as u128),
+            ])),
+            value("array", BorshValue::Sequence(vec![BorshValue::Signed(-1), BorshValue::Signed(0), BorshValue::Signed(1)])),
+            value("choice", BorshValue::Enum {
+                variant: 1,
+                fields: vec![value("0", BorshValue::Unsigned(42)), value("1", BorshValue::String("tuple".to_string()))],
+            }),
+            value("other_choice", BorshValue::Enum {
+                variant: 2,
+                fields: vec![value("delta", BorshValue::Signed(-5)), value("inner", inner_value(false, "inner"))],
+            }),
+            value("nested", BorshValue::Sequence(vec![inner_value(true, ""), inner_value(false, "x")])),
+        ]);
+        (native, value)
+    }
+
+    #[test]
+    fn round_trip_matches_derived_encoding() {
+        let schema = everything_schema();
+        let (native, value) = everything();
+
+        let mut bytes = Vec::new();
+        schema.serialize(&value, &mut bytes).unwrap();
+        assert_eq!(bytes, native.try_to_vec().unwrap());
+        assert_eq!(schema.deserialize_all(&bytes).unwrap(), value);
+    }
+
+    #[test]
+    fn every_primitive_round_trips() {
+        let cases = [
+            (BorshPrimitive::Bool, BorshValue::Bool(false), false.try_to_vec().unwrap()),
+            (BorshPrimitive::U8, BorshValue::Unsigned(200), 200u8.try_to_vec().unwrap()),
+            (BorshPrimitive::U16, BorshValue::Unsigned(65535), 65535u16.try_to_vec().unwrap()),
+            (BorshPrimitive::U32, BorshValue::Unsigned(1 << 31), (1u32 << 31).try_to_vec().unwrap()),
+            (BorshPrimitive::U64, BorshValue::Unsigned(1 << 63), (1u64 << 63).try_to_vec().unwrap()),
+            (BorshPrimitive::U128, BorshValue::Unsigned(1 << 127), (1u128 << 127).try_to_vec().unwrap()),
+            (BorshPrimitive::I8, BorshValue::Signed(-128), (-128i8).try_to_vec().unwrap()),
+            (BorshPrimitive::I16, BorshValue::Signed(-300), (-300i16).try_to_vec().unwrap()),
+            (BorshPrimitive::I32, BorshValue::Signed(i32::MAX as i128), i32::MAX.try_to_vec().unwrap()),
+            (BorshPrimitive::I64, BorshValue::Signed(i64::MIN as i128), i64::MIN.try_to_vec().unwrap()),
+            (BorshPrimitive::I128, BorshValue::Signed(i128::MAX), i128::MAX.try_to_vec().unwrap()),
+            (BorshPrimitive::String, BorshValue::String(String::new()), String::new().try_to_vec().unwrap()),
+            (BorshPrimitive::Pubkey, BorshValue::Pubkey(Pubkey::new_from_array([9; 32])), [9u8; 32].try_to_vec().unwrap()),
+        ];
+        for (primitive, value, expected) in cases {
+            let schema = BorshSchema::Primitive(primitive);
+            let mut bytes = Vec::new();
+            schema.serialize(&value, &mut bytes).unwrap();
+            assert_eq!(bytes, expected, "{:?}", primitive);
+            assert_eq!(schema.deserialize_all(&bytes).unwrap(), value, "{:?}", primitive);
+        }
+    }
+
+    #[test]
+    fn truncated_and_trailing_data_is_rejected() {
+        let schema = everything_schema();
+        let (native, _) = everything();
+        let bytes = native.try_to_vec().unwrap();
+
+        for len in 0..bytes.len() {
+            assert!(schema.deserialize_all(&bytes[..len]).is_err(), "{} of {} bytes", len, bytes.len());
+        }
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(schema.deserialize_all(&trailing).unwrap_err().code(), ErrorCode::Serialization);
+    }
+
+    #[test]
+    fn invalid_tags_are_rejected() {
+        let option = BorshSchema::Option(Box::new(primitive(BorshPrimitive::U8)));
+        assert_eq!(option.deserialize_all(&[2, 0]).unwrap_err().code(), ErrorCode::Serialization);
+        let schema = everything_schema();
+        let enum_schema = match &schema {
+            BorshSchema::Struct(fields) => fields.iter().find(|field| field.name == "choice").unwrap().schema.clone(),
+            _ => unreachable!(),
+        };
+        assert_eq!(enum_schema.deserialize_all(&[3]).unwrap_err().code(), ErrorCode::Serialization);
+    }
+
+    #[test]
+    fn values_not_matching_the_schema_are_rejected() {
+        let mismatch = |schema: BorshSchema, value: BorshValue| {
+            assert_eq!(schema.serialize(&value, &mut Vec::new()).unwrap_err().code(), ErrorCode::BorshSchemaMismatch);
+        };
+        mismatch(primitive(BorshPrimitive::U8), BorshValue::Unsigned(256));
+        mismatch(primitive(BorshPrimitive::I8), BorshValue::Signed(128));
+        mismatch(primitive(BorshPrimitive::U16), BorshValue::Signed(-1));
+        mismatch(primitive(BorshPrimitive::Bool), BorshValue::Unsigned(1));
+        mismatch(BorshSchema::Array(Box::new(primitive(BorshPrimitive::U8)), 2), BorshValue::Sequence(vec![BorshValue::Unsigned(1)]));
+        mismatch(inner_schema(), BorshValue::Struct(vec![value("flag", BorshValue::Bool(true))]));
+        mismatch(inner_schema(), BorshValue::Struct(vec![
+            value("flag", BorshValue::Bool(true)), value("name", BorshValue::String(String::new())), value("extra", BorshValue::Bool(true)),
+        ]));
+    }
+}
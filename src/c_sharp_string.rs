@@ -1,41 +1,77 @@
 use std::ffi::{CStr, CString};
 use std::os::raw::c_char;
-use std::str::FromStr;
 
+use zeroize::Zeroize;
 
-pub fn str_from_c_char_ptr<'a>(s: *const c_char) -> Result<&'a str, std::str::Utf8Error> {
-    unsafe {
-        assert!(!s.is_null());
-        CStr::from_ptr(s)
+use crate::error_export::{export_guard, ExportError};
+
+
+pub fn str_from_c_char_ptr<'a>(s: *const c_char) -> Result<&'a str, ExportError> {
+    if s.is_null() {
+        return Err(ExportError::null_pointer("string"));
     }
-        .to_str()
+    Ok(unsafe { CStr::from_ptr(s) }.to_str()?)
 }
 
-pub fn string_from_c_char_ptr(s: *const c_char) -> Result<String, std::str::Utf8Error> {
+pub fn string_from_c_char_ptr(s: *const c_char) -> Result<String, ExportError> {
     Ok(str_from_c_char_ptr(s)?.to_string())
 }
 
-pub fn string_from_csharp(s: *const c_char) -> String {
-    let c_str = unsafe {
-        assert!(!s.is_null());
-        CStr::from_ptr(s)
-    };
-    let r_str = c_str.to_str().unwrap();
-    r_str.into()
+/// Hands a Rust string to the caller, to be released with `free_cstring`.
+///
+/// Passing a `&str` copies it straight into a buffer sized for the nul terminator, so a
+/// secret passed that way leaves no reallocated copy behind.
+pub fn c_char_ptr_from_string<S: Into<Vec<u8>>>(s: S) -> Result<*mut c_char, ExportError> {
+    Ok(CString::new(s)?.into_raw())
 }
 
 #[no_mangle]
 extern "C" fn ffi_free_cstring(ptr: *mut c_char) {
-    if ptr.is_null() {
-        return;
-    }
-    unsafe { CString::from_raw(ptr) };
+    free_cstring(ptr)
 }
 
 #[no_mangle]
 extern "C" fn free_cstring(ptr: *mut c_char) {
-    if ptr.is_null() {
-        return;
+    export_guard(|| {
+        if !ptr.is_null() {
+            drop(unsafe { CString::from_raw(ptr) });
+        }
+        Ok(())
+    })
+}
+
+/// Takes back a string handed out by `c_char_ptr_from_string` and overwrites it, nul
+/// terminator included. `ptr` must not be null.
+unsafe fn wipe_cstring(ptr: *mut c_char) -> Vec<u8> {
+    let mut bytes = CString::from_raw(ptr).into_bytes_with_nul();
+    bytes.as_mut_slice().zeroize();
+    bytes
+}
+
+/// Like `free_cstring`, but overwrites the string before releasing it. Use it for
+/// strings holding secret keys or mnemonic phrases.
+#[no_mangle]
+extern "C" fn free_secret_cstring(ptr: *mut c_char) {
+    export_guard(|| {
+        if !ptr.is_null() {
+            drop(unsafe { wipe_cstring(ptr) });
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secret_strings_are_wiped() {
+        let phrase = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+        let ptr = c_char_ptr_from_string(phrase).unwrap();
+        let wiped = unsafe { wipe_cstring(ptr) };
+        assert_eq!(wiped.len(), phrase.len() + 1);
+        assert!(wiped.iter().all(|byte| *byte == 0));
+
+        free_secret_cstring(std::ptr::null_mut());
     }
-    unsafe { CString::from_raw(ptr) };
 }
@@ -1,12 +1,19 @@
 use std::ffi::c_void;
 use std::os::raw::c_char;
-use std::ptr::null_mut;
+use std::ptr::{null, null_mut};
+
+use libc::size_t;
+
+use zeroize::Zeroize;
+
+use crate::error_export::{ErrorCode, export_guard, ExportError};
 
 #[repr(C)]
 pub struct ResultExport<T> {
     pub is_error: u8,
     pub result: T,
     pub error: *mut c_char,
+    pub error_code: ErrorCode,
 }
 
 #[repr(C)]
@@ -33,21 +40,95 @@ impl Default for VecRawParts {
     }
 }
 
+/// Value placed in `ResultExport::result` when an export fails.
+pub trait ExportDefault {
+    fn export_default() -> Self;
+}
+
+impl<T> ExportDefault for *mut T {
+    fn export_default() -> Self {
+        null_mut()
+    }
+}
+
+impl<T> ExportDefault for *const T {
+    fn export_default() -> Self {
+        null()
+    }
+}
+
+impl ExportDefault for u8 {
+    fn export_default() -> Self {
+        0
+    }
+}
+
+impl ExportDefault for u32 {
+    fn export_default() -> Self {
+        0
+    }
+}
+
+impl ExportDefault for u64 {
+    fn export_default() -> Self {
+        0
+    }
+}
+
+impl ExportDefault for i64 {
+    fn export_default() -> Self {
+        0
+    }
+}
+
+impl<T: ExportDefault> ExportDefault for OptionExport<T> {
+    fn export_default() -> Self {
+        Self {
+            has_value: 0,
+            value: T::export_default(),
+        }
+    }
+}
+
+impl ExportDefault for VecRawParts {
+    fn export_default() -> Self {
+        Self::default()
+    }
+}
+
+/// Borrows the value behind a caller supplied pointer.
+pub fn ref_from_ptr<'a, T>(ptr: *mut T, name: &str) -> Result<&'a mut T, ExportError> {
+    unsafe { ptr.as_mut() }.ok_or_else(|| ExportError::null_pointer(name))
+}
+
+/// Borrows a caller owned buffer; a null pointer is only accepted for an empty buffer.
+pub fn slice_from_ptr<'a, T>(ptr: *const T, len: size_t, name: &str) -> Result<&'a [T], ExportError> {
+    if ptr.is_null() {
+        return if len == 0 {
+            Ok(&[])
+        } else {
+            Err(ExportError::null_pointer(name))
+        };
+    }
+    Ok(unsafe { std::slice::from_raw_parts(ptr, len) })
+}
+
 #[no_mangle]
 extern "C" fn init_vec(byte_length: u32, out_raw_parts_ptr: *mut VecRawParts) {
-    let mut vec = Vec::<u8>::new();
-    vec.resize(byte_length as usize, 0);
-    let raw = Vec::into_raw_parts(vec);
-    let mut raw_parts = unsafe { out_raw_parts_ptr.as_mut().unwrap() };
-    raw_parts.data = raw.0 as *const c_void;
-    raw_parts.length = raw.1 as u32;
-    raw_parts.capacity = raw.2 as u32;
+    export_guard(|| {
+        let raw_parts = ref_from_ptr(out_raw_parts_ptr, "out_raw_parts_ptr")?;
+        let raw = Vec::into_raw_parts(vec![0u8; byte_length as usize]);
+        raw_parts.data = raw.0 as *const c_void;
+        raw_parts.length = raw.1 as u32;
+        raw_parts.capacity = raw.2 as u32;
+        Ok(())
+    })
 }
 
 pub fn from_raw_parts<T>(parts: &VecRawParts) -> Vec<T> {
     unsafe {
         if parts.capacity == 0 {
-            return Vec::new();
+            Vec::new()
         } else {
             let element_size = std::mem::size_of::<T>();
             Vec::<T>::from_raw_parts(
@@ -61,30 +142,83 @@ pub fn from_raw_parts<T>(parts: &VecRawParts) -> Vec<T> {
 
 #[no_mangle]
 extern "C" fn free_vec(raw_parts: VecRawParts) {
-    unsafe {
-        Vec::from_raw_parts(
-            raw_parts.data as *mut c_void,
-            raw_parts.length as usize,
-            raw_parts.capacity as usize,
-        );
-    }
+    export_guard(|| {
+        if raw_parts.data.is_null() {
+            return Ok(());
+        }
+        drop(unsafe {
+            Vec::from_raw_parts(
+                raw_parts.data as *mut c_void,
+                raw_parts.length as usize,
+                raw_parts.capacity as usize,
+            )
+        });
+        Ok(())
+    })
+}
+
+/// Takes back a byte buffer handed out by `to_raw_parts` and overwrites all of it, spare
+/// capacity included. `raw_parts.data` must not be null.
+unsafe fn wipe_raw_parts(raw_parts: VecRawParts) -> Vec<u8> {
+    let capacity = raw_parts.capacity as usize;
+    let mut bytes = Vec::from_raw_parts(raw_parts.data as *mut u8, raw_parts.length as usize, capacity);
+    // Zeroes the elements, then the spare capacity, so the whole buffer is initialized.
+    bytes.zeroize();
+    bytes.set_len(capacity);
+    bytes
+}
+
+/// Like `free_vec`, but overwrites the whole buffer before releasing it. Use it for
+/// buffers holding secret keys, seeds or entropy.
+#[no_mangle]
+extern "C" fn free_secret_vec(raw_parts: VecRawParts) {
+    export_guard(|| {
+        if raw_parts.data.is_null() {
+            return Ok(());
+        }
+        drop(unsafe { wipe_raw_parts(raw_parts) });
+        Ok(())
+    })
 }
 
 pub fn to_raw_parts<T>(vec: Vec<T>) -> VecRawParts {
-    //unsafe {
-        if vec.capacity() == 0 {
-            return VecRawParts{
-                data: null_mut(),
-                length: 0,
-                capacity: 0
-            };
-        } else {
-            let raw_parts = vec.into_raw_parts();
-            VecRawParts{
-                data: raw_parts.0 as *mut c_void,
-                length: raw_parts.1 as u32,
-                capacity: raw_parts.2 as u32
-            }
+    if vec.capacity() == 0 {
+        VecRawParts{
+            data: null_mut(),
+            length: 0,
+            capacity: 0
+        }
+    } else {
+        let raw_parts = vec.into_raw_parts();
+        VecRawParts{
+            data: raw_parts.0 as *mut c_void,
+            length: raw_parts.1 as u32,
+            capacity: raw_parts.2 as u32
         }
-    //}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secret_buffers_are_wiped_to_their_capacity() {
+        let mut secret = vec![0xa5u8; 64];
+        secret.truncate(48);
+        let raw_parts = to_raw_parts(secret);
+        assert_eq!((raw_parts.length, raw_parts.capacity), (48, 64));
+
+        let wiped = unsafe { wipe_raw_parts(raw_parts) };
+        assert_eq!(wiped.len(), 64);
+        assert!(wiped.iter().all(|byte| *byte == 0));
+    }
+
+    #[test]
+    fn empty_buffers_are_released() {
+        let raw_parts = to_raw_parts(Vec::<u8>::new());
+        assert!(raw_parts.data.is_null());
+        free_secret_vec(raw_parts);
+        free_vec(to_raw_parts(Vec::<u8>::new()));
+    }
 }
@@ -0,0 +1,187 @@
+use solana_program::instruction::{AccountMeta, Instruction};
+use solana_program::message::Message;
+use solana_sdk::compute_budget::{self, ComputeBudgetInstruction};
+
+use crate::common_types::ResultExport;
+use crate::error_export::{export_result, ExportError};
+use crate::handle_table::{self, Handle};
+
+pub const MICRO_LAMPORTS_PER_LAMPORT: u64 = 1_000_000;
+pub const MIN_HEAP_FRAME_BYTES: u32 = 32 * 1024;
+pub const MAX_HEAP_FRAME_BYTES: u32 = 256 * 1024;
+pub const HEAP_FRAME_BYTES_GRANULARITY: u32 = 1024;
+
+fn request_heap_frame(bytes: u32) -> Result<Instruction, ExportError> {
+    if !(MIN_HEAP_FRAME_BYTES..=MAX_HEAP_FRAME_BYTES).contains(&bytes) || !bytes.is_multiple_of(HEAP_FRAME_BYTES_GRANULARITY) {
+        return Err(ExportError::invalid_argument(format!(
+            "Heap frame must be a multiple of {} bytes between {} and {}, got {}",
+            HEAP_FRAME_BYTES_GRANULARITY, MIN_HEAP_FRAME_BYTES, MAX_HEAP_FRAME_BYTES, bytes)));
+    }
+    Ok(ComputeBudgetInstruction::request_heap_frame(bytes))
+}
+
+/// Writability as compiled into the header. `Message::is_writable` is the runtime's view,
+/// which demotes builtins, sysvars and invoked programs to read-only.
+fn is_writable_in_header(message: &Message, index: usize) -> bool {
+    let header = &message.header;
+    let num_required_signatures = header.num_required_signatures as usize;
+    index < num_required_signatures.saturating_sub(header.num_readonly_signed_accounts as usize)
+        || (index >= num_required_signatures
+            && index < message.account_keys.len().saturating_sub(header.num_readonly_unsigned_accounts as usize))
+}
+
+/// Rebuilds the instructions a legacy message was compiled from.
+fn message_instructions(message: &Message) -> Vec<Instruction> {
+    message.instructions
+        .iter()
+        .map(|instruction| Instruction {
+            program_id: message.account_keys[instruction.program_id_index as usize],
+            accounts: instruction.accounts
+                .iter()
+                .map(|index| {
+                    let index = *index as usize;
+                    AccountMeta {
+                        pubkey: message.account_keys[index],
+                        is_signer: message.is_signer(index),
+                        is_writable: is_writable_in_header(message, index),
+                    }
+                })
+                .collect(),
+            data: instruction.data.clone(),
+        })
+        .collect()
+}
+
+fn is_same_compute_budget_instruction(instruction: &Instruction, other: &Instruction) -> bool {
+    instruction.program_id == compute_budget::id() && instruction.data.first() == other.data.first()
+}
+
+#[no_mangle]
+extern "C" fn compute_budget_set_compute_unit_limit(units: u32) -> ResultExport<Handle> {
+    export_result(|| Ok(handle_table::insert(ComputeBudgetInstruction::set_compute_unit_limit(units))))
+}
+
+#[no_mangle]
+extern "C" fn compute_budget_set_compute_unit_price(micro_lamports: u64) -> ResultExport<Handle> {
+    export_result(|| Ok(handle_table::insert(ComputeBudgetInstruction::set_compute_unit_price(micro_lamports))))
+}
+
+#[no_mangle]
+extern "C" fn compute_budget_request_heap_frame(bytes: u32) -> ResultExport<Handle> {
+    export_result(|| Ok(handle_table::insert(request_heap_frame(bytes)?)))
+}
+
+/// Returns a new message with compute budget instructions in front of the instructions
+/// of `message`, replacing any of the same kind it already had. A zero argument adds no
+/// instruction for that setting. Fee payer and recent blockhash are kept.
+#[no_mangle]
+extern "C" fn compute_budget_prepend_to_message(message: Handle, compute_unit_limit: u32, compute_unit_price: u64,
+                                                heap_frame_bytes: u32) -> ResultExport<Handle> {
+    export_result(|| {
+        let message = handle_table::get::<Message>(message)?;
+        let payer = message.account_keys.first().copied()
+            .ok_or_else(|| ExportError::invalid_argument("Message has no fee payer"))?;
+
+        let mut budget_instructions = Vec::new();
+        if compute_unit_limit != 0 {
+            budget_instructions.push(ComputeBudgetInstruction::set_compute_unit_limit(compute_unit_limit));
+        }
+        if compute_unit_price != 0 {
+            budget_instructions.push(ComputeBudgetInstruction::set_compute_unit_price(compute_unit_price));
+        }
+        if heap_frame_bytes != 0 {
+            budget_instructions.push(request_heap_frame(heap_frame_bytes)?);
+        }
+
+        let mut instructions = message_instructions(&message);
+        instructions.retain(|instruction| !budget_instructions
+            .iter()
+            .any(|budget_instruction| is_same_compute_budget_instruction(instruction, budget_instruction)));
+        budget_instructions.append(&mut instructions);
+
+        Ok(handle_table::insert(Message::new_with_blockhash(&budget_instructions, Some(&payer), &message.recent_blockhash)))
+    })
+}
+
+/// Priority fee in lamports paid for `compute_unit_limit` units at `compute_unit_price`
+/// micro-lamports each, rounded up like the runtime does.
+#[no_mangle]
+extern "C" fn compute_budget_priority_fee(compute_unit_price: u64, compute_unit_limit: u32) -> ResultExport<u64> {
+    export_result(|| {
+        let micro_lamports = compute_unit_price as u128 * compute_unit_limit as u128;
+        let lamports = micro_lamports.div_ceil(MICRO_LAMPORTS_PER_LAMPORT as u128);
+        u64::try_from(lamports)
+            .map_err(|_| ExportError::invalid_argument("Priority fee overflows u64"))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_program::hash::Hash;
+    use solana_program::pubkey::Pubkey;
+    use solana_program::sysvar;
+
+    use super::*;
+
+    #[test]
+    fn prepend_keeps_account_metas() {
+        let payer = Pubkey::new_unique();
+        let program_id = Pubkey::new_unique();
+        // A writable sysvar and a writable program account are demoted by the runtime, but
+        // must survive a rebuild as compiled.
+        let other_program = Pubkey::new_unique();
+        let instructions = vec![
+            Instruction::new_with_bytes(program_id, &[1, 2, 3], vec![
+                AccountMeta::new(payer, true),
+                AccountMeta::new(Pubkey::new_unique(), true),
+                AccountMeta::new_readonly(Pubkey::new_unique(), true),
+                AccountMeta::new(Pubkey::new_unique(), false),
+                AccountMeta::new_readonly(Pubkey::new_unique(), false),
+                AccountMeta::new(sysvar::clock::id(), false),
+                AccountMeta::new(other_program, false),
+            ]),
+            Instruction::new_with_bytes(other_program, &[4], vec![AccountMeta::new_readonly(Pubkey::new_unique(), false)]),
+        ];
+        let message = Message::new_with_blockhash(&instructions, Some(&payer), &Hash::new_unique());
+        assert!(!message.is_writable(message.account_keys.iter().position(|key| *key == other_program).unwrap()));
+        assert_eq!(message_instructions(&message), instructions);
+
+        let prepended = compute_budget_prepend_to_message(handle_table::insert(message.clone()), 200_000, 1_000, 0);
+        assert_eq!(prepended.is_error, 0);
+        let prepended = handle_table::get::<Message>(prepended.result).unwrap();
+        let rebuilt = message_instructions(&prepended);
+        assert_eq!(rebuilt[0], ComputeBudgetInstruction::set_compute_unit_limit(200_000));
+        assert_eq!(rebuilt[1], ComputeBudgetInstruction::set_compute_unit_price(1_000));
+        assert_eq!(rebuilt[2..], instructions[..]);
+        assert_eq!((prepended.account_keys[0], prepended.recent_blockhash), (payer, message.recent_blockhash));
+    }
+
+    #[test]
+    fn prepend_replaces_existing_budget_instructions() {
+        let payer = Pubkey::new_unique();
+        let transfer = Instruction::new_with_bytes(Pubkey::new_unique(), &[], vec![AccountMeta::new(payer, true)]);
+        let message = Message::new(&[ComputeBudgetInstruction::set_compute_unit_price(5), transfer.clone()], Some(&payer));
+
+        let prepended = compute_budget_prepend_to_message(handle_table::insert(message), 0, 7, 0);
+        let prepended = handle_table::get::<Message>(prepended.result).unwrap();
+        assert_eq!(message_instructions(&prepended), vec![ComputeBudgetInstruction::set_compute_unit_price(7), transfer]);
+    }
+
+    #[test]
+    fn priority_fee_rounds_up() {
+        assert_eq!(compute_budget_priority_fee(0, 200_000).result, 0);
+        assert_eq!(compute_budget_priority_fee(1, 1).result, 1);
+        assert_eq!(compute_budget_priority_fee(1_000, 200_000).result, 200);
+        assert_eq!(compute_budget_priority_fee(1_000, 200_001).result, 201);
+        assert_eq!(compute_budget_priority_fee(3, 333_333).result, 1);
+        assert_eq!(compute_budget_priority_fee(u64::MAX, u32::MAX).is_error, 1);
+    }
+
+    #[test]
+    fn heap_frame_is_validated() {
+        assert!(request_heap_frame(MIN_HEAP_FRAME_BYTES).is_ok());
+        assert!(request_heap_frame(MAX_HEAP_FRAME_BYTES).is_ok());
+        assert!(request_heap_frame(MIN_HEAP_FRAME_BYTES - HEAP_FRAME_BYTES_GRANULARITY).is_err());
+        assert!(request_heap_frame(MIN_HEAP_FRAME_BYTES + 1).is_err());
+    }
+}
@@ -0,0 +1,451 @@
+use std::convert::TryFrom;
+use std::os::raw::c_char;
+
+use serde_json::json;
+use solana_sdk::signature::Signature;
+use solana_sdk::transaction::TransactionError;
+
+use crate::c_sharp_string::str_from_c_char_ptr;
+use crate::common_types::{ExportDefault, OptionExport, ResultExport};
+use crate::error_export::{ErrorCode, export_result, ExportError};
+use crate::handle_table::{self, Handle, HandleObject, HandleType};
+use crate::rpc_export::{Commitment, parse_response, request_body, RpcSignatureStatus, transaction_error_export,
+                        TransactionErrorExport, WithContext};
+
+/// Blocks to wait for a sent transaction to show up before sending it again.
+pub const DEFAULT_REBROADCAST_INTERVAL_BLOCKS: u64 = 10;
+
+/// Progress of a sent transaction. `Finalized`, `Expired` and `Failed` are final.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ConfirmationState {
+    /// The node has not reported the signature yet.
+    Pending = 0,
+    Processed = 1,
+    Confirmed = 2,
+    Finalized = 3,
+    /// The blockhash expired while the node did not report the signature, either never
+    /// or no longer, e.g. after the fork it was processed on was dropped.
+    Expired = 4,
+    /// The transaction landed but failed, see `confirmation_tracker_error`.
+    Failed = 5,
+}
+
+impl ConfirmationState {
+    fn from_commitment(commitment: Commitment) -> Self {
+        match commitment {
+            Commitment::Processed => ConfirmationState::Processed,
+            Commitment::Confirmed => ConfirmationState::Confirmed,
+            Commitment::Default | Commitment::Finalized => ConfirmationState::Finalized,
+        }
+    }
+
+    pub fn is_final(self) -> bool {
+        matches!(self, ConfirmationState::Finalized | ConfirmationState::Expired | ConfirmationState::Failed)
+    }
+}
+
+impl ExportDefault for ConfirmationState {
+    fn export_default() -> Self {
+        ConfirmationState::Pending
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Broadcast {
+    Never,
+    /// Sent before any block height was fed; the next one counts as the send height.
+    Pending,
+    At(u64),
+}
+
+/// Tracks a sent transaction from the signature statuses and block heights the caller
+/// fetches, and decides when to send it again.
+pub struct ConfirmationTracker {
+    signature: Signature,
+    last_valid_block_height: u64,
+    target: ConfirmationState,
+    rebroadcast_interval_blocks: u64,
+    state: ConfirmationState,
+    err: Option<TransactionError>,
+    /// Whether the last status fed had the signature. A transaction processed on a fork
+    /// that was then dropped is no longer reported.
+    reported: bool,
+    block_height: Option<u64>,
+    last_broadcast: Broadcast,
+}
+
+impl HandleObject for ConfirmationTracker {
+    const HANDLE_TYPE: HandleType = HandleType::ConfirmationTracker;
+}
+
+impl ConfirmationTracker {
+    pub fn new(signature: Signature, last_valid_block_height: u64, commitment: Commitment,
+               rebroadcast_interval_blocks: u64) -> Self {
+        Self {
+            signature,
+            last_valid_block_height,
+            target: ConfirmationState::from_commitment(commitment),
+            rebroadcast_interval_blocks: match rebroadcast_interval_blocks {
+                0 => DEFAULT_REBROADCAST_INTERVAL_BLOCKS,
+                blocks => blocks,
+            },
+            state: ConfirmationState::Pending,
+            err: None,
+            reported: false,
+            block_height: None,
+            last_broadcast: Broadcast::Never,
+        }
+    }
+
+    pub fn state(&self) -> ConfirmationState {
+        self.state
+    }
+
+    pub fn err(&self) -> Option<&TransactionError> {
+        self.err.as_ref()
+    }
+
+    /// Final, or at the commitment the caller waits for.
+    pub fn is_done(&self) -> bool {
+        self.state.is_final() || self.state >= self.target
+    }
+
+    /// Applies the node's status for the signature, `None` if it does not know it (yet).
+    /// States only move forward; a status showing up after `Expired` still counts, since
+    /// the transaction then landed before its blockhash expired.
+    pub fn on_status(&mut self, status: Option<&RpcSignatureStatus>) -> Result<ConfirmationState, ExportError> {
+        self.reported = status.is_some();
+        let status = match status {
+            Some(status) if !matches!(self.state, ConfirmationState::Finalized | ConfirmationState::Failed) => status,
+            Some(_) => return Ok(self.state),
+            None => {
+                self.expire_if_unreported();
+                return Ok(self.state);
+            }
+        };
+        if let Some(err) = &status.err {
+            self.err = Some(err.clone());
+            self.state = ConfirmationState::Failed;
+        } else {
+            let reported = ConfirmationState::from_commitment(status.commitment()?);
+            self.state = match self.state {
+                ConfirmationState::Expired => reported,
+                state => state.max(reported),
+            };
+        }
+        Ok(self.state)
+    }
+
+    /// A transaction the node does not report expires once the block height passes
+    /// `last_valid_block_height`, unless it already reached the commitment.
+    pub fn on_block_height(&mut self, block_height: u64) -> ConfirmationState {
+        let block_height = self.block_height.map_or(block_height, |known| known.max(block_height));
+        self.block_height = Some(block_height);
+        if self.last_broadcast == Broadcast::Pending {
+            self.last_broadcast = Broadcast::At(block_height);
+        }
+        self.expire_if_unreported();
+        self.state
+    }
+
+    fn is_past_last_valid_block_height(&self) -> bool {
+        self.block_height.is_some_and(|block_height| block_height > self.last_valid_block_height)
+    }
+
+    fn expire_if_unreported(&mut self) {
+        if !self.reported && !self.is_done() && self.is_past_last_valid_block_height() {
+            self.state = ConfirmationState::Expired;
+        }
+    }
+
+    /// Records that the transaction was just sent. Before the first block height is fed,
+    /// the send is dated by the next one.
+    pub fn on_broadcast(&mut self) {
+        self.last_broadcast = match self.block_height {
+            Some(block_height) => Broadcast::At(block_height),
+            None => Broadcast::Pending,
+        };
+    }
+
+    /// True while the node does not report the transaction, its blockhash is still valid
+    /// and it was never sent or not within the rebroadcast interval.
+    pub fn should_rebroadcast(&self) -> bool {
+        if self.reported || self.is_done() || self.is_past_last_valid_block_height() {
+            return false;
+        }
+        match (self.last_broadcast, self.block_height) {
+            (Broadcast::Never, _) => true,
+            (Broadcast::At(sent), Some(block_height)) => block_height >= sent.saturating_add(self.rebroadcast_interval_blocks),
+            _ => false,
+        }
+    }
+}
+
+/// Tracks `signature` until it reaches `commitment`. A zero `rebroadcast_interval_blocks`
+/// uses `DEFAULT_REBROADCAST_INTERVAL_BLOCKS`.
+#[no_mangle]
+extern "C" fn confirmation_tracker_new(signature: Handle, last_valid_block_height: u64, commitment: u8,
+                                       rebroadcast_interval_blocks: u64) -> ResultExport<Handle> {
+    export_result(|| {
+        let signature = handle_table::get::<Signature>(signature)?;
+        let commitment = Commitment::try_from(commitment)?;
+        Ok(handle_table::insert(ConfirmationTracker::new(signature, last_valid_block_height, commitment,
+                                                         rebroadcast_interval_blocks)))
+    })
+}
+
+/// `getSignatureStatuses` request for the tracked signature alone, to be fed back with
+/// index 0.
+#[no_mangle]
+extern "C" fn confirmation_tracker_status_request(tracker: Handle, id: u64) -> ResultExport<*mut c_char> {
+    export_result(|| {
+        let signature = handle_table::with(tracker, |tracker: &mut ConfirmationTracker| tracker.signature)?;
+        request_body(id, "getSignatureStatuses", json!([[signature.to_string()]]))
+    })
+}
+
+/// Feeds a `getSignatureStatuses` response; `index` is the position of the tracked
+/// signature in the request.
+#[no_mangle]
+extern "C" fn confirmation_tracker_on_signature_statuses(tracker: Handle, response: *const c_char,
+                                                         index: u32) -> ResultExport<ConfirmationState> {
+    export_result(|| {
+        let statuses: WithContext<Vec<Option<RpcSignatureStatus>>> = parse_response(str_from_c_char_ptr(response)?)?;
+        let status = statuses.value.get(index as usize)
+            .ok_or_else(|| ExportError::new(ErrorCode::InvalidArgument,
+                format!("Response has {} statuses, no index {}", statuses.value.len(), index)))?;
+        handle_table::with(tracker, |tracker: &mut ConfirmationTracker| tracker.on_status(status.as_ref()))?
+    })
+}
+
+/// Feeds a `getBlockHeight` response.
+#[no_mangle]
+extern "C" fn confirmation_tracker_on_block_height(tracker: Handle, response: *const c_char) -> ResultExport<ConfirmationState> {
+    export_result(|| {
+        let block_height: u64 = parse_response(str_from_c_char_ptr(response)?)?;
+        handle_table::with(tracker, |tracker: &mut ConfirmationTracker| tracker.on_block_height(block_height))
+    })
+}
+
+/// Call after each send of the transaction, including the first.
+#[no_mangle]
+extern "C" fn confirmation_tracker_on_broadcast(tracker: Handle) -> ResultExport<u8> {
+    export_result(|| {
+        handle_table::with(tracker, |tracker: &mut ConfirmationTracker| tracker.on_broadcast())?;
+        Ok(true as u8)
+    })
+}
+
+#[no_mangle]
+extern "C" fn confirmation_tracker_state(tracker: Handle) -> ResultExport<ConfirmationState> {
+    export_result(|| handle_table::with(tracker, |tracker: &mut ConfirmationTracker| tracker.state()))
+}
+
+/// True once the state is final or has reached the tracked commitment.
+#[no_mangle]
+extern "C" fn confirmation_tracker_is_done(tracker: Handle) -> ResultExport<u8> {
+    export_result(|| handle_table::with(tracker, |tracker: &mut ConfirmationTracker| tracker.is_done() as u8))
+}
+
+#[no_mangle]
+extern "C" fn confirmation_tracker_should_rebroadcast(tracker: Handle) -> ResultExport<u8> {
+    export_result(|| handle_table::with(tracker, |tracker: &mut ConfirmationTracker| tracker.should_rebroadcast() as u8))
+}
+
+/// Error of a `Failed` transaction.
+#[no_mangle]
+extern "C" fn confirmation_tracker_error(tracker: Handle) -> ResultExport<OptionExport<TransactionErrorExport>> {
+    export_result(|| {
+        let err = handle_table::with(tracker, |tracker: &mut ConfirmationTracker| tracker.err().cloned())?;
+        transaction_error_export(err.as_ref())
+    })
+}
+
+#[no_mangle]
+extern "C" fn free_confirmation_tracker(tracker: Handle) -> ResultExport<u8> {
+    export_result(|| {
+        handle_table::remove::<ConfirmationTracker>(tracker)?;
+        Ok(true as u8)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CString;
+
+    use solana_sdk::instruction::InstructionError;
+
+    use super::*;
+
+    const LAST_VALID_BLOCK_HEIGHT: u64 = 100;
+
+    fn tracker(commitment: Commitment) -> ConfirmationTracker {
+        ConfirmationTracker::new(Signature::new_unique(), LAST_VALID_BLOCK_HEIGHT, commitment, 0)
+    }
+
+    fn status(confirmation_status: &str) -> RpcSignatureStatus {
+        RpcSignatureStatus {
+            slot: 1,
+            confirmations: None,
+            err: None,
+            confirmation_status: Some(confirmation_status.to_string()),
+        }
+    }
+
+    fn failed(err: TransactionError) -> RpcSignatureStatus {
+        RpcSignatureStatus { err: Some(err), ..status("processed") }
+    }
+
+    #[test]
+    fn states_only_move_forward() {
+        let mut tracker = tracker(Commitment::Finalized);
+        assert_eq!(tracker.on_status(None).unwrap(), ConfirmationState::Pending);
+        assert_eq!(tracker.on_status(Some(&status("confirmed"))).unwrap(), ConfirmationState::Confirmed);
+        assert_eq!(tracker.on_status(Some(&status("processed"))).unwrap(), ConfirmationState::Confirmed);
+        assert!(!tracker.is_done());
+        assert_eq!(tracker.on_status(Some(&status("finalized"))).unwrap(), ConfirmationState::Finalized);
+        assert!(tracker.is_done());
+
+        // Final states stay put, whatever is fed afterwards.
+        assert_eq!(tracker.on_status(Some(&status("processed"))).unwrap(), ConfirmationState::Finalized);
+        assert_eq!(tracker.on_status(None).unwrap(), ConfirmationState::Finalized);
+        assert_eq!(tracker.on_block_height(LAST_VALID_BLOCK_HEIGHT + 1), ConfirmationState::Finalized);
+        assert_eq!(tracker.on_status(Some(&failed(TransactionError::AccountInUse))).unwrap(), ConfirmationState::Finalized);
+        assert!(tracker.err().is_none());
+    }
+
+    #[test]
+    fn done_at_the_tracked_commitment() {
+        let mut tracker = tracker(Commitment::Confirmed);
+        tracker.on_status(Some(&status("processed"))).unwrap();
+        assert!(!tracker.is_done());
+        tracker.on_status(Some(&status("confirmed"))).unwrap();
+        assert!(tracker.is_done());
+
+        let mut tracker = self::tracker(Commitment::Default);
+        tracker.on_status(Some(&status("confirmed"))).unwrap();
+        assert!(!tracker.is_done());
+    }
+
+    #[test]
+    fn unseen_transaction_expires_after_last_valid_block_height() {
+        let mut tracker = tracker(Commitment::Confirmed);
+        tracker.on_status(None).unwrap();
+        assert_eq!(tracker.on_block_height(LAST_VALID_BLOCK_HEIGHT), ConfirmationState::Pending);
+        assert_eq!(tracker.on_block_height(LAST_VALID_BLOCK_HEIGHT + 1), ConfirmationState::Expired);
+        assert!(tracker.is_done());
+        // Heights never move backwards, a lagging node does not revive the transaction.
+        assert_eq!(tracker.on_block_height(LAST_VALID_BLOCK_HEIGHT - 5), ConfirmationState::Expired);
+
+        // It landed in time after all, which a later status shows.
+        assert_eq!(tracker.on_status(Some(&status("confirmed"))).unwrap(), ConfirmationState::Confirmed);
+    }
+
+    #[test]
+    fn reported_transaction_does_not_expire() {
+        let mut tracker = tracker(Commitment::Finalized);
+        tracker.on_status(Some(&status("processed"))).unwrap();
+        assert_eq!(tracker.on_block_height(LAST_VALID_BLOCK_HEIGHT + 10), ConfirmationState::Processed);
+        assert_eq!(tracker.on_status(Some(&status("confirmed"))).unwrap(), ConfirmationState::Confirmed);
+        assert_eq!(tracker.on_block_height(LAST_VALID_BLOCK_HEIGHT + 20), ConfirmationState::Confirmed);
+
+        // Reaching the tracked commitment is final for the caller even once no longer reported.
+        let mut tracker = self::tracker(Commitment::Processed);
+        tracker.on_status(Some(&status("processed"))).unwrap();
+        tracker.on_status(None).unwrap();
+        assert_eq!(tracker.on_block_height(LAST_VALID_BLOCK_HEIGHT + 1), ConfirmationState::Processed);
+        assert!(tracker.is_done());
+    }
+
+    #[test]
+    fn transaction_of_dropped_fork_expires() {
+        // Processed on a fork that was dropped: the node forgets the signature and the
+        // blockhash runs out before the transaction lands again.
+        let mut tracker = tracker(Commitment::Confirmed);
+        tracker.on_broadcast();
+        tracker.on_status(Some(&status("processed"))).unwrap();
+        assert_eq!(tracker.on_block_height(LAST_VALID_BLOCK_HEIGHT - 20), ConfirmationState::Processed);
+        assert!(!tracker.should_rebroadcast());
+
+        assert_eq!(tracker.on_status(None).unwrap(), ConfirmationState::Processed);
+        assert!(!tracker.should_rebroadcast());
+        tracker.on_block_height(LAST_VALID_BLOCK_HEIGHT - 20 + DEFAULT_REBROADCAST_INTERVAL_BLOCKS);
+        assert!(tracker.should_rebroadcast());
+        assert_eq!(tracker.on_block_height(LAST_VALID_BLOCK_HEIGHT + 1), ConfirmationState::Expired);
+        assert!(tracker.is_done());
+        assert!(!tracker.should_rebroadcast());
+
+        // Same when the height passes first and the status drops afterwards.
+        let mut tracker = self::tracker(Commitment::Confirmed);
+        tracker.on_status(Some(&status("processed"))).unwrap();
+        assert_eq!(tracker.on_block_height(LAST_VALID_BLOCK_HEIGHT + 1), ConfirmationState::Processed);
+        assert_eq!(tracker.on_status(None).unwrap(), ConfirmationState::Expired);
+    }
+
+    #[test]
+    fn failed_transaction_keeps_its_error() {
+        let err = TransactionError::InstructionError(1, InstructionError::Custom(6001));
+        let mut tracker = tracker(Commitment::Finalized);
+        tracker.on_status(Some(&status("processed"))).unwrap();
+        assert_eq!(tracker.on_status(Some(&failed(err.clone()))).unwrap(), ConfirmationState::Failed);
+        assert!(tracker.is_done());
+        assert_eq!(tracker.err(), Some(&err));
+
+        assert_eq!(tracker.on_status(Some(&status("finalized"))).unwrap(), ConfirmationState::Failed);
+        assert_eq!(tracker.on_status(None).unwrap(), ConfirmationState::Failed);
+        assert_eq!(tracker.on_block_height(LAST_VALID_BLOCK_HEIGHT + 1), ConfirmationState::Failed);
+        assert_eq!(tracker.err(), Some(&err));
+        assert!(!tracker.should_rebroadcast());
+
+        let handle = handle_table::insert(tracker);
+        let result = confirmation_tracker_error(handle);
+        assert_eq!(result.is_error, 0);
+        let export = result.result;
+        assert_eq!(export.has_value, 1);
+        assert_eq!((export.value.instruction_index.has_value, export.value.instruction_index.value), (1, 1));
+        assert_eq!((export.value.custom_code.has_value, export.value.custom_code.value), (1, 6001));
+        drop(unsafe { CString::from_raw(export.value.message) });
+        free_confirmation_tracker(handle);
+    }
+
+    #[test]
+    fn rebroadcast_follows_the_interval() {
+        let mut tracker = ConfirmationTracker::new(Signature::new_unique(), LAST_VALID_BLOCK_HEIGHT, Commitment::Confirmed, 5);
+        assert!(tracker.should_rebroadcast());
+        tracker.on_broadcast();
+        // Sent before any height was known, so the next height dates the send.
+        assert!(!tracker.should_rebroadcast());
+        tracker.on_block_height(50);
+        assert!(!tracker.should_rebroadcast());
+        tracker.on_block_height(54);
+        assert!(!tracker.should_rebroadcast());
+        tracker.on_block_height(55);
+        assert!(tracker.should_rebroadcast());
+        tracker.on_broadcast();
+        assert!(!tracker.should_rebroadcast());
+        tracker.on_block_height(60);
+        assert!(tracker.should_rebroadcast());
+
+        // Not while the node reports it.
+        tracker.on_status(Some(&status("processed"))).unwrap();
+        assert!(!tracker.should_rebroadcast());
+        tracker.on_status(None).unwrap();
+        assert!(tracker.should_rebroadcast());
+
+        // Never once the blockhash is no longer valid.
+        tracker.on_block_height(LAST_VALID_BLOCK_HEIGHT + 1);
+        assert!(!tracker.should_rebroadcast());
+    }
+
+    #[test]
+    fn zero_interval_uses_the_default() {
+        let mut tracker = tracker(Commitment::Confirmed);
+        tracker.on_block_height(10);
+        tracker.on_broadcast();
+        tracker.on_block_height(10 + DEFAULT_REBROADCAST_INTERVAL_BLOCKS - 1);
+        assert!(!tracker.should_rebroadcast());
+        tracker.on_block_height(10 + DEFAULT_REBROADCAST_INTERVAL_BLOCKS);
+        assert!(tracker.should_rebroadcast());
+    }
+}
@@ -0,0 +1,378 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::error::Error;
+use std::ffi::{CString, NulError};
+use std::fmt;
+use std::io;
+use std::os::raw::c_char;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr::null_mut;
+use std::str::Utf8Error;
+
+use bip39::ErrorKind;
+use solana_program::hash::ParseHashError;
+use solana_program::message::CompileError;
+use solana_program::program_error::ProgramError;
+use solana_program::pubkey::{ParsePubkeyError, PubkeyError};
+use solana_program::sanitize::SanitizeError;
+use solana_sdk::signature::ParseSignatureError;
+use solana_sdk::signer::SignerError;
+
+use crate::common_types::{ExportDefault, ResultExport};
+
+/// Stable numeric error codes returned in `ResultExport::error_code`.
+///
+/// Values are part of the C ABI: never renumber a variant, only append new ones.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorCode {
+    Ok = 0,
+    Unknown = 1,
+    /// A panic was caught before it could unwind into the caller.
+    Panic = 2,
+    NullPointer = 3,
+    InvalidUtf8 = 4,
+    /// A string handed back to the caller contained an interior NUL byte.
+    InteriorNul = 5,
+    /// A buffer or decoded string has the wrong number of bytes.
+    InvalidLength = 6,
+    /// An argument is outside of the accepted set of values, e.g. an unknown language.
+    InvalidArgument = 7,
+    InvalidBase58 = 8,
+    /// The bytes do not form a valid ed25519 keypair.
+    InvalidKeypair = 9,
+    MaxSeedLengthExceeded = 10,
+    /// The seeds did not produce a valid off-curve program derived address.
+    PdaOffCurve = 11,
+    IllegalOwner = 12,
+    /// No bump seed produced a valid program derived address.
+    NoViableBumpSeed = 13,
+    InvalidMnemonicChecksum = 14,
+    InvalidMnemonicWord = 15,
+    InvalidMnemonicWordCount = 16,
+    InvalidMnemonicKeysize = 17,
+    InvalidEntropyLength = 18,
+    /// The handle was never issued by this library.
+    InvalidHandle = 19,
+    /// The handle refers to an object of another type.
+    WrongHandleType = 20,
+    /// The handle's slot has since been reused for another object.
+    StaleHandle = 21,
+    /// The handle was already freed.
+    FreedHandle = 22,
+    /// Encoding or decoding the binary wire format failed.
+    Serialization = 23,
+    /// A keypair does not belong to any of the required signers.
+    KeypairPubkeyMismatch = 24,
+    /// Some required signers have not signed yet.
+    MissingSigners = 25,
+    SigningFailed = 26,
+    /// A signature does not verify against the message it is attached to.
+    SignatureVerificationFailed = 27,
+    /// The transaction or message is structurally invalid.
+    InvalidTransaction = 28,
+    InvalidBase64 = 29,
+    /// Instructions could not be compiled into a message.
+    MessageCompile = 30,
+    /// An address does not match the one derived from its base, seed and owner.
+    SeedAddressMismatch = 31,
+    /// An on-chain program rejected its arguments while building an instruction.
+    ProgramError = 32,
+    /// A borsh value does not have the shape its schema describes.
+    BorshSchemaMismatch = 33,
+    InvalidJson = 34,
+    /// An Anchor IDL is malformed or uses types this library cannot encode.
+    InvalidIdl = 35,
+    /// Account data does not start with the expected Anchor discriminator.
+    DiscriminatorMismatch = 36,
+    /// A derivation path is malformed or uses non-hardened indexes.
+    InvalidDerivationPath = 37,
+    /// A keystore could not be decrypted with the given password.
+    WrongPassword = 38,
+    /// A keystore is malformed or uses an unsupported version or algorithm.
+    InvalidKeystore = 39,
+    /// The node answered a JSON-RPC request with an error object.
+    RpcError = 40,
+    /// The transport callback reported that a request could not be delivered.
+    TransportFailed = 41,
+    /// A sent transaction was processed but failed.
+    TransactionFailed = 42,
+    /// The blockhash of a sent transaction expired before it was confirmed.
+    BlockhashExpired = 43,
+}
+
+/// Error produced inside an export, converted into a `ResultExport` before it
+/// crosses the FFI boundary.
+#[derive(Clone, Debug)]
+pub struct ExportError {
+    code: ErrorCode,
+    message: String,
+}
+
+impl ExportError {
+    pub fn new<S: Into<String>>(code: ErrorCode, message: S) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    pub fn null_pointer(name: &str) -> Self {
+        Self::new(ErrorCode::NullPointer, format!("{} is a null pointer", name))
+    }
+
+    pub fn invalid_length(name: &str, expected: usize, actual: usize) -> Self {
+        Self::new(
+            ErrorCode::InvalidLength,
+            format!("{} has length {}, expected {}", name, actual, expected),
+        )
+    }
+
+    pub fn invalid_argument<S: Into<String>>(message: S) -> Self {
+        Self::new(ErrorCode::InvalidArgument, message)
+    }
+
+    fn from_panic(payload: Box<dyn Any + Send>) -> Self {
+        let message = if let Some(message) = payload.downcast_ref::<&str>() {
+            message.to_string()
+        } else if let Some(message) = payload.downcast_ref::<String>() {
+            message.clone()
+        } else {
+            "unknown panic payload".to_string()
+        };
+        Self::new(ErrorCode::Panic, format!("panic in native code: {}", message))
+    }
+
+    pub fn code(&self) -> ErrorCode {
+        self.code
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Hands the message to the caller as a C string, to be released with `free_cstring`.
+    pub fn into_c_string(self) -> *mut c_char {
+        CString::new(self.message.replace('\0', ""))
+            .unwrap_or_default()
+            .into_raw()
+    }
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl From<Utf8Error> for ExportError {
+    fn from(error: Utf8Error) -> Self {
+        Self::new(ErrorCode::InvalidUtf8, error.to_string())
+    }
+}
+
+impl From<NulError> for ExportError {
+    fn from(error: NulError) -> Self {
+        Self::new(ErrorCode::InteriorNul, error.to_string())
+    }
+}
+
+impl From<ParsePubkeyError> for ExportError {
+    fn from(error: ParsePubkeyError) -> Self {
+        let code = match error {
+            ParsePubkeyError::WrongSize => ErrorCode::InvalidLength,
+            ParsePubkeyError::Invalid => ErrorCode::InvalidBase58,
+        };
+        Self::new(code, error.to_string())
+    }
+}
+
+impl From<PubkeyError> for ExportError {
+    fn from(error: PubkeyError) -> Self {
+        let code = match error {
+            PubkeyError::MaxSeedLengthExceeded => ErrorCode::MaxSeedLengthExceeded,
+            PubkeyError::InvalidSeeds => ErrorCode::PdaOffCurve,
+            PubkeyError::IllegalOwner => ErrorCode::IllegalOwner,
+        };
+        Self::new(code, error.to_string())
+    }
+}
+
+impl From<ParseHashError> for ExportError {
+    fn from(error: ParseHashError) -> Self {
+        let code = match error {
+            ParseHashError::WrongSize => ErrorCode::InvalidLength,
+            ParseHashError::Invalid => ErrorCode::InvalidBase58,
+        };
+        Self::new(code, error.to_string())
+    }
+}
+
+impl From<ParseSignatureError> for ExportError {
+    fn from(error: ParseSignatureError) -> Self {
+        let code = match error {
+            ParseSignatureError::WrongSize => ErrorCode::InvalidLength,
+            ParseSignatureError::Invalid => ErrorCode::InvalidBase58,
+        };
+        Self::new(code, error.to_string())
+    }
+}
+
+impl From<&ErrorKind> for ErrorCode {
+    fn from(kind: &ErrorKind) -> Self {
+        match kind {
+            ErrorKind::InvalidChecksum => ErrorCode::InvalidMnemonicChecksum,
+            ErrorKind::InvalidWord => ErrorCode::InvalidMnemonicWord,
+            ErrorKind::InvalidKeysize(_) => ErrorCode::InvalidMnemonicKeysize,
+            ErrorKind::InvalidWordLength(_) => ErrorCode::InvalidMnemonicWordCount,
+            ErrorKind::InvalidEntropyLength(_, _) => ErrorCode::InvalidEntropyLength,
+        }
+    }
+}
+
+impl From<SignerError> for ExportError {
+    fn from(error: SignerError) -> Self {
+        let code = match error {
+            SignerError::KeypairPubkeyMismatch => ErrorCode::KeypairPubkeyMismatch,
+            SignerError::NotEnoughSigners => ErrorCode::MissingSigners,
+            _ => ErrorCode::SigningFailed,
+        };
+        Self::new(code, error.to_string())
+    }
+}
+
+impl From<CompileError> for ExportError {
+    fn from(error: CompileError) -> Self {
+        Self::new(ErrorCode::MessageCompile, error.to_string())
+    }
+}
+
+impl From<ProgramError> for ExportError {
+    fn from(error: ProgramError) -> Self {
+        Self::new(ErrorCode::ProgramError, error.to_string())
+    }
+}
+
+impl From<SanitizeError> for ExportError {
+    fn from(error: SanitizeError) -> Self {
+        Self::new(ErrorCode::InvalidTransaction, error.to_string())
+    }
+}
+
+impl From<io::Error> for ExportError {
+    fn from(error: io::Error) -> Self {
+        Self::new(ErrorCode::Serialization, error.to_string())
+    }
+}
+
+impl From<serde_json::Error> for ExportError {
+    fn from(error: serde_json::Error) -> Self {
+        Self::new(ErrorCode::InvalidJson, error.to_string())
+    }
+}
+
+impl From<bincode::Error> for ExportError {
+    fn from(error: bincode::Error) -> Self {
+        Self::new(ErrorCode::Serialization, error.to_string())
+    }
+}
+
+/// bip39 reports its `ErrorKind` wrapped in an `anyhow::Error`.
+impl From<anyhow::Error> for ExportError {
+    fn from(error: anyhow::Error) -> Self {
+        let code = error
+            .downcast_ref::<ErrorKind>()
+            .map_or(ErrorCode::Unknown, ErrorCode::from);
+        Self::new(code, error.to_string())
+    }
+}
+
+impl From<Box<dyn Error>> for ExportError {
+    fn from(error: Box<dyn Error>) -> Self {
+        let code = error
+            .downcast_ref::<ErrorKind>()
+            .map_or(ErrorCode::Unknown, ErrorCode::from);
+        Self::new(code, error.to_string())
+    }
+}
+
+thread_local! {
+    /// Last error raised by an export on this thread, kept until the next failure
+    /// or until `solana_ffi_clear_error` is called.
+    static LAST_ERROR: RefCell<Option<ExportError>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(error: &ExportError) {
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = Some(error.clone()));
+}
+
+/// Runs the body of an export, catching any panic so it never unwinds into the
+/// managed runtime.
+pub fn export_result<T, F>(f: F) -> ResultExport<T>
+where
+    T: ExportDefault,
+    F: FnOnce() -> Result<T, ExportError>,
+{
+    let result = panic::catch_unwind(AssertUnwindSafe(f))
+        .unwrap_or_else(|payload| Err(ExportError::from_panic(payload)));
+
+    match result {
+        Ok(result) => ResultExport {
+            is_error: 0,
+            result,
+            error: null_mut(),
+            error_code: ErrorCode::Ok,
+        },
+        Err(error) => {
+            set_last_error(&error);
+            ResultExport {
+                is_error: 1,
+                result: T::export_default(),
+                error_code: error.code,
+                error: error.into_c_string(),
+            }
+        }
+    }
+}
+
+/// Same as `export_result` for exports without a return value, such as the
+/// `free_*` functions; errors are only reported through the last error.
+pub fn export_guard<F>(f: F)
+where
+    F: FnOnce() -> Result<(), ExportError>,
+{
+    let result = panic::catch_unwind(AssertUnwindSafe(f))
+        .unwrap_or_else(|payload| Err(ExportError::from_panic(payload)));
+
+    if let Err(error) = result {
+        set_last_error(&error);
+    }
+}
+
+/// Code of the last error raised on the calling thread, `Ok` if there is none.
+#[no_mangle]
+extern "C" fn solana_ffi_last_error_code() -> ErrorCode {
+    LAST_ERROR.with(|last_error| {
+        last_error
+            .borrow()
+            .as_ref()
+            .map_or(ErrorCode::Ok, ExportError::code)
+    })
+}
+
+/// Message of the last error raised on the calling thread, or null if there is none.
+/// The returned string must be released with `free_cstring`.
+#[no_mangle]
+extern "C" fn solana_ffi_last_error_message() -> *mut c_char {
+    LAST_ERROR.with(|last_error| {
+        last_error
+            .borrow()
+            .clone()
+            .map_or(null_mut(), ExportError::into_c_string)
+    })
+}
+
+#[no_mangle]
+extern "C" fn solana_ffi_clear_error() {
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = None);
+}
@@ -0,0 +1,331 @@
+use std::any::Any;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use crate::common_types::{ExportDefault, ResultExport};
+use crate::error_export::{ErrorCode, export_result, ExportError};
+
+/// Opaque reference to an object owned by the library.
+///
+/// Layout, from the most significant bits: 8 bit `HandleType` tag, 24 bit
+/// generation, 32 bit slot index. Zero is never issued.
+pub type Handle = u64;
+
+const INDEX_BITS: u32 = 32;
+const GENERATION_BITS: u32 = 24;
+const GENERATION_MASK: u64 = (1 << GENERATION_BITS) - 1;
+
+/// Type tag stored in every handle. Values are part of the C ABI.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HandleType {
+    Invalid = 0,
+    Keypair = 1,
+    Pubkey = 2,
+    Hash = 3,
+    Signature = 4,
+    Mnemonic = 5,
+    Seed = 6,
+    Instruction = 7,
+    Message = 8,
+    Transaction = 9,
+    VersionedTransaction = 10,
+    VersionedMessage = 11,
+    AddressLookupTableAccount = 12,
+    BorshSchema = 13,
+    BorshValue = 14,
+    AnchorIdl = 15,
+    RpcClient = 16,
+    ConfirmationTracker = 17,
+    BlockhashCache = 18,
+}
+
+impl ExportDefault for HandleType {
+    fn export_default() -> Self {
+        HandleType::Invalid
+    }
+}
+
+/// Implemented by every type that can be handed to the caller as a `Handle`.
+pub trait HandleObject: Any + Send {
+    const HANDLE_TYPE: HandleType;
+}
+
+type SharedObject = Arc<Mutex<Box<dyn Any + Send>>>;
+
+struct Slot {
+    generation: u32,
+    handle_type: HandleType,
+    object: Option<SharedObject>,
+}
+
+struct HandleTable {
+    slots: Vec<Slot>,
+    free_slots: Vec<u32>,
+}
+
+static HANDLE_TABLE: Mutex<HandleTable> = Mutex::new(HandleTable {
+    slots: Vec::new(),
+    free_slots: Vec::new(),
+});
+
+fn lock<T: ?Sized>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    // A panic inside an export is caught at the boundary, the data itself stays consistent.
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn make_handle(handle_type: HandleType, generation: u32, index: u32) -> Handle {
+    ((handle_type as u64) << (INDEX_BITS + GENERATION_BITS))
+        | ((generation as u64 & GENERATION_MASK) << INDEX_BITS)
+        | index as u64
+}
+
+fn split_handle(handle: Handle) -> (u8, u32, u32) {
+    (
+        (handle >> (INDEX_BITS + GENERATION_BITS)) as u8,
+        ((handle >> INDEX_BITS) & GENERATION_MASK) as u32,
+        handle as u32,
+    )
+}
+
+fn next_generation(generation: u32) -> u32 {
+    match (generation + 1) & GENERATION_MASK as u32 {
+        0 => 1,
+        generation => generation,
+    }
+}
+
+impl HandleTable {
+    fn insert(&mut self, handle_type: HandleType, object: SharedObject) -> Handle {
+        let index = match self.free_slots.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index as usize];
+                slot.generation = next_generation(slot.generation);
+                slot.handle_type = handle_type;
+                slot.object = Some(object);
+                index
+            }
+            None => {
+                self.slots.push(Slot {
+                    generation: 1,
+                    handle_type,
+                    object: Some(object),
+                });
+                (self.slots.len() - 1) as u32
+            }
+        };
+        make_handle(handle_type, self.slots[index as usize].generation, index)
+    }
+
+    fn slot(&self, handle: Handle, expected: HandleType) -> Result<&Slot, ExportError> {
+        let (tag, generation, index) = split_handle(handle);
+        let slot = match self.slots.get(index as usize) {
+            Some(slot) if handle != 0 => slot,
+            _ => return Err(ExportError::new(ErrorCode::InvalidHandle, format!("Invalid handle {:#x}", handle))),
+        };
+        if tag != expected as u8 {
+            return Err(ExportError::new(
+                ErrorCode::WrongHandleType,
+                format!("Handle {:#x} is not a {:?} handle", handle, expected),
+            ));
+        }
+        if slot.generation != generation {
+            return Err(ExportError::new(ErrorCode::StaleHandle, format!("Handle {:#x} is stale", handle)));
+        }
+        if slot.object.is_none() {
+            return Err(ExportError::new(ErrorCode::FreedHandle, format!("Handle {:#x} was already freed", handle)));
+        }
+        Ok(slot)
+    }
+
+    fn get(&self, handle: Handle, expected: HandleType) -> Result<SharedObject, ExportError> {
+        let slot = self.slot(handle, expected)?;
+        Ok(slot.object.clone().expect("slot() only returns occupied slots"))
+    }
+
+    fn remove(&mut self, handle: Handle, expected: HandleType) -> Result<SharedObject, ExportError> {
+        self.slot(handle, expected)?;
+        let index = split_handle(handle).2;
+        let object = self.slots[index as usize].object.take();
+        self.free_slots.push(index);
+        Ok(object.expect("slot() only returns occupied slots"))
+    }
+
+    fn handle_type(&self, handle: Handle) -> Result<HandleType, ExportError> {
+        let (tag, _, index) = split_handle(handle);
+        let handle_type = self
+            .slots
+            .get(index as usize)
+            .map_or(HandleType::Invalid, |slot| slot.handle_type);
+        if handle_type as u8 != tag {
+            return Err(ExportError::new(ErrorCode::InvalidHandle, format!("Invalid handle {:#x}", handle)));
+        }
+        self.slot(handle, handle_type)?;
+        Ok(handle_type)
+    }
+}
+
+/// Moves `object` into the table and returns its handle.
+pub fn insert<T: HandleObject>(object: T) -> Handle {
+    let object: SharedObject = Arc::new(Mutex::new(Box::new(object)));
+    lock(&HANDLE_TABLE).insert(T::HANDLE_TYPE, object)
+}
+
+/// Runs `f` on the object behind `handle`.
+///
+/// The table itself is not locked while `f` runs, so `f` may resolve other handles.
+pub fn with<T: HandleObject, R>(handle: Handle, f: impl FnOnce(&mut T) -> R) -> Result<R, ExportError> {
+    let shared = lock(&HANDLE_TABLE).get(handle, T::HANDLE_TYPE)?;
+    let mut guard = lock(&*shared);
+    let object = guard
+        .downcast_mut::<T>()
+        .expect("handle type tag matches the stored object");
+    Ok(f(object))
+}
+
+/// Copies the object behind `handle` out of the table.
+pub fn get<T: HandleObject + Clone>(handle: Handle) -> Result<T, ExportError> {
+    with(handle, |object: &mut T| object.clone())
+}
+
+/// Invalidates `handle` and drops its object once no export is using it anymore.
+pub fn remove<T: HandleObject>(handle: Handle) -> Result<(), ExportError> {
+    let object = lock(&HANDLE_TABLE).remove(handle, T::HANDLE_TYPE)?;
+    drop(object);
+    Ok(())
+}
+
+/// Moves `objects` into the table and writes their handles to the caller's `out` array,
+/// which must have room for all of them. Returns the number of handles written.
+pub fn insert_into_ptr<T: HandleObject>(objects: Vec<T>, out: *mut Handle, out_len: usize, name: &str) -> Result<u32, ExportError> {
+    if out.is_null() {
+        return Err(ExportError::null_pointer(name));
+    }
+    if out_len < objects.len() {
+        return Err(ExportError::invalid_length(name, objects.len(), out_len));
+    }
+    let out = unsafe { std::slice::from_raw_parts_mut(out, objects.len()) };
+    for (slot, object) in out.iter_mut().zip(objects) {
+        *slot = insert(object);
+    }
+    Ok(out.len() as u32)
+}
+
+/// Type of the object behind `handle`, for exports that accept several handle types.
+pub fn type_of(handle: Handle) -> Result<HandleType, ExportError> {
+    lock(&HANDLE_TABLE).handle_type(handle)
+}
+
+#[no_mangle]
+extern "C" fn handle_type(handle: Handle) -> ResultExport<HandleType> {
+    export_result(|| type_of(handle))
+}
+
+#[no_mangle]
+extern "C" fn handle_is_valid(handle: Handle) -> ResultExport<u8> {
+    export_result(|| Ok(type_of(handle).is_ok() as u8))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use solana_program::hash::Hash;
+    use solana_program::pubkey::Pubkey;
+
+    use super::*;
+
+    fn empty_table() -> HandleTable {
+        HandleTable {
+            slots: Vec::new(),
+            free_slots: Vec::new(),
+        }
+    }
+
+    fn shared<T: Any + Send>(object: T) -> SharedObject {
+        Arc::new(Mutex::new(Box::new(object)))
+    }
+
+    fn error_code<T>(result: Result<T, ExportError>) -> ErrorCode {
+        result.err().expect("expected an error").code()
+    }
+
+    struct DropCounter(Arc<AtomicUsize>);
+
+    impl Drop for DropCounter {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn issued_handle_resolves_to_its_object() {
+        let pubkey = Pubkey::new_unique();
+        let handle = insert(pubkey);
+        assert_eq!(get::<Pubkey>(handle).unwrap(), pubkey);
+        assert_eq!(type_of(handle).unwrap(), HandleType::Pubkey);
+        assert_eq!(handle_is_valid(handle).result, 1);
+        remove::<Pubkey>(handle).unwrap();
+    }
+
+    #[test]
+    fn freed_handle_is_rejected() {
+        let mut table = empty_table();
+        let handle = table.insert(HandleType::Pubkey, shared(Pubkey::new_unique()));
+        table.remove(handle, HandleType::Pubkey).unwrap();
+        assert_eq!(error_code(table.get(handle, HandleType::Pubkey)), ErrorCode::FreedHandle);
+        assert_eq!(error_code(table.handle_type(handle)), ErrorCode::FreedHandle);
+
+        // In the shared table the slot may be reused by another test meanwhile, which
+        // makes the handle stale instead of freed; either way it no longer resolves.
+        let handle = insert(Pubkey::new_unique());
+        remove::<Pubkey>(handle).unwrap();
+        assert!(get::<Pubkey>(handle).is_err());
+        assert_eq!(handle_is_valid(handle).result, 0);
+    }
+
+    #[test]
+    fn wrong_type_is_rejected() {
+        let handle = insert(Pubkey::new_unique());
+        assert_eq!(error_code(get::<Hash>(handle)), ErrorCode::WrongHandleType);
+        assert_eq!(error_code(remove::<Hash>(handle)), ErrorCode::WrongHandleType);
+        // The failed remove left the object in place.
+        assert!(get::<Pubkey>(handle).is_ok());
+        remove::<Pubkey>(handle).unwrap();
+    }
+
+    #[test]
+    fn stale_handle_of_reused_slot_is_rejected() {
+        let mut table = empty_table();
+        let old = table.insert(HandleType::Pubkey, shared(Pubkey::new_unique()));
+        table.remove(old, HandleType::Pubkey).unwrap();
+        let new = table.insert(HandleType::Pubkey, shared(Pubkey::new_unique()));
+
+        assert_eq!(split_handle(old).2, split_handle(new).2);
+        assert_eq!(error_code(table.get(old, HandleType::Pubkey)), ErrorCode::StaleHandle);
+        assert!(table.get(new, HandleType::Pubkey).is_ok());
+    }
+
+    #[test]
+    fn invalid_handles_are_rejected() {
+        let table = empty_table();
+        assert_eq!(error_code(table.get(0, HandleType::Pubkey)), ErrorCode::InvalidHandle);
+        let never_issued = make_handle(HandleType::Pubkey, 1, 12345);
+        assert_eq!(error_code(table.get(never_issued, HandleType::Pubkey)), ErrorCode::InvalidHandle);
+        assert_eq!(error_code(table.handle_type(never_issued)), ErrorCode::InvalidHandle);
+    }
+
+    #[test]
+    fn double_remove_fails_and_drops_once() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let mut table = empty_table();
+        let handle = table.insert(HandleType::Pubkey, shared(DropCounter(drops.clone())));
+
+        drop(table.remove(handle, HandleType::Pubkey).unwrap());
+        assert_eq!(drops.load(Ordering::SeqCst), 1);
+        assert_eq!(error_code(table.remove(handle, HandleType::Pubkey)), ErrorCode::FreedHandle);
+        assert_eq!(drops.load(Ordering::SeqCst), 1);
+        // The slot was only queued for reuse once.
+        assert_eq!(table.free_slots, vec![split_handle(handle).2]);
+    }
+}
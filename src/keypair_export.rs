@@ -1,164 +1,524 @@
-use std::ffi::CString;
-use std::hash::Hash;
+use std::fmt;
 use std::os::raw::c_char;
-use std::ptr::null_mut;
 use bip39::Seed;
 
 use libc::size_t;
-use solana_sdk::pubkey::Pubkey;
-use solana_sdk::signature::{Keypair, Signature, Signer};
-use solana_sdk::signer::keypair::{keypair_from_seed, keypair_from_seed_phrase_and_passphrase};
+use serde::de::{self, Deserialize, Deserializer, SeqAccess, Visitor};
+use solana_sdk::derivation_path::DerivationPath;
+use solana_sdk::signature::{Keypair, Signer};
+use solana_sdk::signer::keypair::{keypair_from_seed, keypair_from_seed_and_derivation_path, keypair_from_seed_phrase_and_passphrase};
+use zeroize::Zeroizing;
 
-use crate::c_sharp_string::str_from_c_char_ptr;
-use crate::common_types::{ResultExport, to_raw_parts, VecRawParts};
+use crate::c_sharp_string::{c_char_ptr_from_string, str_from_c_char_ptr};
+use crate::common_types::{ResultExport, slice_from_ptr, to_raw_parts, VecRawParts};
+use crate::error_export::{ErrorCode, export_result, ExportError};
+use crate::handle_table::{self, Handle, HandleObject, HandleType};
 
-#[no_mangle]
-extern "C" fn new_keypair() -> *mut Keypair {
-    Box::into_raw(Box::new(Keypair::new()))
+/// Account path used by Phantom and Solflare, see `keypair_derive_accounts`.
+pub const SOLANA_ACCOUNT_PATH_TEMPLATE: &str = "m/44'/501'/{account}'/0'";
+const ACCOUNT_PLACEHOLDER: &str = "{account}";
+const HARDENED_OFFSET: u32 = 1 << 31;
+pub const SECRET_KEY_LENGTH: usize = 32;
+pub const KEYPAIR_LENGTH: usize = 64;
+
+impl HandleObject for Keypair {
+    const HANDLE_TYPE: HandleType = HandleType::Keypair;
 }
 
-#[no_mangle]
-extern "C" fn from_bytes(bytes: *const u8, len: size_t) -> ResultExport<*mut Keypair>{
+/// Parses an absolute path such as `m/44'/501'/0'/0'`. SLIP-0010 only defines hardened
+/// derivation for ed25519, so every index must be marked hardened with `'` or `h`.
+pub fn derivation_path_from_str(path: &str) -> Result<DerivationPath, ExportError> {
+    let invalid = |reason: String| ExportError::new(
+        ErrorCode::InvalidDerivationPath,
+        format!("Invalid derivation path {}: {}", path, reason),
+    );
+
+    let mut components = path.split('/');
+    if components.next() != Some("m") {
+        return Err(invalid("it must start with m".to_string()));
+    }
+    let mut normalized = String::from("m");
+    for component in components {
+        let index = component.strip_suffix('\'')
+            .or_else(|| component.strip_suffix('h'))
+            .ok_or_else(|| invalid(format!("index {} is not hardened", component)))?;
+        let index = Some(index)
+            .filter(|index| !index.is_empty() && index.chars().all(|c| c.is_ascii_digit()))
+            .and_then(|index| index.parse::<u32>().ok())
+            .filter(|index| *index < HARDENED_OFFSET)
+            .ok_or_else(|| invalid(format!("index {} is not a number below 2^31", component)))?;
+        normalized.push_str(&format!("/{}'", index));
+    }
 
-    let bytes = unsafe {
-        assert!(!bytes.is_null());
-        std::slice::from_raw_parts(bytes  , len as usize)
-    };
+    DerivationPath::from_absolute_path_str(&normalized).map_err(|error| invalid(error.to_string()))
+}
 
-    match Keypair::from_bytes(bytes){
-        Ok(keypair) => ResultExport{
-            is_error: 0,
-            result: Box::into_raw(Box::new(keypair)),
-            error: null_mut()
-        },
-        Err(error) => ResultExport{
-            is_error: 1,
-            result: null_mut(),
-            error: CString::new(error.to_string()).unwrap().into_raw()
-        }
+/// Same as `Keypair::from_bytes`, but also rejects bytes whose public half was not derived
+/// from the secret half, which `Keypair::from_bytes` accepts.
+pub fn keypair_from_bytes(bytes: &[u8]) -> Result<Keypair, ExportError> {
+    if bytes.len() != KEYPAIR_LENGTH {
+        return Err(ExportError::invalid_length("keypair", KEYPAIR_LENGTH, bytes.len()));
     }
+    let keypair = Keypair::from_bytes(bytes)
+        .map_err(|error| ExportError::new(ErrorCode::InvalidKeypair, error.to_string()))?;
+    let derived = keypair_from_seed(&bytes[..SECRET_KEY_LENGTH])
+        .map_err(|error| ExportError::new(ErrorCode::InvalidKeypair, error.to_string()))?;
+    if derived.pubkey() != keypair.pubkey() {
+        return Err(ExportError::new(ErrorCode::InvalidKeypair, "Public key does not match the secret key"));
+    }
+    Ok(keypair)
+}
 
+/// Same bytes as `Keypair::to_bytes`, written straight to the heap so no copy of the
+/// secret key is left on the stack.
+pub fn keypair_to_vec(keypair: &Keypair) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(KEYPAIR_LENGTH);
+    bytes.extend_from_slice(keypair.secret().as_bytes());
+    bytes.extend_from_slice(keypair.pubkey().as_ref());
+    bytes
+}
+
+fn keypair_from_seed_handle(seed: Handle, derivation_path: DerivationPath) -> Result<Keypair, ExportError> {
+    handle_table::with(seed, |seed: &mut Seed| {
+        keypair_from_seed_and_derivation_path(seed.as_bytes(), Some(derivation_path))
+    })?.map_err(|error| ExportError::new(ErrorCode::InvalidDerivationPath, error.to_string()))
+}
+
+#[no_mangle]
+extern "C" fn new_keypair() -> ResultExport<Handle> {
+    export_result(|| Ok(handle_table::insert(Keypair::new())))
 }
 
 #[no_mangle]
-extern "C" fn to_bytes(ptr : *mut Keypair)  -> VecRawParts {
-    let keypair = unsafe {
-        assert!(!ptr.is_null());
-        &mut *ptr
-    };
+extern "C" fn from_bytes(bytes: *const u8, len: size_t) -> ResultExport<Handle>{
+    export_result(|| {
+        let bytes = slice_from_ptr(bytes, len, "bytes")?;
+        Ok(handle_table::insert(keypair_from_bytes(bytes)?))
+    })
+}
 
-    to_raw_parts(keypair.to_bytes().to_vec())
+/// Release the returned buffer with `free_secret_vec`.
+#[no_mangle]
+extern "C" fn to_bytes(keypair: Handle)  -> ResultExport<VecRawParts> {
+    export_result(|| {
+        handle_table::with(keypair, |keypair: &mut Keypair| to_raw_parts(keypair_to_vec(keypair)))
+    })
+}
 
+#[no_mangle]
+extern "C" fn from_base58_string(bs58: *const c_char) -> ResultExport<Handle> {
+    export_result(|| {
+        // `Keypair::from_base58_string` panics on malformed input, so decode by hand.
+        let bytes = Zeroizing::new(bs58::decode(str_from_c_char_ptr(bs58)?)
+            .into_vec()
+            .map_err(|error| ExportError::new(ErrorCode::InvalidBase58, error.to_string()))?);
+        Ok(handle_table::insert(keypair_from_bytes(&bytes)?))
+    })
 }
 
+/// Release the returned string with `free_secret_cstring`.
 #[no_mangle]
-extern "C" fn from_base58_string(bs58: *const c_char) -> *mut Keypair {
-    Box::into_raw(Box::new(Keypair::from_base58_string(str_from_c_char_ptr(bs58).unwrap())))
+extern "C" fn to_base58_string(keypair: Handle) -> ResultExport<*mut c_char> {
+    export_result(|| {
+        let bytes = Zeroizing::new(handle_table::with(keypair, |keypair: &mut Keypair| keypair_to_vec(keypair))?);
+        let encoded = Zeroizing::new(bs58::encode(bytes.as_slice()).into_string());
+        c_char_ptr_from_string(encoded.as_str())
+    })
 }
 
+/// Release the returned buffer with `free_secret_vec`.
 #[no_mangle]
-extern "C" fn to_base58_string(ptr : *mut Keypair) -> *mut c_char {
-    let keypair = unsafe {
-        assert!(!ptr.is_null());
-        &mut *ptr
-    };
-    CString::new(keypair.to_base58_string()).unwrap().into_raw()
+extern "C" fn secret(keypair: Handle) -> ResultExport<VecRawParts> {
+    export_result(|| {
+        handle_table::with(keypair, |keypair: &mut Keypair| to_raw_parts(keypair.secret().as_bytes().to_vec()))
+    })
 }
 
 #[no_mangle]
-extern "C" fn secret(ptr : *mut Keypair) -> VecRawParts {
-    let keypair = unsafe {
-        assert!(!ptr.is_null());
-        &mut *ptr
-    };
+extern "C" fn pubkey(keypair: Handle) -> ResultExport<Handle> {
+    export_result(|| {
+        let pubkey = handle_table::with(keypair, |keypair: &mut Keypair| keypair.pubkey())?;
+        Ok(handle_table::insert(pubkey))
+    })
+}
 
-    to_raw_parts(keypair.secret().to_bytes().to_vec())
+#[no_mangle]
+extern "C" fn sign_message(keypair: Handle, message: *const u8, len: size_t) -> ResultExport<Handle> {
+    export_result(|| {
+        let message = slice_from_ptr(message, len, "message")?;
+        let signature = handle_table::with(keypair, |keypair: &mut Keypair| keypair.sign_message(message))?;
+        Ok(handle_table::insert(signature))
+    })
 }
 
 #[no_mangle]
-extern "C" fn pubkey(ptr : *mut Keypair) -> *mut Pubkey {
-    let keypair = unsafe {
-        assert!(!ptr.is_null());
-        &mut *ptr
-    };
-    Box::into_raw(Box::new(keypair.pubkey()))
+extern "C" fn is_interactive(keypair: Handle) -> ResultExport<u8> {
+    export_result(|| {
+        handle_table::with(keypair, |keypair: &mut Keypair| keypair.is_interactive() as u8)
+    })
 }
 
 #[no_mangle]
-extern "C" fn sign_message(ptr : *mut Keypair, message: *const u8, len: size_t) -> *mut Signature {
-    let keypair = unsafe {
-        assert!(!ptr.is_null());
-        &mut *ptr
-    };
+extern "C" fn  keypair_from_seed_entropy(seed: Handle) -> ResultExport<Handle> {
+    export_result(|| {
+        let keypair = handle_table::with(seed, |seed: &mut Seed| keypair_from_seed(seed.as_bytes()))??;
+        Ok(handle_table::insert(keypair))
+    })
+}
+
+/// JSON array of keypair bytes, read into a buffer allocated once so that no copy of the
+/// secret key is left behind by reallocation.
+struct KeypairJson(Zeroizing<Vec<u8>>);
+
+impl<'de> Deserialize<'de> for KeypairJson {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct KeypairJsonVisitor;
+
+        impl<'de> Visitor<'de> for KeypairJsonVisitor {
+            type Value = KeypairJson;
 
-    let message = unsafe {
-        assert!(!message.is_null());
-        std::slice::from_raw_parts(message  , len as usize)
-    };
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                write!(formatter, "an array of {} bytes", KEYPAIR_LENGTH)
+            }
 
-    Box::into_raw(Box::new(keypair.sign_message(message)))
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let mut bytes = Zeroizing::new(Vec::with_capacity(KEYPAIR_LENGTH));
+                while let Some(byte) = seq.next_element()? {
+                    if bytes.len() == KEYPAIR_LENGTH {
+                        return Err(de::Error::invalid_length(KEYPAIR_LENGTH + 1, &self));
+                    }
+                    bytes.push(byte);
+                }
+                Ok(KeypairJson(bytes))
+            }
+        }
 
+        deserializer.deserialize_seq(KeypairJsonVisitor)
+    }
 }
 
+/// Reads the JSON array of 64 bytes written by `solana-keygen`, e.g. `~/.config/solana/id.json`.
 #[no_mangle]
-extern "C" fn is_interactive(ptr : *mut Keypair) -> u8 {
-    let keypair = unsafe {
-        assert!(!ptr.is_null());
-        &mut *ptr
-    };
+extern "C" fn keypair_from_json(json: *const c_char) -> ResultExport<Handle> {
+    export_result(|| {
+        let KeypairJson(bytes) = serde_json::from_str(str_from_c_char_ptr(json)?)?;
+        Ok(handle_table::insert(keypair_from_bytes(&bytes)?))
+    })
+}
 
-    keypair.is_interactive() as u8
+/// Writes the keypair in the `solana-keygen` JSON format. Release the returned string
+/// with `free_secret_cstring`.
+#[no_mangle]
+extern "C" fn keypair_to_json(keypair: Handle) -> ResultExport<*mut c_char> {
+    export_result(|| {
+        let bytes = Zeroizing::new(handle_table::with(keypair, |keypair: &mut Keypair| keypair_to_vec(keypair))?);
+        // Room for "[" and up to 64 "255," so the buffer is never reallocated.
+        let mut json = Zeroizing::new(Vec::with_capacity(KEYPAIR_LENGTH * 4 + 1));
+        serde_json::to_writer(&mut *json, bytes.as_slice())?;
+        c_char_ptr_from_string(json.as_slice())
+    })
+}
 
+#[no_mangle]
+extern "C" fn derivation_path_validate(path: *const c_char) -> ResultExport<u8> {
+    export_result(|| {
+        derivation_path_from_str(str_from_c_char_ptr(path)?)?;
+        Ok(true as u8)
+    })
 }
+
+/// SLIP-0010 derivation from a BIP39 `Seed` handle, e.g. along `m/44'/501'/0'/0'`.
 #[no_mangle]
-extern "C" fn  keypair_from_seed_entropy(seed: *mut Seed) -> ResultExport<*mut Keypair> {
-    let seed = unsafe {
-        assert!(!seed.is_null());
-        &mut *seed
-    };
+extern "C" fn keypair_from_seed_and_path(seed: Handle, path: *const c_char) -> ResultExport<Handle> {
+    export_result(|| {
+        let derivation_path = derivation_path_from_str(str_from_c_char_ptr(path)?)?;
+        Ok(handle_table::insert(keypair_from_seed_handle(seed, derivation_path)?))
+    })
+}
 
-    match   keypair_from_seed(seed.as_bytes()){
-        Ok(keypair) => ResultExport{
-            is_error: 0,
-            result: Box::into_raw(Box::new(keypair)),
-            error: null_mut()
-        },
-        Err(error) => ResultExport{
-            is_error: 1,
-            result: null_mut(),
-            error: CString::new(error.to_string()).unwrap().into_raw()
+/// Derives `count` accounts starting at index `first_account`, substituting each index for
+/// `{account}` in `path_template`, e.g. `SOLANA_ACCOUNT_PATH_TEMPLATE`. The keypair handles
+/// are written to `out_keypairs`, which must have room for `count` of them.
+#[no_mangle]
+extern "C" fn keypair_derive_accounts(seed: Handle, path_template: *const c_char, first_account: u32, count: u32,
+                                      out_keypairs: *mut Handle, out_len: size_t) -> ResultExport<u32> {
+    export_result(|| {
+        let path_template = str_from_c_char_ptr(path_template)?;
+        if !path_template.contains(ACCOUNT_PLACEHOLDER) {
+            return Err(ExportError::new(
+                ErrorCode::InvalidDerivationPath,
+                format!("Path template {} has no {} placeholder", path_template, ACCOUNT_PLACEHOLDER),
+            ));
         }
-    }
+        if out_len < count as usize {
+            return Err(ExportError::invalid_length("out_keypairs", count as usize, out_len));
+        }
+
+        let keypairs = (0..count)
+            .map(|offset| {
+                let account = first_account.checked_add(offset)
+                    .ok_or_else(|| ExportError::invalid_argument("Account index overflows u32"))?;
+                let path = path_template.replace(ACCOUNT_PLACEHOLDER, &account.to_string());
+                keypair_from_seed_handle(seed, derivation_path_from_str(&path)?)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        handle_table::insert_into_ptr(keypairs, out_keypairs, out_len, "out_keypairs")
+    })
 }
 
 #[no_mangle]
-extern "C" fn from_seed_phrase_and_passphrase(seed_phrase: *const c_char, passphrase: *const c_char) -> ResultExport<*mut Keypair>{
+extern "C" fn from_seed_phrase_and_passphrase(seed_phrase: *const c_char, passphrase: *const c_char) -> ResultExport<Handle>{
+    export_result(|| {
+        let keypair = keypair_from_seed_phrase_and_passphrase(
+            str_from_c_char_ptr(seed_phrase)?, str_from_c_char_ptr(passphrase)?)?;
+        Ok(handle_table::insert(keypair))
+    })
+}
 
-   match keypair_from_seed_phrase_and_passphrase(
-       str_from_c_char_ptr(seed_phrase).unwrap(), str_from_c_char_ptr(passphrase).unwrap())
-   {
-       Ok(keypair) => ResultExport{
-           is_error: 0,
-           result: Box::into_raw(Box::new(keypair)),
-           error: null_mut()
-       },
-       Err(error) => ResultExport{
-           is_error: 1,
-           result: null_mut(),
-           error: CString::new(error.to_string()).unwrap().into_raw()
-       }
-   }
 
+/// The secret key is overwritten when the keypair is dropped.
+#[no_mangle]
+pub extern "C" fn free_keypair(keypair: Handle) -> ResultExport<u8> {
+    export_result(|| {
+        handle_table::remove::<Keypair>(keypair)?;
+        Ok(true as u8)
+    })
 }
 
+#[cfg(test)]
+mod tests {
+    use std::ffi::CString;
 
-#[no_mangle]
-pub extern "C" fn free_keypair(ptr: *mut Keypair) {
-    if ptr.is_null() {
-        return;
+    use bip39::{Language, Mnemonic};
+
+    use super::*;
+
+    const MNEMONIC: &str = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+
+    fn from_hex(hex: &str) -> Vec<u8> {
+        (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect()
+    }
+
+    fn seed_handle(passphrase: &str) -> Handle {
+        let mnemonic = Mnemonic::from_phrase(MNEMONIC, Language::English).unwrap();
+        handle_table::insert(Seed::new(&mnemonic, passphrase))
+    }
+
+    fn address(keypair: Handle) -> String {
+        let address = handle_table::with(keypair, |keypair: &mut Keypair| keypair.pubkey().to_string()).unwrap();
+        free_keypair(keypair);
+        address
+    }
+
+    /// Test vectors 1 and 2 for ed25519 from SLIP-0010, as (seed, path, secret key, public key).
+    const SLIP_0010_VECTORS: &[(&str, &str, &str, &str)] = &[
+        ("000102030405060708090a0b0c0d0e0f", "m/0'",
+         "68e0fe46dfb67e368c75379acec591dad19df3cde26e63b93a8e704f1dade7a3",
+         "8c8a13df77a28f3445213a0f432fde644acaa215fc72dcdf300d5efaa85d350c"),
+        ("000102030405060708090a0b0c0d0e0f", "m/0'/1'",
+         "b1d0bad404bf35da785a64ca1ac54b2617211d2777696fbffaf208f746ae84f2",
+         "1932a5270f335bed617d5b935c80aedb1a35bd9fc1e31acafd5372c30f5c1187"),
+        ("000102030405060708090a0b0c0d0e0f", "m/0h/1h/2h",
+         "92a5b23c0b8a99e37d07df3fb9966917f5d06e02ddbd909c7e184371463e9fc9",
+         "ae98736566d30ed0e9d2f4486a64bc95740d89c7db33f52121f8ea8f76ff0fc1"),
+        ("000102030405060708090a0b0c0d0e0f", "m/0'/1'/2'/2'",
+         "30d1dc7e5fc04c31219ab25a27ae00b50f6fd66622f6e9c913253d6511d1e662",
+         "8abae2d66361c879b900d204ad2cc4984fa2aa344dd7ddc46007329ac76c429c"),
+        ("000102030405060708090a0b0c0d0e0f", "m/0'/1'/2'/2'/1000000000'",
+         "8f94d394a8e8fd6b1bc2f3f49f5c47e385281d5c17e65324b0f62483e37e8793",
+         "3c24da049451555d51a7014a37337aa4e12d41e485abccfa46b47dfb2af54b7a"),
+        ("fffcf9f6f3f0edeae7e4e1dedbd8d5d2cfccc9c6c3c0bdbab7b4b1aeaba8a5a29f9c999693908d8a8784817e7b7875726f6c696663605d5a5754514e4b484542",
+         "m/0'",
+         "1559eb2bbec5790b0c65d8693e4d0875b1747f4970ae8b650486ed7470845635",
+         "86fab68dcb57aa196c77c5f264f215a112c22a912c10d123b0d03c3c28ef1037"),
+        ("fffcf9f6f3f0edeae7e4e1dedbd8d5d2cfccc9c6c3c0bdbab7b4b1aeaba8a5a29f9c999693908d8a8784817e7b7875726f6c696663605d5a5754514e4b484542",
+         "m/0'/2147483647'",
+         "ea4f5bfe8694d8bb74b7b59404632fd5968b774ed545e810de9c32a4fb4192f4",
+         "5ba3b9ac6e90e83effcd25ac4e58a1365a9e35a3d3ae5eb07b9e4d90bcf7506d"),
+        ("fffcf9f6f3f0edeae7e4e1dedbd8d5d2cfccc9c6c3c0bdbab7b4b1aeaba8a5a29f9c999693908d8a8784817e7b7875726f6c696663605d5a5754514e4b484542",
+         "m/0'/2147483647'/1'/2147483646'/2'",
+         "551d333177df541ad876a60ea71f00447931c0a9da16f227c11ea080d7391b8d",
+         "47150c75db263559a70d5778bf36abbab30fb061ad69f69ece61a72b0cfa4fc0"),
+    ];
+
+    #[test]
+    fn slip_0010_test_vectors() {
+        for (seed, path, secret, public) in SLIP_0010_VECTORS {
+            let derivation_path = derivation_path_from_str(path).unwrap();
+            let keypair = keypair_from_seed_and_derivation_path(&from_hex(seed), Some(derivation_path)).unwrap();
+            assert_eq!(keypair.secret().as_bytes().to_vec(), from_hex(secret), "{}", path);
+            assert_eq!(keypair.pubkey().to_bytes().to_vec(), from_hex(public), "{}", path);
+        }
+    }
+
+    #[test]
+    fn mnemonic_derives_known_solana_addresses() {
+        let seed = seed_handle("");
+        let path = CString::new("m/44'/501'/0'/0'").unwrap();
+        let result = keypair_from_seed_and_path(seed, path.as_ptr());
+        assert_eq!((result.is_error, result.error_code), (0, ErrorCode::Ok));
+        assert_eq!(address(result.result), "HAgk14JpMQLgt6rVgv7cBQFJWFto5Dqxi472uT3DKpqk");
+
+        let template = CString::new(SOLANA_ACCOUNT_PATH_TEMPLATE).unwrap();
+        let mut keypairs = [0 as Handle; 3];
+        let result = keypair_derive_accounts(seed, template.as_ptr(), 0, 3, keypairs.as_mut_ptr(), keypairs.len());
+        assert_eq!((result.is_error, result.result), (0, 3));
+        let addresses: Vec<_> = keypairs.iter().map(|keypair| address(*keypair)).collect();
+        assert_eq!(addresses, [
+            "HAgk14JpMQLgt6rVgv7cBQFJWFto5Dqxi472uT3DKpqk",
+            "Hh8QwFUA6MtVu1qAoq12ucvFHNwCcVTV7hpWjeY1Hztb",
+            "7WktogJEd2wQ9eH2oWusmcoFTgeYi6rS632UviTBJ2jm",
+        ]);
+
+        let mut keypairs = [0 as Handle; 1];
+        let result = keypair_derive_accounts(seed, template.as_ptr(), 2, 1, keypairs.as_mut_ptr(), keypairs.len());
+        assert_eq!((result.is_error, result.result), (0, 1));
+        assert_eq!(address(keypairs[0]), "7WktogJEd2wQ9eH2oWusmcoFTgeYi6rS632UviTBJ2jm");
+        handle_table::remove::<Seed>(seed).unwrap();
+
+        let seed = seed_handle("TREZOR");
+        let result = keypair_from_seed_and_path(seed, path.as_ptr());
+        assert_eq!(address(result.result), "7zSmbu6gKkb6HB7UDPtHYjwCWuBHU1D4TpNZFm4sndQe");
+        handle_table::remove::<Seed>(seed).unwrap();
+    }
+
+    #[test]
+    fn malformed_and_non_hardened_paths_are_rejected() {
+        let seed = seed_handle("");
+        let paths = [
+            "m/44'/501'/0'/0",
+            "m/44/501'",
+            "44'/501'/0'/0'",
+            "",
+            "m/",
+            "m//0'",
+            "m/44'/x'",
+            "m/-1'",
+            "m/+1'",
+            "m/44''",
+            "m/2147483648'",
+            "m/4294967296'",
+            "m/44'/501'/{account}'/0'",
+        ];
+        for path in paths {
+            assert_eq!(derivation_path_from_str(path).unwrap_err().code(), ErrorCode::InvalidDerivationPath, "{}", path);
+            let path = CString::new(path).unwrap();
+            assert_eq!(derivation_path_validate(path.as_ptr()).error_code, ErrorCode::InvalidDerivationPath);
+            let result = keypair_from_seed_and_path(seed, path.as_ptr());
+            assert_eq!((result.is_error, result.error_code), (1, ErrorCode::InvalidDerivationPath));
+        }
+
+        let path = derivation_path_from_str("m/44h/501h/0h/0h").unwrap();
+        assert_eq!(path, DerivationPath::new_bip44(Some(0), Some(0)));
+        handle_table::remove::<Seed>(seed).unwrap();
     }
 
-    unsafe {
-        Box::from_raw(ptr);
+    #[test]
+    fn account_templates_are_validated() {
+        let seed = seed_handle("");
+        let mut keypairs = [0 as Handle; 2];
+        let derive = |template: &str, first_account: u32, count: u32, keypairs: &mut [Handle]| {
+            let template = CString::new(template).unwrap();
+            keypair_derive_accounts(seed, template.as_ptr(), first_account, count, keypairs.as_mut_ptr(), keypairs.len())
+        };
+
+        let result = derive("m/44'/501'/0'/0'", 0, 1, &mut keypairs);
+        assert_eq!((result.is_error, result.error_code), (1, ErrorCode::InvalidDerivationPath));
+        let result = derive("m/44'/501'/{account}'/0", 0, 1, &mut keypairs);
+        assert_eq!((result.is_error, result.error_code), (1, ErrorCode::InvalidDerivationPath));
+        let result = derive("m/44'/501'/{account}/0'", 0, 1, &mut keypairs);
+        assert_eq!((result.is_error, result.error_code), (1, ErrorCode::InvalidDerivationPath));
+        let result = derive(SOLANA_ACCOUNT_PATH_TEMPLATE, 0, 3, &mut keypairs);
+        assert_eq!((result.is_error, result.error_code), (1, ErrorCode::InvalidLength));
+        let result = derive(SOLANA_ACCOUNT_PATH_TEMPLATE, u32::MAX, 2, &mut keypairs);
+        assert_eq!(result.is_error, 1);
+        // Indices at or past 2^31 can not be hardened.
+        let result = derive(SOLANA_ACCOUNT_PATH_TEMPLATE, HARDENED_OFFSET, 1, &mut keypairs);
+        assert_eq!((result.is_error, result.error_code), (1, ErrorCode::InvalidDerivationPath));
+        assert_eq!(keypairs, [0, 0]);
+
+        let result = derive("m/44'/501'/0'/{account}'", 1, 2, &mut keypairs);
+        assert_eq!((result.is_error, result.result), (0, 2));
+        let expected = Mnemonic::from_phrase(MNEMONIC, Language::English)
+            .map(|mnemonic| Seed::new(&mnemonic, ""))
+            .unwrap();
+        for (index, keypair) in keypairs.iter().enumerate() {
+            let path = DerivationPath::new_bip44(Some(0), Some(index as u32 + 1));
+            let expected = keypair_from_seed_and_derivation_path(expected.as_bytes(), Some(path)).unwrap();
+            assert_eq!(address(*keypair), expected.pubkey().to_string());
+        }
+        handle_table::remove::<Seed>(seed).unwrap();
+    }
+
+    fn json_to_keypair(json: &str) -> Result<Keypair, ErrorCode> {
+        let json = CString::new(json).unwrap();
+        let result = keypair_from_json(json.as_ptr());
+        if result.is_error != 0 {
+            return Err(result.error_code);
+        }
+        let keypair = handle_table::with(result.result, |keypair: &mut Keypair| keypair.insecure_clone()).unwrap();
+        free_keypair(result.result);
+        Ok(keypair)
+    }
+
+    fn keypair_to_json_string(keypair: &Keypair) -> String {
+        let handle = handle_table::insert(keypair.insecure_clone());
+        let result = keypair_to_json(handle);
+        free_keypair(handle);
+        assert_eq!(result.is_error, 0);
+        unsafe { CString::from_raw(result.result) }.into_string().unwrap()
+    }
+
+    #[test]
+    fn keypair_json_round_trips() {
+        let keypair = Keypair::new();
+        let json = keypair_to_json_string(&keypair);
+        let bytes: Vec<u8> = serde_json::from_str(&json).unwrap();
+        assert_eq!(bytes, keypair.to_bytes());
+        assert_eq!(json_to_keypair(&json).unwrap().to_bytes(), keypair.to_bytes());
+
+        // Same layout as `solana-keygen`, whitespace included.
+        let spaced = format!(" [ {} ]\n", bytes.iter().map(u8::to_string).collect::<Vec<_>>().join(", "));
+        assert_eq!(json_to_keypair(&spaced).unwrap().pubkey(), keypair.pubkey());
+    }
+
+    #[test]
+    fn keypair_json_of_wrong_length_is_rejected() {
+        let bytes = Keypair::new().to_bytes();
+        let json = |bytes: &[u8]| serde_json::to_string(bytes).unwrap();
+        assert_eq!(json_to_keypair(&json(&bytes[..63])).unwrap_err(), ErrorCode::InvalidLength);
+        assert_eq!(json_to_keypair(&json(&[bytes.as_slice(), &[0]].concat())).unwrap_err(), ErrorCode::InvalidJson);
+        assert_eq!(json_to_keypair(&json(&[bytes, bytes].concat())).unwrap_err(), ErrorCode::InvalidJson);
+        assert_eq!(json_to_keypair("[]").unwrap_err(), ErrorCode::InvalidLength);
+        assert_eq!(json_to_keypair("{}").unwrap_err(), ErrorCode::InvalidJson);
+        assert_eq!(json_to_keypair("").unwrap_err(), ErrorCode::InvalidJson);
+    }
+
+    #[test]
+    fn keypair_json_values_must_be_bytes() {
+        let bytes = Keypair::new().to_bytes();
+        let with_value = |value: &str| {
+            let mut values: Vec<String> = bytes.iter().map(u8::to_string).collect();
+            values[10] = value.to_string();
+            format!("[{}]", values.join(","))
+        };
+        for value in ["256", "1000", "-1", "1.5", "\"7\"", "null", "true"] {
+            assert_eq!(json_to_keypair(&with_value(value)).unwrap_err(), ErrorCode::InvalidJson, "{}", value);
+        }
+        // A valid byte gets past the JSON parsing, the keypair check is tested separately.
+        assert_ne!(json_to_keypair(&with_value("255")).err(), Some(ErrorCode::InvalidJson));
+    }
+
+    #[test]
+    fn keypair_json_with_foreign_pubkey_is_rejected() {
+        let keypair = Keypair::new();
+        let mut bytes = keypair.to_bytes();
+        bytes[SECRET_KEY_LENGTH..].copy_from_slice(Keypair::new().pubkey().as_ref());
+        assert_eq!(json_to_keypair(&serde_json::to_string(bytes.as_slice()).unwrap()).unwrap_err(), ErrorCode::InvalidKeypair);
+
+        let mut bytes = keypair.to_bytes();
+        bytes[KEYPAIR_LENGTH - 1] ^= 1;
+        assert_eq!(keypair_from_bytes(&bytes).unwrap_err().code(), ErrorCode::InvalidKeypair);
+        assert_eq!(keypair_from_bytes(&keypair.to_bytes()).unwrap().pubkey(), keypair.pubkey());
     }
 }
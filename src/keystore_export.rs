@@ -0,0 +1,365 @@
+use std::convert::TryFrom;
+use std::os::raw::c_char;
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use argon2::{Algorithm, Argon2, Params, Version};
+use bip39::Mnemonic;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use solana_sdk::signature::Keypair;
+use zeroize::Zeroizing;
+
+use crate::bip39_export::language_from_str;
+use crate::c_sharp_string::{c_char_ptr_from_string, str_from_c_char_ptr};
+use crate::common_types::ResultExport;
+use crate::error_export::{ErrorCode, export_result, ExportError};
+use crate::handle_table::{self, Handle};
+use crate::keypair_export::{keypair_from_bytes, keypair_to_vec};
+
+pub const KEYSTORE_VERSION: u32 = 1;
+const KDF_NAME: &str = "argon2id";
+const CIPHER_NAME: &str = "aes-256-gcm";
+const KEY_LENGTH: usize = 32;
+const SALT_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 12;
+
+/// Argon2id cost used for new keystores: 19 MiB, 2 passes, 1 lane, which stays
+/// responsive on phones. Stored in the keystore, so it can be raised later.
+const M_COST_KIB: u32 = 19 * 1024;
+const T_COST: u32 = 2;
+const P_COST: u32 = 1;
+/// Upper bounds accepted when decrypting, so a crafted keystore cannot exhaust memory or time.
+const MAX_M_COST_KIB: u32 = 256 * 1024;
+const MAX_T_COST: u32 = 16;
+const MAX_P_COST: u32 = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum KeystoreKind {
+    Keypair,
+    Mnemonic,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct KdfParams {
+    name: String,
+    salt: String,
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CipherParams {
+    name: String,
+    nonce: String,
+}
+
+/// Self describing keystore JSON. All binary fields are base64.
+#[derive(Debug, Serialize, Deserialize)]
+struct Keystore {
+    version: u32,
+    kind: KeystoreKind,
+    /// Wordlist of an encrypted mnemonic, as accepted by `from_phrase`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    language: Option<String>,
+    kdf: KdfParams,
+    cipher: CipherParams,
+    ciphertext: String,
+}
+
+fn invalid_keystore<S: Into<String>>(message: S) -> ExportError {
+    ExportError::new(ErrorCode::InvalidKeystore, message)
+}
+
+fn decode_base64(value: &str, name: &str) -> Result<Vec<u8>, ExportError> {
+    base64::decode(value).map_err(|error| invalid_keystore(format!("{} is not valid base64: {}", name, error)))
+}
+
+fn derive_key(password: &str, kdf: &KdfParams, salt: &[u8]) -> Result<Zeroizing<[u8; KEY_LENGTH]>, ExportError> {
+    let params = Params::new(kdf.m_cost, kdf.t_cost, kdf.p_cost, Some(KEY_LENGTH))
+        .map_err(|error| invalid_keystore(format!("Invalid {} parameters: {}", KDF_NAME, error)))?;
+    let mut key = Zeroizing::new([0u8; KEY_LENGTH]);
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(password.as_bytes(), salt, key.as_mut())
+        .map_err(|error| invalid_keystore(format!("Key derivation failed: {}", error)))?;
+    Ok(key)
+}
+
+/// Binds the fields that decide how the plaintext is interpreted to the ciphertext.
+fn associated_data(version: u32, kind: KeystoreKind, language: Option<&str>) -> Vec<u8> {
+    format!("solana-keystore:{}:{:?}:{}", version, kind, language.unwrap_or("")).into_bytes()
+}
+
+fn encrypt(kind: KeystoreKind, language: Option<String>, plaintext: &[u8], password: &str) -> Result<String, ExportError> {
+    if password.is_empty() {
+        return Err(ExportError::invalid_argument("Keystore password must not be empty"));
+    }
+
+    let mut salt = [0u8; SALT_LENGTH];
+    let mut nonce = [0u8; NONCE_LENGTH];
+    OsRng.fill_bytes(&mut salt);
+    OsRng.fill_bytes(&mut nonce);
+
+    let kdf = KdfParams {
+        name: KDF_NAME.to_string(),
+        salt: base64::encode(salt),
+        m_cost: M_COST_KIB,
+        t_cost: T_COST,
+        p_cost: P_COST,
+    };
+    let key = derive_key(password, &kdf, &salt)?;
+    let aad = associated_data(KEYSTORE_VERSION, kind, language.as_deref());
+    let ciphertext = Aes256Gcm::new_from_slice(key.as_ref())
+        .map_err(|_| invalid_keystore("Invalid key length"))?
+        .encrypt(&Nonce::from(nonce), Payload { msg: plaintext, aad: &aad })
+        .map_err(|_| invalid_keystore("Encryption failed"))?;
+
+    Ok(serde_json::to_string(&Keystore {
+        version: KEYSTORE_VERSION,
+        kind,
+        language,
+        kdf,
+        cipher: CipherParams {
+            name: CIPHER_NAME.to_string(),
+            nonce: base64::encode(nonce),
+        },
+        ciphertext: base64::encode(ciphertext),
+    })?)
+}
+
+/// Returns the plaintext and language of a keystore holding a `kind` secret.
+fn decrypt(json: &str, password: &str, kind: KeystoreKind) -> Result<(Zeroizing<Vec<u8>>, Option<String>), ExportError> {
+    let keystore: Keystore = serde_json::from_str(json)
+        .map_err(|error| invalid_keystore(format!("Invalid keystore JSON: {}", error)))?;
+    if keystore.version != KEYSTORE_VERSION {
+        return Err(invalid_keystore(format!("Unsupported keystore version {}", keystore.version)));
+    }
+    if keystore.kind != kind {
+        return Err(invalid_keystore(format!("Keystore holds a {:?}, not a {:?}", keystore.kind, kind)));
+    }
+    if keystore.kdf.name != KDF_NAME || keystore.cipher.name != CIPHER_NAME {
+        return Err(invalid_keystore(format!("Unsupported keystore algorithms {}/{}", keystore.kdf.name, keystore.cipher.name)));
+    }
+    if keystore.kdf.m_cost > MAX_M_COST_KIB || keystore.kdf.t_cost > MAX_T_COST || keystore.kdf.p_cost > MAX_P_COST {
+        return Err(invalid_keystore("Keystore key derivation cost exceeds the supported maximum"));
+    }
+
+    let salt = decode_base64(&keystore.kdf.salt, "salt")?;
+    let nonce = decode_base64(&keystore.cipher.nonce, "nonce")?;
+    let ciphertext = decode_base64(&keystore.ciphertext, "ciphertext")?;
+    let nonce = <[u8; NONCE_LENGTH]>::try_from(nonce.as_slice())
+        .map_err(|_| ExportError::invalid_length("nonce", NONCE_LENGTH, nonce.len()))?;
+
+    let key = derive_key(password, &keystore.kdf, &salt)?;
+    let aad = associated_data(keystore.version, keystore.kind, keystore.language.as_deref());
+    let plaintext = Aes256Gcm::new_from_slice(key.as_ref())
+        .map_err(|_| invalid_keystore("Invalid key length"))?
+        .decrypt(&Nonce::from(nonce), Payload { msg: &ciphertext, aad: &aad })
+        .map_err(|_| ExportError::new(ErrorCode::WrongPassword, "Wrong password, or the keystore was modified"))?;
+
+    Ok((Zeroizing::new(plaintext), keystore.language))
+}
+
+/// Encrypts the keypair with `password` into a keystore JSON string, to be released with `free_cstring`.
+#[no_mangle]
+extern "C" fn keystore_encrypt_keypair(keypair: Handle, password: *const c_char) -> ResultExport<*mut c_char> {
+    export_result(|| {
+        let password = str_from_c_char_ptr(password)?;
+        let bytes = Zeroizing::new(handle_table::with(keypair, |keypair: &mut Keypair| keypair_to_vec(keypair))?);
+        c_char_ptr_from_string(encrypt(KeystoreKind::Keypair, None, bytes.as_ref(), password)?)
+    })
+}
+
+#[no_mangle]
+extern "C" fn keystore_decrypt_keypair(json: *const c_char, password: *const c_char) -> ResultExport<Handle> {
+    export_result(|| {
+        let (bytes, _) = decrypt(str_from_c_char_ptr(json)?, str_from_c_char_ptr(password)?, KeystoreKind::Keypair)?;
+        Ok(handle_table::insert(keypair_from_bytes(&bytes)?))
+    })
+}
+
+/// Encrypts the mnemonic phrase with `password` into a keystore JSON string, to be released with `free_cstring`.
+#[no_mangle]
+extern "C" fn keystore_encrypt_mnemonic(mnemonic: Handle, password: *const c_char) -> ResultExport<*mut c_char> {
+    export_result(|| {
+        let password = str_from_c_char_ptr(password)?;
+        let (phrase, language) = handle_table::with(mnemonic, |mnemonic: &mut Mnemonic| {
+            (Zeroizing::new(mnemonic.phrase().to_string()), format!("{:?}", mnemonic.language()))
+        })?;
+        c_char_ptr_from_string(encrypt(KeystoreKind::Mnemonic, Some(language), phrase.as_bytes(), password)?)
+    })
+}
+
+#[no_mangle]
+extern "C" fn keystore_decrypt_mnemonic(json: *const c_char, password: *const c_char) -> ResultExport<Handle> {
+    export_result(|| {
+        let (phrase, language) = decrypt(str_from_c_char_ptr(json)?, str_from_c_char_ptr(password)?, KeystoreKind::Mnemonic)?;
+        let language = language.as_deref()
+            .and_then(language_from_str)
+            .ok_or_else(|| invalid_keystore("Keystore has no valid mnemonic language"))?;
+        let phrase = std::str::from_utf8(&phrase)?;
+        Ok(handle_table::insert(Mnemonic::from_phrase(phrase, language)?))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::{CStr, CString};
+
+    use bip39::{Language, MnemonicType};
+    use serde_json::Value;
+    use solana_sdk::signature::Signer;
+
+    use super::*;
+
+    const PASSWORD: &str = "correct horse battery staple";
+
+    type Tamper = Box<dyn FnOnce(&mut Value)>;
+
+    fn export_json(result: ResultExport<*mut c_char>) -> String {
+        assert_eq!((result.is_error, result.error_code), (0, ErrorCode::Ok));
+        unsafe { CString::from_raw(result.result) }.into_string().unwrap()
+    }
+
+    fn encrypt_keypair(keypair: &Keypair) -> String {
+        let handle = handle_table::insert(keypair.insecure_clone());
+        let password = CString::new(PASSWORD).unwrap();
+        let json = export_json(keystore_encrypt_keypair(handle, password.as_ptr()));
+        handle_table::remove::<Keypair>(handle).unwrap();
+        json
+    }
+
+    fn decrypt_keypair(json: &str, password: &str) -> Result<Keypair, ErrorCode> {
+        let (json, password) = (CString::new(json).unwrap(), CString::new(password).unwrap());
+        let result = keystore_decrypt_keypair(json.as_ptr(), password.as_ptr());
+        if result.is_error != 0 {
+            assert!(!result.error.is_null());
+            assert!(!unsafe { CStr::from_ptr(result.error) }.to_bytes().is_empty());
+            return Err(result.error_code);
+        }
+        let keypair = handle_table::with(result.result, |keypair: &mut Keypair| keypair.insecure_clone()).unwrap();
+        handle_table::remove::<Keypair>(result.result).unwrap();
+        Ok(keypair)
+    }
+
+    fn decrypt_mnemonic(json: &str, password: &str) -> Result<Mnemonic, ErrorCode> {
+        let (json, password) = (CString::new(json).unwrap(), CString::new(password).unwrap());
+        let result = keystore_decrypt_mnemonic(json.as_ptr(), password.as_ptr());
+        if result.is_error != 0 {
+            return Err(result.error_code);
+        }
+        let mnemonic = handle_table::get::<Mnemonic>(result.result).unwrap();
+        handle_table::remove::<Mnemonic>(result.result).unwrap();
+        Ok(mnemonic)
+    }
+
+    fn tampered(json: &str, tamper: impl FnOnce(&mut Value)) -> String {
+        let mut keystore: Value = serde_json::from_str(json).unwrap();
+        tamper(&mut keystore);
+        keystore.to_string()
+    }
+
+    fn flip_base64(value: &mut Value) {
+        let mut bytes = base64::decode(value.as_str().unwrap()).unwrap();
+        bytes[0] ^= 1;
+        *value = Value::String(base64::encode(bytes));
+    }
+
+    #[test]
+    fn keypair_round_trips() {
+        let keypair = Keypair::new();
+        let json = encrypt_keypair(&keypair);
+        let keystore: Keystore = serde_json::from_str(&json).unwrap();
+        assert_eq!((keystore.version, keystore.kind, keystore.language), (KEYSTORE_VERSION, KeystoreKind::Keypair, None));
+        assert_eq!((keystore.kdf.m_cost, keystore.kdf.t_cost, keystore.kdf.p_cost), (M_COST_KIB, T_COST, P_COST));
+
+        assert_eq!(decrypt_keypair(&json, PASSWORD).unwrap().to_bytes(), keypair.to_bytes());
+        // Fresh salt and nonce for every keystore.
+        assert_ne!(encrypt_keypair(&keypair), json);
+    }
+
+    #[test]
+    fn mnemonic_round_trips() {
+        for language in [Language::English, Language::Japanese] {
+            let mnemonic = Mnemonic::new(MnemonicType::Words24, language);
+            let handle = handle_table::insert(mnemonic.clone());
+            let password = CString::new(PASSWORD).unwrap();
+            let json = export_json(keystore_encrypt_mnemonic(handle, password.as_ptr()));
+            handle_table::remove::<Mnemonic>(handle).unwrap();
+
+            let decrypted = decrypt_mnemonic(&json, PASSWORD).unwrap();
+            assert_eq!(decrypted.phrase(), mnemonic.phrase());
+            assert_eq!(decrypted.language(), language);
+            assert_eq!(decrypt_keypair(&json, PASSWORD).unwrap_err(), ErrorCode::InvalidKeystore);
+        }
+    }
+
+    #[test]
+    fn wrong_password_is_a_typed_error() {
+        let json = encrypt_keypair(&Keypair::new());
+        assert_eq!(decrypt_keypair(&json, "wrong password").unwrap_err(), ErrorCode::WrongPassword);
+        assert_eq!(decrypt_keypair(&json, "").unwrap_err(), ErrorCode::WrongPassword);
+
+        let handle = handle_table::insert(Keypair::new());
+        let empty = CString::new("").unwrap();
+        let result = keystore_encrypt_keypair(handle, empty.as_ptr());
+        assert_eq!((result.is_error, result.error_code), (1, ErrorCode::InvalidArgument));
+        handle_table::remove::<Keypair>(handle).unwrap();
+    }
+
+    #[test]
+    fn tampered_keystores_are_rejected() {
+        let keypair = Keypair::new();
+        let json = encrypt_keypair(&keypair);
+
+        let cases: Vec<(&str, Tamper, ErrorCode)> = vec![
+            ("ciphertext", Box::new(|keystore| flip_base64(&mut keystore["ciphertext"])), ErrorCode::WrongPassword),
+            ("nonce", Box::new(|keystore| flip_base64(&mut keystore["cipher"]["nonce"])), ErrorCode::WrongPassword),
+            ("salt", Box::new(|keystore| flip_base64(&mut keystore["kdf"]["salt"])), ErrorCode::WrongPassword),
+            ("t_cost", Box::new(|keystore| keystore["kdf"]["t_cost"] = Value::from(T_COST + 1)), ErrorCode::WrongPassword),
+            ("language", Box::new(|keystore| keystore["language"] = Value::from("English")), ErrorCode::WrongPassword),
+            ("version", Box::new(|keystore| keystore["version"] = Value::from(KEYSTORE_VERSION + 1)), ErrorCode::InvalidKeystore),
+            ("kind", Box::new(|keystore| keystore["kind"] = Value::from("mnemonic")), ErrorCode::InvalidKeystore),
+            ("cipher", Box::new(|keystore| keystore["cipher"]["name"] = Value::from("aes-128-gcm")), ErrorCode::InvalidKeystore),
+            ("kdf", Box::new(|keystore| keystore["kdf"]["name"] = Value::from("scrypt")), ErrorCode::InvalidKeystore),
+            ("base64", Box::new(|keystore| keystore["ciphertext"] = Value::from("not base64!")), ErrorCode::InvalidKeystore),
+            ("missing field", Box::new(|keystore| { keystore.as_object_mut().unwrap().remove("kdf"); }), ErrorCode::InvalidKeystore),
+            ("short nonce", Box::new(|keystore| keystore["cipher"]["nonce"] = Value::from(base64::encode([0u8; 8]))), ErrorCode::InvalidLength),
+        ];
+        for (field, tamper, code) in cases {
+            assert_eq!(decrypt_keypair(&tampered(&json, tamper), PASSWORD).unwrap_err(), code, "{}", field);
+        }
+        assert_eq!(decrypt_keypair("{not json", PASSWORD).unwrap_err(), ErrorCode::InvalidKeystore);
+        assert_eq!(decrypt_keypair(&json, PASSWORD).unwrap().pubkey(), keypair.pubkey());
+    }
+
+    #[test]
+    fn mnemonic_language_is_authenticated() {
+        let handle = handle_table::insert(Mnemonic::new(MnemonicType::Words12, Language::English));
+        let password = CString::new(PASSWORD).unwrap();
+        let json = export_json(keystore_encrypt_mnemonic(handle, password.as_ptr()));
+        handle_table::remove::<Mnemonic>(handle).unwrap();
+
+        let french = tampered(&json, |keystore| keystore["language"] = Value::from("French"));
+        assert_eq!(decrypt_mnemonic(&french, PASSWORD).unwrap_err(), ErrorCode::WrongPassword);
+        let missing = tampered(&json, |keystore| { keystore.as_object_mut().unwrap().remove("language"); });
+        assert_eq!(decrypt_mnemonic(&missing, PASSWORD).unwrap_err(), ErrorCode::WrongPassword);
+        let kind = tampered(&json, |keystore| keystore["kind"] = Value::from("keypair"));
+        assert_eq!(decrypt_keypair(&kind, PASSWORD).unwrap_err(), ErrorCode::WrongPassword);
+    }
+
+    #[test]
+    fn excessive_kdf_costs_are_refused() {
+        let json = encrypt_keypair(&Keypair::new());
+        for (param, value) in [("m_cost", MAX_M_COST_KIB + 1), ("t_cost", MAX_T_COST + 1), ("p_cost", MAX_P_COST + 1), ("m_cost", u32::MAX)] {
+            let json = tampered(&json, |keystore| keystore["kdf"][param] = Value::from(value));
+            assert_eq!(decrypt_keypair(&json, PASSWORD).unwrap_err(), ErrorCode::InvalidKeystore, "{} {}", param, value);
+        }
+        // Values Argon2 itself rejects are invalid keystores as well.
+        let json = tampered(&json, |keystore| keystore["kdf"]["t_cost"] = Value::from(0));
+        assert_eq!(decrypt_keypair(&json, PASSWORD).unwrap_err(), ErrorCode::InvalidKeystore);
+    }
+}
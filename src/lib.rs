@@ -1,4 +1,4 @@
-#![feature(vec_into_raw_parts)]
+#![allow(clippy::not_unsafe_ptr_arg_deref)]
 
 pub mod c_sharp_string;
 pub mod pubkey_export;
@@ -7,3 +7,20 @@ pub mod keypair_export;
 pub mod signature_export;
 pub mod common_types;
 pub mod bip39_export;
+pub mod error_export;
+pub mod handle_table;
+pub mod transaction_export;
+pub mod versioned_transaction_export;
+pub mod system_instruction_export;
+pub mod spl_token_export;
+pub mod token_2022_export;
+pub mod compute_budget_export;
+pub mod memo_export;
+pub mod borsh_export;
+pub mod anchor_idl_export;
+pub mod keystore_export;
+pub mod rpc_export;
+pub mod rpc_client_export;
+pub mod pubsub_export;
+pub mod confirmation_export;
+pub mod blockhash_cache_export;
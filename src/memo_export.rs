@@ -0,0 +1,44 @@
+use std::os::raw::c_char;
+
+use libc::size_t;
+use solana_sdk::packet::PACKET_DATA_SIZE;
+
+use crate::c_sharp_string::str_from_c_char_ptr;
+use crate::common_types::ResultExport;
+use crate::error_export::{ErrorCode, export_result, ExportError};
+use crate::handle_table::{self, Handle};
+use crate::pubkey_export::pubkeys_from_ptr;
+
+/// Longest memo that fits in a transaction carrying only the memo instruction, paid for by
+/// its first signer, or by a separate fee payer when there are no signers. Every signer
+/// costs a signature, an account key and an account index.
+pub fn max_memo_length(signers_len: usize) -> usize {
+    let signatures = signers_len.max(1);
+    // Signature count, header, key count, keys plus the memo program, blockhash,
+    // instruction count, program index, account index count and a two byte data length.
+    let overhead = 1 + 64 * signatures + 3 + 1 + 32 * (signatures + 1) + 32 + 1 + 1 + 1 + signers_len + 2;
+    PACKET_DATA_SIZE.saturating_sub(overhead)
+}
+
+#[no_mangle]
+extern "C" fn memo_program_id() -> ResultExport<Handle> {
+    export_result(|| Ok(handle_table::insert(spl_memo::id())))
+}
+
+/// Every key in `signers` must sign the transaction carrying the memo.
+#[no_mangle]
+extern "C" fn memo_new(memo: *const c_char, signers: *const Handle, signers_len: size_t) -> ResultExport<Handle> {
+    export_result(|| {
+        let memo = str_from_c_char_ptr(memo)?;
+        let signers = pubkeys_from_ptr(signers, signers_len)?;
+        let max_length = max_memo_length(signers.len());
+        if memo.len() > max_length {
+            return Err(ExportError::new(
+                ErrorCode::InvalidLength,
+                format!("memo has length {}, expected at most {} with {} signers", memo.len(), max_length, signers.len()),
+            ));
+        }
+
+        Ok(handle_table::insert(spl_memo::build_memo(memo.as_bytes(), &signers.iter().collect::<Vec<_>>())))
+    })
+}
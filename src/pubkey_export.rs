@@ -1,13 +1,12 @@
-#[allow(unused_variables)]
-use std::convert::TryInto;
-use std::ffi::{CString};
+use std::convert::TryFrom;
 use std::os::raw::c_char;
-use std::ptr::null_mut;
 use std::str::FromStr;
 use libc::size_t;
 use solana_program::pubkey::{Pubkey};
-use crate::c_sharp_string::str_from_c_char_ptr;
-use crate::common_types::{ResultExport, to_raw_parts, VecRawParts};
+use crate::c_sharp_string::{c_char_ptr_from_string, str_from_c_char_ptr};
+use crate::common_types::{ExportDefault, ResultExport, slice_from_ptr, to_raw_parts, VecRawParts};
+use crate::error_export::{ErrorCode, export_result, ExportError};
+use crate::handle_table::{self, Handle, HandleObject, HandleType};
 
 
 /// Number of bytes in a pubkey
@@ -16,205 +15,160 @@ pub const PUBKEY_BYTES: usize = 32;
 pub const MAX_SEED_LEN: usize = 32;
 /// Maximum number of seeds
 pub const MAX_SEEDS: usize = 16;
-/// Maximum string length of a base58 encoded pubkey
-const MAX_BASE58_LEN: usize = 44;
 
 #[repr(C)]
 pub struct ProgramAddress {
-    pub pubkey: *mut Pubkey,
+    pub pubkey: Handle,
     pub bump_seed: u8
 }
 
+impl ExportDefault for ProgramAddress {
+    fn export_default() -> Self {
+        ProgramAddress {
+            pubkey: 0,
+            bump_seed: 0
+        }
+    }
+}
+
+impl HandleObject for Pubkey {
+    const HANDLE_TYPE: HandleType = HandleType::Pubkey;
+}
+
 #[repr(C)]
 pub struct Seeds<T> {
     seed: *const T,
     length: size_t
 }
 
-#[no_mangle]
-extern "C" fn pubkey_new_from_array(pubkey_array: *const u8, len: size_t) -> *mut Pubkey{
-    let pubkey_array = unsafe {
-        assert!(!pubkey_array.is_null());
-        std::slice::from_raw_parts(pubkey_array  , len as usize)
-    };
-
-    let pubkey = Pubkey::new_from_array(pubkey_array.try_into().expect("slice with incorrect length"));
-    Box::into_raw(Box::new(pubkey))
+fn seeds_from_ptr<'a>(seeds: *const Seeds<u8>, len: size_t) -> Result<Vec<&'a [u8]>, ExportError> {
+    slice_from_ptr(seeds, len, "seeds")?
+        .iter()
+        .map(|seed| slice_from_ptr(seed.seed, seed.length, "seed"))
+        .collect()
 }
 
-#[no_mangle]
-extern "C" fn pubkey_new_unique()-> *mut Pubkey {
-    let pubkey = Pubkey::new_unique();
-    Box::into_raw(Box::new(pubkey))
+pub fn pubkeys_from_ptr(pubkeys: *const Handle, len: size_t) -> Result<Vec<Pubkey>, ExportError> {
+    slice_from_ptr(pubkeys, len, "pubkeys")?
+        .iter()
+        .map(|pubkey| handle_table::get::<Pubkey>(*pubkey))
+        .collect()
 }
 
-#[no_mangle]
-extern "C" fn pubkey_from_str(s: *const c_char) -> ResultExport<*mut Pubkey> {
-
-    match Pubkey::from_str(str_from_c_char_ptr(s).unwrap()) {
-        Ok(pubkey) => ResultExport{
-            is_error: 0 ,
-            result: Box::into_raw(Box::new(pubkey)),
-            error: null_mut()
-        },
-        Err(error) => ResultExport{
-            is_error: 1,
-            result: null_mut(),
-            error: CString::new(error.to_string()).unwrap().into_raw()
-        }
-    }
-
+/// Same as `Pubkey::find_program_address`, with the derived address handed out as a handle.
+pub fn find_program_address(seeds: &[&[u8]], program_id: &Pubkey) -> Result<ProgramAddress, ExportError> {
+    let program_address = Pubkey::try_find_program_address(seeds, program_id)
+        .ok_or_else(|| ExportError::new(ErrorCode::NoViableBumpSeed, "Unable to find a viable program address bump seed"))?;
 
+    Ok(ProgramAddress {
+        pubkey: handle_table::insert(program_address.0),
+        bump_seed: program_address.1
+    })
 }
 
-
-
 #[no_mangle]
-extern "C" fn pubkey_create_with_seed(base: *const Pubkey, seed: *const c_char, owner: *const Pubkey)-> ResultExport<*mut Pubkey>{
-    let base = unsafe {
-        assert!(!base.is_null());
-        &*base
-    };
-
-    let owner = unsafe {
-        assert!(!owner.is_null());
-        &*owner
-    };
-
-    let seed = str_from_c_char_ptr(seed).unwrap();
-
-    let pubkey_result = Pubkey::create_with_seed(base, seed, owner);
-    match pubkey_result {
-        Ok(pubkey) => ResultExport{
-            is_error: 0 ,
-            result: Box::into_raw(Box::new(pubkey)),
-            error: null_mut()
-        },
-        Err(error) => ResultExport{
-            is_error: 1,
-            result: null_mut(),
-            error: CString::new(error.to_string()).unwrap().into_raw()
-        }
-    }
+extern "C" fn pubkey_new_from_array(pubkey_array: *const u8, len: size_t) -> ResultExport<Handle>{
+    export_result(|| {
+        let pubkey_array = slice_from_ptr(pubkey_array, len, "pubkey_array")?;
+        let pubkey = Pubkey::try_from(pubkey_array)
+            .map_err(|_| ExportError::invalid_length("pubkey_array", PUBKEY_BYTES, pubkey_array.len()))?;
+        Ok(handle_table::insert(pubkey))
+    })
 }
 
 #[no_mangle]
-extern "C" fn pubkey_find_program_address(seeds:  *const Seeds<u8>, len: size_t, program_id: *const Pubkey)-> ProgramAddress {
-
-    let array_slice = unsafe { std::slice::from_raw_parts(seeds, len  as usize) };
-    let mut vec_of_seed : Vec<&[u8]> = Vec::new();
-    for seed_slice in array_slice {
-        let slice = unsafe { std::slice::from_raw_parts(seed_slice.seed, seed_slice.length  as usize) };
-        vec_of_seed.push(slice)
-    };
-
-
-    let program_id = unsafe {
-        assert!(!program_id.is_null());
-        &*program_id
-    };
-
-
-    let program_address = Pubkey::find_program_address(vec_of_seed.as_slice(),program_id);
-
-    ProgramAddress {
-        pubkey: Box::into_raw(Box::new(program_address.0)),
-        bump_seed: program_address.1
-    }
+extern "C" fn pubkey_new_unique()-> ResultExport<Handle> {
+    export_result(|| Ok(handle_table::insert(Pubkey::new_unique())))
 }
 
 #[no_mangle]
-extern "C" fn pubkey_try_find_program_address(seeds: *const Seeds<u8>, len: size_t, program_id: *mut Pubkey) -> ProgramAddress {
+extern "C" fn pubkey_from_str(s: *const c_char) -> ResultExport<Handle> {
+    export_result(|| {
+        let pubkey = Pubkey::from_str(str_from_c_char_ptr(s)?)?;
+        Ok(handle_table::insert(pubkey))
+    })
+}
 
-    let array_slice = unsafe { std::slice::from_raw_parts(seeds, len  as usize) };
-    let mut vec_of_seed : Vec<&[u8]> = Vec::new();
-    for seed_slice in array_slice {
-        let slice = unsafe { std::slice::from_raw_parts(seed_slice.seed, seed_slice.length  as usize) };
-        vec_of_seed.push(slice)
-    };
 
 
-    let program_id = unsafe {
-        assert!(!program_id.is_null());
-        &*program_id
-    };
+#[no_mangle]
+extern "C" fn pubkey_create_with_seed(base: Handle, seed: *const c_char, owner: Handle)-> ResultExport<Handle>{
+    export_result(|| {
+        let base = handle_table::get::<Pubkey>(base)?;
+        let owner = handle_table::get::<Pubkey>(owner)?;
+        let seed = str_from_c_char_ptr(seed)?;
+
+        let pubkey = Pubkey::create_with_seed(&base, seed, &owner)?;
+        Ok(handle_table::insert(pubkey))
+    })
+}
 
+#[no_mangle]
+extern "C" fn pubkey_find_program_address(seeds:  *const Seeds<u8>, len: size_t, program_id: Handle)-> ResultExport<ProgramAddress> {
+    export_result(|| {
+        let vec_of_seed = seeds_from_ptr(seeds, len)?;
+        let program_id = handle_table::get::<Pubkey>(program_id)?;
 
-    let program_address_result = Pubkey::try_find_program_address(vec_of_seed.as_slice(),program_id);
-    match program_address_result {
-        None => ProgramAddress {
-            pubkey: null_mut(),
-            bump_seed: 0
-        },
-        Some(program_address) => ProgramAddress {
-            pubkey: Box::into_raw(Box::new(program_address.0)),
-            bump_seed: program_address.1
-        }
-    }
+        find_program_address(vec_of_seed.as_slice(),&program_id)
+    })
 }
 
 #[no_mangle]
-extern "C" fn pubkey_create_program_address(seeds:  *const Seeds<u8>, len: size_t, program_id: *const Pubkey)-> ResultExport<*mut Pubkey> {
-    let array_slice = unsafe { std::slice::from_raw_parts(seeds, len  as usize) };
-    let mut vec_of_seed : Vec<&[u8]> = Vec::new();
-    for seed_slice in array_slice {
-        let slice = unsafe { std::slice::from_raw_parts(seed_slice.seed, seed_slice.length  as usize) };
-        vec_of_seed.push(slice)
-    };
-
-    let program_id = unsafe {
-        assert!(!program_id.is_null());
-        &*program_id
-    };
-
-    match Pubkey::create_program_address(vec_of_seed.as_slice(),program_id) {
-        Ok(pubkey) => ResultExport{
-            is_error: 0 ,
-            result: Box::into_raw(Box::new(pubkey)),
-            error: null_mut()
-        },
-        Err(error) => ResultExport{
-            is_error: 1,
-            result: null_mut(),
-            error: CString::new(error.to_string()).unwrap().into_raw()
-        }
-    }
+extern "C" fn pubkey_try_find_program_address(seeds: *const Seeds<u8>, len: size_t, program_id: Handle) -> ResultExport<ProgramAddress> {
+    export_result(|| {
+        let vec_of_seed = seeds_from_ptr(seeds, len)?;
+        let program_id = handle_table::get::<Pubkey>(program_id)?;
+
+        let program_address_result = Pubkey::try_find_program_address(vec_of_seed.as_slice(),&program_id);
+        Ok(match program_address_result {
+            None => ProgramAddress::export_default(),
+            Some(program_address) => ProgramAddress {
+                pubkey: handle_table::insert(program_address.0),
+                bump_seed: program_address.1
+            }
+        })
+    })
 }
 
 #[no_mangle]
-extern "C" fn pubkey_to_bytes(ptr : *mut Pubkey) -> VecRawParts {
-    let pubkey = unsafe {
-        assert!(!ptr.is_null());
-        &mut *ptr
-    };
+extern "C" fn pubkey_create_program_address(seeds:  *const Seeds<u8>, len: size_t, program_id: Handle)-> ResultExport<Handle> {
+    export_result(|| {
+        let vec_of_seed = seeds_from_ptr(seeds, len)?;
+        let program_id = handle_table::get::<Pubkey>(program_id)?;
+
+        let pubkey = Pubkey::create_program_address(vec_of_seed.as_slice(),&program_id)?;
+        Ok(handle_table::insert(pubkey))
+    })
+}
 
-    to_raw_parts(pubkey.to_bytes().to_vec())
+#[no_mangle]
+extern "C" fn pubkey_to_bytes(pubkey: Handle) -> ResultExport<VecRawParts> {
+    export_result(|| {
+        let pubkey = handle_table::get::<Pubkey>(pubkey)?;
+        Ok(to_raw_parts(pubkey.to_bytes().to_vec()))
+    })
 }
 
 #[no_mangle]
-extern "C" fn pubkey_to_base58(ptr : *mut Pubkey) -> *mut c_char {
-    let pubkey = unsafe {
-        assert!(!ptr.is_null());
-        &mut *ptr
-    };
-    CString::new(pubkey.to_string()).unwrap().into_raw()
+extern "C" fn pubkey_to_base58(pubkey: Handle) -> ResultExport<*mut c_char> {
+    export_result(|| {
+        let pubkey = handle_table::get::<Pubkey>(pubkey)?;
+        c_char_ptr_from_string(pubkey.to_string())
+    })
 }
 
 #[no_mangle]
-extern "C" fn pubkey_is_on_curve(ptr : *mut Pubkey) -> u8 {
-    let pubkey = unsafe {
-        assert!(!ptr.is_null());
-        &mut *ptr
-    };
-    pubkey.is_on_curve() as u8
+extern "C" fn pubkey_is_on_curve(pubkey: Handle) -> ResultExport<u8> {
+    export_result(|| {
+        let pubkey = handle_table::get::<Pubkey>(pubkey)?;
+        Ok(pubkey.is_on_curve() as u8)
+    })
 }
 
 #[no_mangle]
-extern "C" fn free_pubkey(ptr: *mut Pubkey){
-    if ptr.is_null() {
-        return;
-    }
-    unsafe {
-        Box::from_raw(ptr);
-    }
+extern "C" fn free_pubkey(pubkey: Handle) -> ResultExport<u8> {
+    export_result(|| {
+        handle_table::remove::<Pubkey>(pubkey)?;
+        Ok(true as u8)
+    })
 }
@@ -0,0 +1,468 @@
+use std::convert::TryFrom;
+use std::os::raw::c_char;
+use std::ptr::null_mut;
+use std::str::FromStr;
+
+use serde::de::{DeserializeOwned, IgnoredAny};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use solana_program::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_sdk::transaction::TransactionError;
+
+use crate::c_sharp_string::{c_char_ptr_from_string, str_from_c_char_ptr};
+use crate::common_types::{ExportDefault, OptionExport, ResultExport};
+use crate::error_export::{ErrorCode, export_result, ExportError};
+use crate::handle_table::{self, Handle};
+use crate::rpc_export::{config, Commitment, decode_logs, decode_transaction_error, Issue, issue_logs, request_body,
+                        RpcAccount, RpcAccountExport, RpcErrorObject, RpcKeyedAccount, TransactionErrorExport,
+                        WithContext};
+
+/// Subscription type, used to pick the matching unsubscribe method. Passed as a `u8`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SubscriptionKind {
+    Account = 0,
+    Signature = 1,
+    Logs = 2,
+    Program = 3,
+}
+
+impl SubscriptionKind {
+    fn unsubscribe_method(self) -> &'static str {
+        match self {
+            SubscriptionKind::Account => "accountUnsubscribe",
+            SubscriptionKind::Signature => "signatureUnsubscribe",
+            SubscriptionKind::Logs => "logsUnsubscribe",
+            SubscriptionKind::Program => "programUnsubscribe",
+        }
+    }
+}
+
+impl TryFrom<u8> for SubscriptionKind {
+    type Error = ExportError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Ok(match value {
+            0 => SubscriptionKind::Account,
+            1 => SubscriptionKind::Signature,
+            2 => SubscriptionKind::Logs,
+            3 => SubscriptionKind::Program,
+            _ => return Err(ExportError::invalid_argument(format!("Unknown subscription kind {}", value))),
+        })
+    }
+}
+
+/// What a decoded WebSocket frame is, and so which `PubsubMessageExport` fields are set.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PubsubMessageKind {
+    /// Answer to a subscribe request: `request_id` and `subscription`.
+    Subscribed = 0,
+    /// Answer to an unsubscribe request: `request_id`.
+    Unsubscribed = 1,
+    /// `subscription`, `slot` and `account`.
+    AccountNotification = 2,
+    /// `subscription`, `slot`, `pubkey` and `account`.
+    ProgramNotification = 3,
+    /// `subscription`, `slot` and `err`. The subscription ends after this notification.
+    SignatureNotification = 4,
+    /// `subscription` and `slot`, sent when the node received the signature.
+    SignatureReceived = 5,
+    /// `subscription`, `slot`, `signature`, `err` and `logs`.
+    LogsNotification = 6,
+    /// The node rejected a request: `request_id`, `error_code` and `error_message`.
+    Error = 7,
+}
+
+/// Decoded WebSocket frame. Fields not used by `kind` are zero. Handles are released with
+/// their free functions, `account.data` with `free_vec`, `logs`, `err.value.message` and
+/// `error_message` with `free_cstring`.
+#[repr(C)]
+pub struct PubsubMessageExport {
+    pub kind: PubsubMessageKind,
+    pub request_id: u64,
+    pub subscription: u64,
+    pub slot: u64,
+    pub pubkey: Handle,
+    pub account: RpcAccountExport,
+    pub signature: Handle,
+    pub err: OptionExport<TransactionErrorExport>,
+    pub logs: *mut c_char,
+    /// JSON-RPC error code of an `Error` frame.
+    pub error_code: i64,
+    pub error_message: *mut c_char,
+}
+
+impl PubsubMessageExport {
+    fn new(kind: PubsubMessageKind) -> Self {
+        Self {
+            kind,
+            request_id: 0,
+            subscription: 0,
+            slot: 0,
+            pubkey: 0,
+            account: RpcAccountExport::export_default(),
+            signature: 0,
+            err: OptionExport::export_default(),
+            logs: null_mut(),
+            error_code: 0,
+            error_message: null_mut(),
+        }
+    }
+}
+
+impl ExportDefault for PubsubMessageExport {
+    fn export_default() -> Self {
+        Self::new(PubsubMessageKind::Subscribed)
+    }
+}
+
+#[derive(Deserialize)]
+struct Frame {
+    id: Option<u64>,
+    result: Option<Value>,
+    error: Option<RpcErrorObject>,
+    method: Option<String>,
+    params: Option<NotificationParams>,
+}
+
+#[derive(Deserialize)]
+struct NotificationParams {
+    result: Value,
+    subscription: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SignatureNotification {
+    Processed { err: Option<TransactionError> },
+    /// The `"receivedSignature"` string.
+    Received(IgnoredAny),
+}
+
+#[derive(Deserialize)]
+struct LogsNotification {
+    signature: String,
+    err: Option<TransactionError>,
+    logs: Vec<String>,
+}
+
+fn notification<T: DeserializeOwned>(params: NotificationParams) -> Result<(u64, WithContext<T>), ExportError> {
+    Ok((params.subscription, serde_json::from_value(params.result)?))
+}
+
+fn decode_notification(method: &str, params: NotificationParams) -> Result<PubsubMessageExport, ExportError> {
+    match method {
+        "accountNotification" => {
+            let (subscription, result) = notification::<RpcAccount>(params)?;
+            Ok(PubsubMessageExport {
+                subscription,
+                slot: result.context.slot,
+                account: result.value.to_export()?,
+                ..PubsubMessageExport::new(PubsubMessageKind::AccountNotification)
+            })
+        }
+        "programNotification" => {
+            let (subscription, result) = notification::<RpcKeyedAccount>(params)?;
+            // Decode everything before issuing the pubkey handle, so a bad account leaks nothing.
+            let pubkey = Pubkey::from_str(&result.value.pubkey)?;
+            let account = result.value.account.decode()?;
+            Ok(PubsubMessageExport {
+                subscription,
+                slot: result.context.slot,
+                pubkey: handle_table::insert(pubkey),
+                account: account.issue(),
+                ..PubsubMessageExport::new(PubsubMessageKind::ProgramNotification)
+            })
+        }
+        "signatureNotification" => {
+            let (subscription, result) = notification::<SignatureNotification>(params)?;
+            let message = PubsubMessageExport {
+                subscription,
+                slot: result.context.slot,
+                ..PubsubMessageExport::new(PubsubMessageKind::SignatureReceived)
+            };
+            Ok(match result.value {
+                SignatureNotification::Received(_) => message,
+                SignatureNotification::Processed { err } => PubsubMessageExport {
+                    kind: PubsubMessageKind::SignatureNotification,
+                    err: decode_transaction_error(err.as_ref())?.issue(),
+                    ..message
+                },
+            })
+        }
+        "logsNotification" => {
+            let (subscription, result) = notification::<LogsNotification>(params)?;
+            let signature = Signature::from_str(&result.value.signature)?;
+            let err = decode_transaction_error(result.value.err.as_ref())?;
+            let logs = decode_logs(Some(&result.value.logs))?;
+            Ok(PubsubMessageExport {
+                subscription,
+                slot: result.context.slot,
+                signature: handle_table::insert(signature),
+                err: err.issue(),
+                logs: issue_logs(logs),
+                ..PubsubMessageExport::new(PubsubMessageKind::LogsNotification)
+            })
+        }
+        _ => Err(ExportError::new(ErrorCode::InvalidJson, format!("Unknown notification {}", method))),
+    }
+}
+
+fn decode_frame(frame: &str) -> Result<PubsubMessageExport, ExportError> {
+    let frame: Frame = serde_json::from_str(frame)?;
+    if let (Some(method), Some(params)) = (frame.method, frame.params) {
+        return decode_notification(&method, params);
+    }
+
+    let request_id = match (frame.id, frame.error) {
+        (Some(request_id), Some(error)) => return Ok(PubsubMessageExport {
+            request_id,
+            error_code: error.code,
+            error_message: c_char_ptr_from_string(error.message)?,
+            ..PubsubMessageExport::new(PubsubMessageKind::Error)
+        }),
+        // Without an id, e.g. for a frame the node could not parse, no request can be blamed.
+        (None, Some(error)) => return Err(error.into()),
+        (Some(request_id), None) => request_id,
+        (None, None) => return Err(ExportError::new(ErrorCode::InvalidJson, "Frame is neither a response nor a notification")),
+    };
+    match frame.result {
+        Some(Value::Number(subscription)) => Ok(PubsubMessageExport {
+            request_id,
+            subscription: subscription.as_u64()
+                .ok_or_else(|| ExportError::new(ErrorCode::InvalidJson, "Subscription id is not a u64"))?,
+            ..PubsubMessageExport::new(PubsubMessageKind::Subscribed)
+        }),
+        Some(Value::Bool(_)) => Ok(PubsubMessageExport {
+            request_id,
+            ..PubsubMessageExport::new(PubsubMessageKind::Unsubscribed)
+        }),
+        _ => Err(ExportError::new(ErrorCode::InvalidJson, "Response has no subscription result")),
+    }
+}
+
+/// Notifies about changes to the account, with base64 data.
+#[no_mangle]
+extern "C" fn pubsub_account_subscribe_request(id: u64, pubkey: Handle, commitment: u8) -> ResultExport<*mut c_char> {
+    export_result(|| {
+        let pubkey = handle_table::get::<Pubkey>(pubkey)?;
+        let config = config(Commitment::try_from(commitment)?, json!({ "encoding": "base64" }));
+        request_body(id, "accountSubscribe", json!([pubkey.to_string(), config]))
+    })
+}
+
+/// Notifies once the transaction reaches `commitment`. With `received_notification` set,
+/// the node also notifies as soon as it receives the transaction.
+#[no_mangle]
+extern "C" fn pubsub_signature_subscribe_request(id: u64, signature: Handle, commitment: u8,
+                                                 received_notification: u8) -> ResultExport<*mut c_char> {
+    export_result(|| {
+        let signature = handle_table::get::<Signature>(signature)?;
+        let config = config(Commitment::try_from(commitment)?,
+                            json!({ "enableReceivedNotification": received_notification != 0 }));
+        request_body(id, "signatureSubscribe", json!([signature.to_string(), config]))
+    })
+}
+
+/// Notifies about the logs of transactions mentioning `mentions`, or of all non-vote
+/// transactions when `mentions` is 0.
+#[no_mangle]
+extern "C" fn pubsub_logs_subscribe_request(id: u64, mentions: Handle, commitment: u8) -> ResultExport<*mut c_char> {
+    export_result(|| {
+        let filter = match mentions {
+            0 => json!("all"),
+            mentions => json!({ "mentions": [handle_table::get::<Pubkey>(mentions)?.to_string()] }),
+        };
+        request_body(id, "logsSubscribe", json!([filter, config(Commitment::try_from(commitment)?, Value::Null)]))
+    })
+}
+
+/// Notifies about changes to accounts owned by the program, with base64 data.
+#[no_mangle]
+extern "C" fn pubsub_program_subscribe_request(id: u64, program_id: Handle, commitment: u8) -> ResultExport<*mut c_char> {
+    export_result(|| {
+        let program_id = handle_table::get::<Pubkey>(program_id)?;
+        let config = config(Commitment::try_from(commitment)?, json!({ "encoding": "base64" }));
+        request_body(id, "programSubscribe", json!([program_id.to_string(), config]))
+    })
+}
+
+#[no_mangle]
+extern "C" fn pubsub_unsubscribe_request(id: u64, kind: u8, subscription: u64) -> ResultExport<*mut c_char> {
+    export_result(|| {
+        let kind = SubscriptionKind::try_from(kind)?;
+        request_body(id, kind.unsubscribe_method(), json!([subscription]))
+    })
+}
+
+/// Classifies an incoming WebSocket text frame. A rejected request decodes as an `Error`
+/// frame carrying its `request_id`; an error without a request id fails with `RpcError`.
+#[no_mangle]
+extern "C" fn pubsub_decode_message(frame: *const c_char) -> ResultExport<PubsubMessageExport> {
+    export_result(|| decode_frame(str_from_c_char_ptr(frame)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CString;
+
+    use solana_sdk::instruction::InstructionError;
+
+    use super::*;
+    use crate::common_types::from_raw_parts;
+
+    const PUBKEY: &str = "So11111111111111111111111111111111111111112";
+    const OWNER: &str = "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA";
+    const SIGNATURE: &str = "5VERv8NMvzbJMEkV8xnrLkEaWRtSz9CosKDYjCJjBRnbJLgp8uirBgmQpjKhoR4tjF3ZpRzrFmBV6UjKdiSZkQUW";
+
+    fn notification(method: &str, value: &str) -> String {
+        format!(r#"{{"jsonrpc":"2.0","method":"{}","params":{{"result":{{"context":{{"slot":5199307}},"value":{}}},"subscription":23784}}}}"#,
+                method, value)
+    }
+
+    fn account(data: &str) -> String {
+        format!(r#"{{"data":[{:?},"base64"],"executable":false,"lamports":33594,"owner":"{}","rentEpoch":635,"space":3}}"#,
+                data, OWNER)
+    }
+
+    fn decode(frame: &str) -> ResultExport<PubsubMessageExport> {
+        let frame = CString::new(frame).unwrap();
+        pubsub_decode_message(frame.as_ptr())
+    }
+
+    fn decoded(frame: &str) -> PubsubMessageExport {
+        let result = decode(frame);
+        assert_eq!((result.is_error, result.error_code), (0, ErrorCode::Ok), "{}", frame);
+        result.result
+    }
+
+    fn take_string(ptr: *mut c_char) -> String {
+        assert!(!ptr.is_null());
+        unsafe { CString::from_raw(ptr) }.into_string().unwrap()
+    }
+
+    fn take_account(account: &RpcAccountExport) -> (Pubkey, Vec<u8>) {
+        let owner = handle_table::get::<Pubkey>(account.owner).unwrap();
+        handle_table::remove::<Pubkey>(account.owner).unwrap();
+        (owner, from_raw_parts(&account.data))
+    }
+
+    #[test]
+    fn account_notification() {
+        let message = decoded(&notification("accountNotification", &account("AQID")));
+        assert_eq!(message.kind, PubsubMessageKind::AccountNotification);
+        assert_eq!((message.subscription, message.slot), (23784, 5199307));
+        assert_eq!((message.account.lamports, message.account.executable, message.account.rent_epoch), (33594, 0, 635));
+        assert_eq!(take_account(&message.account), (Pubkey::from_str(OWNER).unwrap(), vec![1, 2, 3]));
+        assert_eq!((message.pubkey, message.signature), (0, 0));
+        assert!(message.logs.is_null());
+    }
+
+    #[test]
+    fn program_notification() {
+        let value = format!(r#"{{"pubkey":"{}","account":{}}}"#, PUBKEY, account(""));
+        let message = decoded(&notification("programNotification", &value));
+        assert_eq!(message.kind, PubsubMessageKind::ProgramNotification);
+        assert_eq!((message.subscription, message.slot), (23784, 5199307));
+        assert_eq!(handle_table::get::<Pubkey>(message.pubkey).unwrap(), Pubkey::from_str(PUBKEY).unwrap());
+        handle_table::remove::<Pubkey>(message.pubkey).unwrap();
+        assert_eq!(take_account(&message.account), (Pubkey::from_str(OWNER).unwrap(), vec![]));
+
+        let bad_account = format!(r#"{{"pubkey":"{}","account":{}}}"#, PUBKEY, account("not base64!"));
+        assert_eq!(decode(&notification("programNotification", &bad_account)).is_error, 1);
+    }
+
+    #[test]
+    fn signature_processed_notification() {
+        let message = decoded(&notification("signatureNotification", r#"{"err":null}"#));
+        assert_eq!(message.kind, PubsubMessageKind::SignatureNotification);
+        assert_eq!((message.subscription, message.slot), (23784, 5199307));
+        assert_eq!(message.err.has_value, 0);
+
+        let failed = decoded(&notification("signatureNotification", r#"{"err":{"InstructionError":[0,{"Custom":1}]}}"#));
+        assert_eq!(failed.kind, PubsubMessageKind::SignatureNotification);
+        assert_eq!(failed.err.has_value, 1);
+        let err = failed.err.value;
+        assert_eq!((err.instruction_index.has_value, err.instruction_index.value), (1, 0));
+        assert_eq!((err.custom_code.has_value, err.custom_code.value), (1, 1));
+        assert_eq!(take_string(err.message),
+                   TransactionError::InstructionError(0, InstructionError::Custom(1)).to_string());
+    }
+
+    #[test]
+    fn signature_received_notification() {
+        let message = decoded(&notification("signatureNotification", r#""receivedSignature""#));
+        assert_eq!(message.kind, PubsubMessageKind::SignatureReceived);
+        assert_eq!((message.subscription, message.slot), (23784, 5199307));
+        assert_eq!(message.err.has_value, 0);
+    }
+
+    #[test]
+    fn logs_notification() {
+        let value = format!(r#"{{"signature":"{}","err":null,"logs":["Program {} invoke [1]","Program {} success"]}}"#,
+                            SIGNATURE, OWNER, OWNER);
+        let message = decoded(&notification("logsNotification", &value));
+        assert_eq!(message.kind, PubsubMessageKind::LogsNotification);
+        assert_eq!((message.subscription, message.slot), (23784, 5199307));
+        assert_eq!(handle_table::get::<Signature>(message.signature).unwrap(), Signature::from_str(SIGNATURE).unwrap());
+        handle_table::remove::<Signature>(message.signature).unwrap();
+        assert_eq!(message.err.has_value, 0);
+        assert_eq!(take_string(message.logs), format!("Program {} invoke [1]\nProgram {} success", OWNER, OWNER));
+
+        let bad_signature = r#"{"signature":"nope","err":null,"logs":[]}"#;
+        assert_eq!(decode(&notification("logsNotification", bad_signature)).is_error, 1);
+    }
+
+    #[test]
+    fn subscribe_and_unsubscribe_responses() {
+        let message = decoded(r#"{"jsonrpc":"2.0","result":23784,"id":1}"#);
+        assert_eq!(message.kind, PubsubMessageKind::Subscribed);
+        assert_eq!((message.request_id, message.subscription), (1, 23784));
+
+        let message = decoded(r#"{"jsonrpc":"2.0","result":true,"id":2}"#);
+        assert_eq!(message.kind, PubsubMessageKind::Unsubscribed);
+        assert_eq!((message.request_id, message.subscription), (2, 0));
+
+        assert_eq!(decode(r#"{"jsonrpc":"2.0","result":-1,"id":3}"#).error_code, ErrorCode::InvalidJson);
+        assert_eq!(decode(r#"{"jsonrpc":"2.0","result":"23784","id":3}"#).error_code, ErrorCode::InvalidJson);
+    }
+
+    #[test]
+    fn error_frame_with_id() {
+        let message = decoded(r#"{"jsonrpc":"2.0","error":{"code":-32602,"message":"Invalid param: Invalid"},"id":4}"#);
+        assert_eq!(message.kind, PubsubMessageKind::Error);
+        assert_eq!((message.request_id, message.error_code), (4, -32602));
+        assert_eq!(take_string(message.error_message), "Invalid param: Invalid");
+    }
+
+    #[test]
+    fn error_frame_without_id() {
+        for frame in [
+            r#"{"jsonrpc":"2.0","error":{"code":-32700,"message":"Parse error"},"id":null}"#,
+            r#"{"jsonrpc":"2.0","error":{"code":-32700,"message":"Parse error"}}"#,
+        ] {
+            let result = decode(frame);
+            assert_eq!((result.is_error, result.error_code), (1, ErrorCode::RpcError), "{}", frame);
+        }
+    }
+
+    #[test]
+    fn unknown_and_malformed_frames() {
+        let result = decode(&notification("slotNotification", r#"{"parent":1,"root":0,"slot":2}"#));
+        assert_eq!((result.is_error, result.error_code), (1, ErrorCode::InvalidJson));
+        for frame in ["", "[]", r#"{"jsonrpc":"2.0"}"#, r#"{"jsonrpc":"2.0","method":"accountNotification","params":{}}"#] {
+            assert_eq!(decode(frame).error_code, ErrorCode::InvalidJson, "{}", frame);
+        }
+    }
+
+    #[test]
+    fn unsubscribe_request_matches_the_kind() {
+        let request = pubsub_unsubscribe_request(7, SubscriptionKind::Logs as u8, 23784);
+        assert_eq!(request.is_error, 0);
+        let request: Value = serde_json::from_str(&take_string(request.result)).unwrap();
+        assert_eq!(request, json!({"jsonrpc": "2.0", "id": 7, "method": "logsUnsubscribe", "params": [23784]}));
+        assert_eq!(pubsub_unsubscribe_request(7, 4, 23784).error_code, ErrorCode::InvalidArgument);
+    }
+}
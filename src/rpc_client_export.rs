@@ -0,0 +1,534 @@
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryFrom;
+use std::ffi::{c_void, CString};
+use std::os::raw::c_char;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use libc::size_t;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use solana_program::hash::{hash, Hash};
+use solana_program::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_sdk::transaction::{Transaction, VersionedTransaction};
+
+use crate::c_sharp_string::{str_from_c_char_ptr, string_from_c_char_ptr};
+use crate::common_types::{from_raw_parts, OptionExport, ResultExport, VecRawParts};
+use crate::confirmation_export::{ConfirmationState, ConfirmationTracker};
+use crate::error_export::{ErrorCode, export_result, ExportError};
+use crate::handle_table::{self, Handle, HandleObject, HandleType};
+use crate::rpc_export::{config, Commitment, LatestBlockhashExport, option_export, parse_response, request_json,
+                        RpcBlockhash, RpcSignatureStatus, serialized_transaction, SignatureStatusExport, WithContext};
+use crate::transaction_export::keypairs_from_ptr;
+
+/// Sends the JSON-RPC `request` to `url` and blocks until the response arrives. The
+/// response body is written to `response`, allocated with `init_vec`. Returns 0 on
+/// success; any other value fails the request with `TransportFailed`.
+///
+/// The callback runs on the thread of the export that issued the request. The client
+/// handle is not locked meanwhile, so other threads may use it concurrently.
+pub type TransportCallback = extern "C" fn(user_data: *mut c_void, url: *const c_char, request: *const u8,
+                                           request_len: size_t, response: *mut VecRawParts) -> i32;
+
+/// Poll interval of `rpc_client_send_and_confirm_transaction` when passed 0, about one slot.
+pub const DEFAULT_POLL_INTERVAL_MS: u32 = 400;
+
+/// Blocks a mock transaction needs on top of its slot to be reported as finalized.
+const MOCK_FINALIZATION_DEPTH: u64 = 32;
+/// Blocks a blockhash stays valid for, as on mainnet.
+const MOCK_BLOCKHASH_VALIDITY: u64 = 150;
+
+/// In-process stand-in for a validator, for tests and offline play.
+///
+/// Every request advances the chain by one block. Transactions sent to it must be
+/// signed over a blockhash it handed out that has not expired yet; they are then
+/// reported as processed, confirmed one block later and finalized after
+/// `MOCK_FINALIZATION_DEPTH` blocks. Responses queued with
+/// `rpc_client_mock_push_response` take precedence over the built-in ones.
+pub struct MockTransport {
+    block_height: u64,
+    /// Blockhashes handed out so far with their last valid block height, newest last.
+    blockhashes: Vec<(Hash, u64)>,
+    sent: HashMap<Signature, u64>,
+    queued: HashMap<String, VecDeque<Value>>,
+    /// Transactions still to be accepted but never landed, see `rpc_client_mock_drop_transactions`.
+    dropped_transactions: u32,
+}
+
+impl MockTransport {
+    fn new() -> Self {
+        Self {
+            block_height: 0,
+            blockhashes: Vec::new(),
+            sent: HashMap::new(),
+            queued: HashMap::new(),
+            dropped_transactions: 0,
+        }
+    }
+
+    /// Produces a new blockhash every block, like a validator does.
+    fn advance(&mut self) {
+        self.block_height += 1;
+        let block_height = self.block_height;
+        self.blockhashes.retain(|(_, last_valid_block_height)| *last_valid_block_height >= block_height);
+        self.blockhashes.push((hash(&block_height.to_le_bytes()), block_height + MOCK_BLOCKHASH_VALIDITY));
+    }
+
+    fn context(&self, value: Value) -> Value {
+        json!({ "context": { "slot": self.block_height }, "value": value })
+    }
+
+    fn send_transaction(&mut self, params: &Value) -> Result<Value, (i64, String)> {
+        let transaction = params[0].as_str()
+            .and_then(|transaction| base64::decode(transaction).ok())
+            .and_then(|transaction| bincode::deserialize::<VersionedTransaction>(&transaction).ok())
+            .ok_or((-32602, "invalid transaction: failed to deserialize".to_string()))?;
+        let signature = *transaction.signatures.first()
+            .ok_or((-32602, "invalid transaction: transaction has no signatures".to_string()))?;
+        if !transaction.verify_with_results().iter().all(|verified| *verified) {
+            return Err((-32003, "Transaction signature verification failure".to_string()));
+        }
+        if !self.blockhashes.iter().any(|(blockhash, _)| blockhash == transaction.message.recent_blockhash()) {
+            return Err((-32002, "Transaction simulation failed: Blockhash not found".to_string()));
+        }
+        if self.dropped_transactions > 0 {
+            self.dropped_transactions -= 1;
+        } else {
+            self.sent.entry(signature).or_insert(self.block_height);
+        }
+        Ok(Value::from(signature.to_string()))
+    }
+
+    fn signature_status(&self, signature: &str) -> Value {
+        let slot = match signature.parse::<Signature>().ok().and_then(|signature| self.sent.get(&signature)) {
+            Some(slot) => *slot,
+            None => return Value::Null,
+        };
+        let depth = self.block_height - slot;
+        let (confirmations, status) = match depth {
+            0 => (Value::from(0), "processed"),
+            depth if depth < MOCK_FINALIZATION_DEPTH => (Value::from(depth), "confirmed"),
+            _ => (Value::Null, "finalized"),
+        };
+        json!({
+            "slot": slot,
+            "confirmations": confirmations,
+            "err": null,
+            "status": { "Ok": null },
+            "confirmationStatus": status,
+        })
+    }
+
+    fn handle(&mut self, method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        self.advance();
+        match method {
+            "getLatestBlockhash" => {
+                let (blockhash, last_valid_block_height) = self.blockhashes[self.blockhashes.len() - 1];
+                Ok(self.context(json!({
+                    "blockhash": blockhash.to_string(),
+                    "lastValidBlockHeight": last_valid_block_height,
+                })))
+            }
+            "getBlockHeight" => Ok(Value::from(self.block_height)),
+            "getBalance" => Ok(self.context(Value::from(0))),
+            "getAccountInfo" => Ok(self.context(Value::Null)),
+            "sendTransaction" => self.send_transaction(params),
+            "getSignatureStatuses" => {
+                let statuses: Vec<Value> = params[0].as_array()
+                    .map(|signatures| signatures
+                        .iter()
+                        .map(|signature| self.signature_status(signature.as_str().unwrap_or_default()))
+                        .collect())
+                    .unwrap_or_default();
+                Ok(self.context(Value::from(statuses)))
+            }
+            _ => Err((-32601, "Method not found".to_string())),
+        }
+    }
+
+    fn send(&mut self, request: &str) -> Result<String, ExportError> {
+        let request: Value = serde_json::from_str(request)?;
+        let method = request["method"].as_str().unwrap_or_default().to_string();
+        let queued = self.queued.get_mut(&method).and_then(VecDeque::pop_front);
+        let mut response = match queued {
+            Some(response) => response,
+            None => match self.handle(&method, &request["params"]) {
+                Ok(result) => json!({ "jsonrpc": "2.0", "result": result }),
+                Err((code, message)) => json!({ "jsonrpc": "2.0", "error": { "code": code, "message": message } }),
+            },
+        };
+        response["id"] = request["id"].clone();
+        Ok(response.to_string())
+    }
+}
+
+#[derive(Clone)]
+pub enum Transport {
+    Callback {
+        callback: TransportCallback,
+        /// `user_data` pointer, only ever handed back to the callback.
+        user_data: usize,
+    },
+    Mock(Arc<Mutex<MockTransport>>),
+}
+
+impl Transport {
+    fn send(&self, url: &str, request: String) -> Result<String, ExportError> {
+        match self {
+            Transport::Callback { callback, user_data } => {
+                let url = CString::new(url)?;
+                let mut response = VecRawParts::default();
+                let status = callback(*user_data as *mut c_void, url.as_ptr(), request.as_ptr(), request.len(), &mut response);
+                let response = from_raw_parts::<u8>(&response);
+                if status != 0 {
+                    return Err(ExportError::new(ErrorCode::TransportFailed,
+                        format!("Transport failed with status {} for {}", status, url.to_string_lossy())));
+                }
+                String::from_utf8(response).map_err(|error| ExportError::new(ErrorCode::InvalidUtf8, error.to_string()))
+            }
+            Transport::Mock(mock) => mock.lock().unwrap_or_else(PoisonError::into_inner).send(&request),
+        }
+    }
+}
+
+/// Cheap to clone; clones share the transport and request ids. Exports work on a clone,
+/// so the client handle is never locked while a request is in flight.
+#[derive(Clone)]
+pub struct RpcClient {
+    url: String,
+    transport: Transport,
+    next_id: Arc<AtomicU64>,
+}
+
+impl HandleObject for RpcClient {
+    const HANDLE_TYPE: HandleType = HandleType::RpcClient;
+}
+
+impl RpcClient {
+    fn new(url: String, transport: Transport) -> Self {
+        Self {
+            url,
+            transport,
+            next_id: Arc::new(AtomicU64::new(1)),
+        }
+    }
+
+    fn with_mock<R>(&self, f: impl FnOnce(&mut MockTransport) -> R) -> Result<R, ExportError> {
+        match &self.transport {
+            Transport::Mock(mock) => Ok(f(&mut mock.lock().unwrap_or_else(PoisonError::into_inner))),
+            Transport::Callback { .. } => Err(ExportError::invalid_argument("Client does not use a mock transport")),
+        }
+    }
+
+    pub fn call<T: DeserializeOwned>(&self, method: &str, params: Value) -> Result<T, ExportError> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let response = self.transport.send(&self.url, request_json(id, method, params))?;
+        parse_response(&response)
+    }
+
+    pub fn latest_blockhash(&self, commitment: Commitment) -> Result<WithContext<RpcBlockhash>, ExportError> {
+        self.call("getLatestBlockhash", json!([config(commitment, Value::Null)]))
+    }
+
+    pub fn block_height(&self, commitment: Commitment) -> Result<u64, ExportError> {
+        self.call("getBlockHeight", json!([config(commitment, Value::Null)]))
+    }
+
+    pub fn send_transaction(&self, transaction: &[u8], skip_preflight: bool,
+                            preflight_commitment: Commitment) -> Result<Signature, ExportError> {
+        let mut config = json!({ "encoding": "base64", "skipPreflight": skip_preflight });
+        if let Some(commitment) = preflight_commitment.as_str() {
+            config["preflightCommitment"] = Value::from(commitment);
+        }
+        let signature: String = self.call("sendTransaction", json!([base64::encode(transaction), config]))?;
+        Ok(signature.parse()?)
+    }
+
+    pub fn signature_status(&self, signature: &Signature) -> Result<Option<RpcSignatureStatus>, ExportError> {
+        let mut statuses: WithContext<Vec<Option<RpcSignatureStatus>>> =
+            self.call("getSignatureStatuses", json!([[signature.to_string()]]))?;
+        Ok(statuses.value.pop().flatten())
+    }
+}
+
+fn poll_interval_ms_or_default(poll_interval_ms: u32) -> u64 {
+    match poll_interval_ms {
+        0 => DEFAULT_POLL_INTERVAL_MS,
+        poll_interval_ms => poll_interval_ms,
+    }.into()
+}
+
+/// Sends `transaction` and polls until it reaches `commitment` or its blockhash expires,
+/// sending it again whenever the tracker asks for it. Only the first send runs preflight.
+fn confirm_transaction(client: &RpcClient, transaction: &Transaction, last_valid_block_height: u64,
+                       commitment: Commitment, poll_interval: Duration) -> Result<Signature, ExportError> {
+    let bytes = bincode::serialize(transaction)?;
+    let signature = client.send_transaction(&bytes, false, commitment)?;
+    let mut tracker = ConfirmationTracker::new(signature, last_valid_block_height, commitment, 0);
+    tracker.on_broadcast();
+    loop {
+        tracker.on_status(client.signature_status(&signature)?.as_ref())?;
+        if !tracker.is_done() && tracker.on_block_height(client.block_height(commitment)?) == ConfirmationState::Expired {
+            // The transaction may have landed between the two polls, so check once more
+            // before the caller is told to sign and send it again.
+            tracker.on_status(client.signature_status(&signature)?.as_ref())?;
+        }
+        match tracker.state() {
+            ConfirmationState::Failed => return Err(ExportError::new(ErrorCode::TransactionFailed, format!(
+                "Transaction {} failed: {}", signature, tracker.err().map(ToString::to_string).unwrap_or_default()))),
+            ConfirmationState::Expired => return Err(ExportError::new(ErrorCode::BlockhashExpired,
+                format!("Blockhash of transaction {} expired before it was confirmed", signature))),
+            _ if tracker.is_done() => return Ok(signature),
+            _ => {}
+        }
+        if tracker.should_rebroadcast() {
+            client.send_transaction(&bytes, true, commitment)?;
+            tracker.on_broadcast();
+        }
+        std::thread::sleep(poll_interval);
+    }
+}
+
+/// Creates a client that sends every request through `callback`.
+#[no_mangle]
+extern "C" fn rpc_client_new(url: *const c_char, callback: Option<TransportCallback>, user_data: *mut c_void) -> ResultExport<Handle> {
+    export_result(|| {
+        let callback = callback.ok_or_else(|| ExportError::null_pointer("callback"))?;
+        let transport = Transport::Callback { callback, user_data: user_data as usize };
+        Ok(handle_table::insert(RpcClient::new(string_from_c_char_ptr(url)?, transport)))
+    })
+}
+
+/// Creates a client backed by an in-process `MockTransport`.
+#[no_mangle]
+extern "C" fn rpc_client_new_mock() -> ResultExport<Handle> {
+    export_result(|| {
+        let transport = Transport::Mock(Arc::new(Mutex::new(MockTransport::new())));
+        Ok(handle_table::insert(RpcClient::new("mock://".to_string(), transport)))
+    })
+}
+
+/// Queues a full JSON-RPC response for the next `method` request to a mock client.
+/// Its `id` is replaced with the id of the request it answers.
+#[no_mangle]
+extern "C" fn rpc_client_mock_push_response(client: Handle, method: *const c_char, response: *const c_char) -> ResultExport<u8> {
+    export_result(|| {
+        let method = string_from_c_char_ptr(method)?;
+        let response: Value = serde_json::from_str(str_from_c_char_ptr(response)?)?;
+        handle_table::get::<RpcClient>(client)?.with_mock(|mock| {
+            mock.queued.entry(method).or_default().push_back(response);
+            true as u8
+        })
+    })
+}
+
+/// Makes a mock client accept the next `count` transactions without ever landing them, as
+/// a congested leader would, so they have to be sent again or expire.
+#[no_mangle]
+extern "C" fn rpc_client_mock_drop_transactions(client: Handle, count: u32) -> ResultExport<u8> {
+    export_result(|| {
+        handle_table::get::<RpcClient>(client)?.with_mock(|mock| {
+            mock.dropped_transactions = count;
+            true as u8
+        })
+    })
+}
+
+#[no_mangle]
+extern "C" fn rpc_client_get_latest_blockhash(client: Handle, commitment: u8) -> ResultExport<LatestBlockhashExport> {
+    export_result(|| {
+        let commitment = Commitment::try_from(commitment)?;
+        handle_table::get::<RpcClient>(client)?.latest_blockhash(commitment)?.to_export()
+    })
+}
+
+#[no_mangle]
+extern "C" fn rpc_client_get_block_height(client: Handle, commitment: u8) -> ResultExport<u64> {
+    export_result(|| {
+        let commitment = Commitment::try_from(commitment)?;
+        handle_table::get::<RpcClient>(client)?.block_height(commitment)
+    })
+}
+
+#[no_mangle]
+extern "C" fn rpc_client_get_balance(client: Handle, pubkey: Handle, commitment: u8) -> ResultExport<u64> {
+    export_result(|| {
+        let pubkey = handle_table::get::<Pubkey>(pubkey)?;
+        let commitment = Commitment::try_from(commitment)?;
+        let balance: WithContext<u64> = handle_table::get::<RpcClient>(client)?
+            .call("getBalance", json!([pubkey.to_string(), config(commitment, Value::Null)]))?;
+        Ok(balance.value)
+    })
+}
+
+/// Sends a signed `Transaction` or `VersionedTransaction` and returns its signature.
+#[no_mangle]
+extern "C" fn rpc_client_send_transaction(client: Handle, transaction: Handle, skip_preflight: u8,
+                                          preflight_commitment: u8) -> ResultExport<Handle> {
+    export_result(|| {
+        let transaction = serialized_transaction(transaction)?;
+        let preflight_commitment = Commitment::try_from(preflight_commitment)?;
+        let signature = handle_table::get::<RpcClient>(client)?
+            .send_transaction(&transaction, skip_preflight != 0, preflight_commitment)?;
+        Ok(handle_table::insert(signature))
+    })
+}
+
+/// Empty when the node does not know the signature.
+#[no_mangle]
+extern "C" fn rpc_client_get_signature_status(client: Handle, signature: Handle) -> ResultExport<OptionExport<SignatureStatusExport>> {
+    export_result(|| {
+        let signature = handle_table::get::<Signature>(signature)?;
+        let status = handle_table::get::<RpcClient>(client)?.signature_status(&signature)?;
+        Ok(option_export(status.as_ref().map(RpcSignatureStatus::to_export).transpose()?))
+    })
+}
+
+/// Fetches a blockhash, signs the `Transaction` with `keypairs`, sends it and waits until
+/// it reaches `commitment`, polling every `poll_interval_ms`, or `DEFAULT_POLL_INTERVAL_MS`
+/// when 0. The signed transaction is stored back into `transaction`. Fails with
+/// `TransactionFailed` or `BlockhashExpired`.
+///
+/// Blocks the calling thread until then, which can take as long as the blockhash stays
+/// valid, about a minute. Call it from a worker thread, not from Unity's main thread.
+#[no_mangle]
+extern "C" fn rpc_client_send_and_confirm_transaction(client: Handle, transaction: Handle, keypairs: *const Handle,
+                                                      keypairs_len: size_t, commitment: u8,
+                                                      poll_interval_ms: u32) -> ResultExport<Handle> {
+    export_result(|| {
+        let keypairs = keypairs_from_ptr(keypairs, keypairs_len)?;
+        let commitment = Commitment::try_from(commitment)?;
+        let mut signed = handle_table::get::<Transaction>(transaction)?;
+        let client = handle_table::get::<RpcClient>(client)?;
+        let latest = client.latest_blockhash(commitment)?;
+        signed.try_sign(&keypairs.iter().collect::<Vec<_>>(), latest.blockhash()?)?;
+        handle_table::with(transaction, |transaction: &mut Transaction| *transaction = signed.clone())?;
+        let signature = confirm_transaction(&client, &signed, latest.value.last_valid_block_height, commitment,
+                                            Duration::from_millis(poll_interval_ms_or_default(poll_interval_ms)))?;
+        Ok(handle_table::insert(signature))
+    })
+}
+
+#[no_mangle]
+extern "C" fn free_rpc_client(client: Handle) -> ResultExport<u8> {
+    export_result(|| {
+        handle_table::remove::<RpcClient>(client)?;
+        Ok(true as u8)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_program::system_instruction;
+    use solana_sdk::message::Message;
+    use solana_sdk::signature::{Keypair, Signer};
+
+    use super::*;
+    use crate::confirmation_export::DEFAULT_REBROADCAST_INTERVAL_BLOCKS;
+
+    struct Fixture {
+        client: Handle,
+        transaction: Handle,
+        payer: Handle,
+    }
+
+    impl Fixture {
+        fn new() -> Self {
+            let payer = Keypair::new();
+            let instruction = system_instruction::transfer(&payer.pubkey(), &Pubkey::new_unique(), 1_000);
+            let transaction = Transaction::new_unsigned(Message::new(&[instruction], Some(&payer.pubkey())));
+            Self {
+                client: rpc_client_new_mock().result,
+                transaction: handle_table::insert(transaction),
+                payer: handle_table::insert(payer),
+            }
+        }
+
+        fn send_and_confirm(&self, commitment: Commitment) -> ResultExport<Handle> {
+            rpc_client_send_and_confirm_transaction(self.client, self.transaction, &self.payer, 1, commitment as u8, 1)
+        }
+
+        fn with_mock<R>(&self, f: impl FnOnce(&mut MockTransport) -> R) -> R {
+            handle_table::get::<RpcClient>(self.client).unwrap().with_mock(f).unwrap()
+        }
+    }
+
+    #[test]
+    fn send_and_confirm_reaches_commitment() {
+        let fixture = Fixture::new();
+        for commitment in [Commitment::Processed, Commitment::Confirmed, Commitment::Finalized] {
+            let result = fixture.send_and_confirm(commitment);
+            assert_eq!((result.is_error, result.error_code), (0, ErrorCode::Ok));
+
+            let signature = handle_table::get::<Signature>(result.result).unwrap();
+            let signed = handle_table::get::<Transaction>(fixture.transaction).unwrap();
+            assert_eq!(signed.signatures, vec![signature]);
+            let status = rpc_client_get_signature_status(fixture.client, result.result).result;
+            assert_eq!(status.has_value, 1);
+            assert!(status.value.confirmation_status >= commitment);
+        }
+    }
+
+    #[test]
+    fn send_and_confirm_rebroadcasts_dropped_transaction() {
+        let fixture = Fixture::new();
+        assert_eq!(rpc_client_mock_drop_transactions(fixture.client, 1).result, 1);
+
+        let result = fixture.send_and_confirm(Commitment::Confirmed);
+        assert_eq!((result.is_error, result.error_code), (0, ErrorCode::Ok));
+        // The first send was dropped, so the mock only knows the signature from a rebroadcast.
+        let signature = handle_table::get::<Signature>(result.result).unwrap();
+        assert!(fixture.with_mock(|mock| mock.sent.contains_key(&signature)));
+    }
+
+    #[test]
+    fn send_and_confirm_fails_once_blockhash_expires() {
+        let fixture = Fixture::new();
+        rpc_client_mock_drop_transactions(fixture.client, u32::MAX);
+
+        let result = fixture.send_and_confirm(Commitment::Confirmed);
+        assert_eq!((result.is_error, result.error_code), (1, ErrorCode::BlockhashExpired));
+        // Every send but the first was a rebroadcast, one per interval until expiry.
+        let dropped = u32::MAX - fixture.with_mock(|mock| mock.dropped_transactions);
+        assert!(dropped as u64 > MOCK_BLOCKHASH_VALIDITY / DEFAULT_REBROADCAST_INTERVAL_BLOCKS / 2, "{}", dropped);
+    }
+
+    #[test]
+    fn send_and_confirm_checks_status_again_before_expiring() {
+        let fixture = Fixture::new();
+        // The first status poll misses the transaction and the block height poll is already
+        // past its blockhash, as if it landed between the two.
+        for (method, response) in [
+            ("getSignatureStatuses", r#"{"jsonrpc":"2.0","result":{"context":{"slot":1},"value":[null]}}"#),
+            ("getBlockHeight", r#"{"jsonrpc":"2.0","result":1000000}"#),
+        ] {
+            let (method, response) = (CString::new(method).unwrap(), CString::new(response).unwrap());
+            assert_eq!(rpc_client_mock_push_response(fixture.client, method.as_ptr(), response.as_ptr()).result, 1);
+        }
+
+        let result = fixture.send_and_confirm(Commitment::Confirmed);
+        assert_eq!((result.is_error, result.error_code), (0, ErrorCode::Ok));
+    }
+
+    #[test]
+    fn zero_poll_interval_uses_the_default() {
+        assert_eq!(poll_interval_ms_or_default(0), DEFAULT_POLL_INTERVAL_MS as u64);
+        assert_eq!(poll_interval_ms_or_default(25), 25);
+    }
+
+    #[test]
+    fn mock_rejects_transaction_without_signatures() {
+        let mut mock = MockTransport::new();
+        mock.advance();
+        let mut message = Message::new(&[], Some(&Pubkey::new_unique()));
+        message.recent_blockhash = mock.blockhashes[0].0;
+        let mut transaction = VersionedTransaction::from(Transaction::new_unsigned(message));
+        transaction.signatures.clear();
+        let params = json!([base64::encode(bincode::serialize(&transaction).unwrap())]);
+
+        let (code, _) = mock.send_transaction(&params).unwrap_err();
+        assert_eq!(code, -32602);
+    }
+}
@@ -0,0 +1,972 @@
+use std::convert::TryFrom;
+use std::ffi::CString;
+use std::os::raw::c_char;
+use std::ptr::null_mut;
+use std::str::FromStr;
+
+use libc::size_t;
+use serde::de::{DeserializeOwned, IgnoredAny};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use solana_program::hash::Hash;
+use solana_program::instruction::InstructionError;
+use solana_program::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_sdk::transaction::{Transaction, TransactionError, VersionedTransaction};
+
+use crate::c_sharp_string::{c_char_ptr_from_string, str_from_c_char_ptr};
+use crate::common_types::{ExportDefault, OptionExport, ResultExport, slice_from_ptr, to_raw_parts, VecRawParts};
+use crate::error_export::{ErrorCode, export_result, ExportError};
+use crate::handle_table::{self, Handle, HandleType};
+use crate::pubkey_export::pubkeys_from_ptr;
+
+/// Commitment level of a request or of a reported signature status. Passed as a `u8`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Commitment {
+    /// Leaves the commitment to the node, which uses `Finalized`.
+    Default = 0,
+    Processed = 1,
+    Confirmed = 2,
+    Finalized = 3,
+}
+
+impl Commitment {
+    pub fn as_str(self) -> Option<&'static str> {
+        match self {
+            Commitment::Default => None,
+            Commitment::Processed => Some("processed"),
+            Commitment::Confirmed => Some("confirmed"),
+            Commitment::Finalized => Some("finalized"),
+        }
+    }
+
+    /// Level the node applies, i.e. `Finalized` for `Default`.
+    pub fn effective(self) -> Self {
+        match self {
+            Commitment::Default => Commitment::Finalized,
+            commitment => commitment,
+        }
+    }
+}
+
+impl TryFrom<u8> for Commitment {
+    type Error = ExportError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Ok(match value {
+            0 => Commitment::Default,
+            1 => Commitment::Processed,
+            2 => Commitment::Confirmed,
+            3 => Commitment::Finalized,
+            _ => return Err(ExportError::invalid_argument(format!("Unknown commitment {}", value))),
+        })
+    }
+}
+
+impl FromStr for Commitment {
+    type Err = ExportError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "processed" => Ok(Commitment::Processed),
+            "confirmed" => Ok(Commitment::Confirmed),
+            "finalized" => Ok(Commitment::Finalized),
+            _ => Err(ExportError::new(ErrorCode::InvalidJson, format!("Unknown commitment {}", s))),
+        }
+    }
+}
+
+impl ExportDefault for Commitment {
+    fn export_default() -> Self {
+        Commitment::Default
+    }
+}
+
+#[repr(C)]
+pub struct LatestBlockhashExport {
+    pub blockhash: Handle,
+    pub last_valid_block_height: u64,
+    pub context_slot: u64,
+}
+
+impl ExportDefault for LatestBlockhashExport {
+    fn export_default() -> Self {
+        Self {
+            blockhash: 0,
+            last_valid_block_height: 0,
+            context_slot: 0,
+        }
+    }
+}
+
+/// Account as returned by `getAccountInfo` and friends. `data` is released with `free_vec`.
+#[repr(C)]
+pub struct RpcAccountExport {
+    pub lamports: u64,
+    pub owner: Handle,
+    pub data: VecRawParts,
+    pub executable: u8,
+    pub rent_epoch: u64,
+}
+
+impl ExportDefault for RpcAccountExport {
+    fn export_default() -> Self {
+        Self {
+            lamports: 0,
+            owner: 0,
+            data: VecRawParts::default(),
+            executable: 0,
+            rent_epoch: 0,
+        }
+    }
+}
+
+#[repr(C)]
+pub struct RpcKeyedAccountExport {
+    pub pubkey: Handle,
+    pub account: RpcAccountExport,
+}
+
+/// Decoded `TransactionError`. `message` is released with `free_cstring`; the
+/// instruction index and custom program error code are set for instruction errors.
+#[repr(C)]
+pub struct TransactionErrorExport {
+    pub message: *mut c_char,
+    pub instruction_index: OptionExport<u8>,
+    pub custom_code: OptionExport<u32>,
+}
+
+impl ExportDefault for TransactionErrorExport {
+    fn export_default() -> Self {
+        Self {
+            message: null_mut(),
+            instruction_index: OptionExport::export_default(),
+            custom_code: OptionExport::export_default(),
+        }
+    }
+}
+
+/// Result of `simulateTransaction`. `logs` holds one log line per line and is released
+/// with `free_cstring`; it is null when the node returned no logs.
+#[repr(C)]
+pub struct SimulationExport {
+    pub err: OptionExport<TransactionErrorExport>,
+    pub logs: *mut c_char,
+    pub units_consumed: OptionExport<u64>,
+    pub context_slot: u64,
+}
+
+impl ExportDefault for SimulationExport {
+    fn export_default() -> Self {
+        Self {
+            err: OptionExport::export_default(),
+            logs: null_mut(),
+            units_consumed: OptionExport::export_default(),
+            context_slot: 0,
+        }
+    }
+}
+
+/// Entry of `getSignatureStatuses`. `confirmations` is empty once the block is rooted.
+#[repr(C)]
+pub struct SignatureStatusExport {
+    pub slot: u64,
+    pub confirmations: OptionExport<u64>,
+    pub confirmation_status: Commitment,
+    pub err: OptionExport<TransactionErrorExport>,
+}
+
+impl ExportDefault for SignatureStatusExport {
+    fn export_default() -> Self {
+        Self {
+            slot: 0,
+            confirmations: OptionExport::export_default(),
+            confirmation_status: Commitment::Default,
+            err: OptionExport::export_default(),
+        }
+    }
+}
+
+/// Rust side of an export struct. Decoding does everything that can fail, so handles and
+/// buffers are only issued once the whole result is known to reach the caller.
+pub trait Issue {
+    type Export;
+
+    fn issue(self) -> Self::Export;
+}
+
+impl<T: Issue> Issue for Option<T> where T::Export: ExportDefault {
+    type Export = OptionExport<T::Export>;
+
+    fn issue(self) -> Self::Export {
+        option_export(self.map(Issue::issue))
+    }
+}
+
+pub struct DecodedAccount {
+    lamports: u64,
+    owner: Pubkey,
+    data: Vec<u8>,
+    executable: bool,
+    rent_epoch: u64,
+}
+
+impl Issue for DecodedAccount {
+    type Export = RpcAccountExport;
+
+    fn issue(self) -> RpcAccountExport {
+        RpcAccountExport {
+            lamports: self.lamports,
+            owner: handle_table::insert(self.owner),
+            data: to_raw_parts(self.data),
+            executable: self.executable as u8,
+            rent_epoch: self.rent_epoch,
+        }
+    }
+}
+
+pub struct DecodedKeyedAccount {
+    pubkey: Pubkey,
+    account: DecodedAccount,
+}
+
+impl Issue for DecodedKeyedAccount {
+    type Export = RpcKeyedAccountExport;
+
+    fn issue(self) -> RpcKeyedAccountExport {
+        RpcKeyedAccountExport {
+            pubkey: handle_table::insert(self.pubkey),
+            account: self.account.issue(),
+        }
+    }
+}
+
+pub struct DecodedTransactionError {
+    message: CString,
+    instruction_index: Option<u8>,
+    custom_code: Option<u32>,
+}
+
+impl Issue for DecodedTransactionError {
+    type Export = TransactionErrorExport;
+
+    fn issue(self) -> TransactionErrorExport {
+        TransactionErrorExport {
+            message: self.message.into_raw(),
+            instruction_index: option_export(self.instruction_index),
+            custom_code: option_export(self.custom_code),
+        }
+    }
+}
+
+pub struct DecodedSignatureStatus {
+    slot: u64,
+    confirmations: Option<u64>,
+    confirmation_status: Commitment,
+    err: Option<DecodedTransactionError>,
+}
+
+impl Issue for DecodedSignatureStatus {
+    type Export = SignatureStatusExport;
+
+    fn issue(self) -> SignatureStatusExport {
+        SignatureStatusExport {
+            slot: self.slot,
+            confirmations: option_export(self.confirmations),
+            confirmation_status: self.confirmation_status,
+            err: self.err.issue(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RpcErrorObject {
+    pub code: i64,
+    pub message: String,
+}
+
+impl From<RpcErrorObject> for ExportError {
+    fn from(error: RpcErrorObject) -> Self {
+        ExportError::new(ErrorCode::RpcError, format!("RPC error {}: {}", error.code, error.message))
+    }
+}
+
+#[derive(Deserialize)]
+struct RpcResponse<T> {
+    result: Option<T>,
+    error: Option<RpcErrorObject>,
+}
+
+#[derive(Deserialize)]
+pub struct RpcContext {
+    pub slot: u64,
+}
+
+#[derive(Deserialize)]
+pub struct WithContext<T> {
+    pub context: RpcContext,
+    pub value: T,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcBlockhash {
+    pub blockhash: String,
+    pub last_valid_block_height: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RpcAccountData {
+    /// `[data, encoding]`, as returned for `base58` and `base64` encodings.
+    Encoded(String, String),
+    /// Bare base58 string of the deprecated `binary` encoding.
+    Legacy(String),
+    /// `jsonParsed` data, which this library does not decode.
+    Parsed(IgnoredAny),
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcAccount {
+    lamports: u64,
+    owner: String,
+    data: RpcAccountData,
+    executable: bool,
+    rent_epoch: u64,
+}
+
+#[derive(Deserialize)]
+pub struct RpcKeyedAccount {
+    pub pubkey: String,
+    pub account: RpcAccount,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RpcSimulation {
+    err: Option<TransactionError>,
+    logs: Option<Vec<String>>,
+    units_consumed: Option<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcSignatureStatus {
+    pub slot: u64,
+    pub confirmations: Option<u64>,
+    pub err: Option<TransactionError>,
+    pub confirmation_status: Option<String>,
+}
+
+impl WithContext<RpcBlockhash> {
+    pub fn blockhash(&self) -> Result<Hash, ExportError> {
+        Ok(Hash::from_str(&self.value.blockhash)?)
+    }
+
+    pub fn to_export(&self) -> Result<LatestBlockhashExport, ExportError> {
+        Ok(LatestBlockhashExport {
+            blockhash: handle_table::insert(self.blockhash()?),
+            last_valid_block_height: self.value.last_valid_block_height,
+            context_slot: self.context.slot,
+        })
+    }
+}
+
+impl RpcAccount {
+    pub fn decode_data(&self) -> Result<Vec<u8>, ExportError> {
+        match &self.data {
+            RpcAccountData::Encoded(data, encoding) if encoding == "base64" => base64::decode(data)
+                .map_err(|error| ExportError::new(ErrorCode::InvalidBase64, error.to_string())),
+            RpcAccountData::Encoded(data, encoding) if encoding == "base58" => decode_base58(data),
+            RpcAccountData::Legacy(data) => decode_base58(data),
+            RpcAccountData::Encoded(_, encoding) => Err(ExportError::invalid_argument(
+                format!("Unsupported account data encoding {}", encoding))),
+            RpcAccountData::Parsed(_) => Err(ExportError::invalid_argument(
+                "jsonParsed account data is not supported, request base64")),
+        }
+    }
+
+    pub fn decode(&self) -> Result<DecodedAccount, ExportError> {
+        Ok(DecodedAccount {
+            lamports: self.lamports,
+            owner: Pubkey::from_str(&self.owner)?,
+            data: self.decode_data()?,
+            executable: self.executable,
+            rent_epoch: self.rent_epoch,
+        })
+    }
+
+    pub fn to_export(&self) -> Result<RpcAccountExport, ExportError> {
+        Ok(self.decode()?.issue())
+    }
+}
+
+impl RpcKeyedAccount {
+    pub fn decode(&self) -> Result<DecodedKeyedAccount, ExportError> {
+        Ok(DecodedKeyedAccount {
+            pubkey: Pubkey::from_str(&self.pubkey)?,
+            account: self.account.decode()?,
+        })
+    }
+}
+
+impl RpcSignatureStatus {
+    pub fn commitment(&self) -> Result<Commitment, ExportError> {
+        match &self.confirmation_status {
+            Some(status) => status.parse(),
+            // Nodes older than 1.5.5 only report confirmations, which are cleared once rooted.
+            None if self.confirmations.is_none() => Ok(Commitment::Finalized),
+            None => Ok(Commitment::Processed),
+        }
+    }
+
+    pub fn decode(&self) -> Result<DecodedSignatureStatus, ExportError> {
+        Ok(DecodedSignatureStatus {
+            slot: self.slot,
+            confirmations: self.confirmations,
+            confirmation_status: self.commitment()?,
+            err: decode_transaction_error(self.err.as_ref())?,
+        })
+    }
+
+    pub fn to_export(&self) -> Result<SignatureStatusExport, ExportError> {
+        Ok(self.decode()?.issue())
+    }
+}
+
+fn decode_base58(data: &str) -> Result<Vec<u8>, ExportError> {
+    bs58::decode(data)
+        .into_vec()
+        .map_err(|error| ExportError::new(ErrorCode::InvalidBase58, error.to_string()))
+}
+
+pub fn option_export<T: ExportDefault>(value: Option<T>) -> OptionExport<T> {
+    match value {
+        Some(value) => OptionExport { has_value: true as u8, value },
+        None => OptionExport::export_default(),
+    }
+}
+
+pub fn decode_transaction_error(error: Option<&TransactionError>) -> Result<Option<DecodedTransactionError>, ExportError> {
+    let error = match error {
+        Some(error) => error,
+        None => return Ok(None),
+    };
+    let (instruction_index, custom_code) = match error {
+        TransactionError::InstructionError(index, InstructionError::Custom(code)) => (Some(*index), Some(*code)),
+        TransactionError::InstructionError(index, _) => (Some(*index), None),
+        _ => (None, None),
+    };
+    Ok(Some(DecodedTransactionError {
+        message: CString::new(error.to_string())?,
+        instruction_index,
+        custom_code,
+    }))
+}
+
+pub fn transaction_error_export(error: Option<&TransactionError>) -> Result<OptionExport<TransactionErrorExport>, ExportError> {
+    Ok(decode_transaction_error(error)?.issue())
+}
+
+/// Config object carrying the commitment, if any, plus `extra` fields.
+pub fn config(commitment: Commitment, extra: Value) -> Value {
+    let mut config = match extra {
+        Value::Object(map) => map,
+        _ => Map::new(),
+    };
+    if let Some(commitment) = commitment.as_str() {
+        config.insert("commitment".to_string(), Value::from(commitment));
+    }
+    Value::Object(config)
+}
+
+/// Log lines joined with newlines, see `issue_logs`.
+pub fn decode_logs(logs: Option<&[String]>) -> Result<Option<CString>, ExportError> {
+    Ok(logs.map(|logs| CString::new(logs.join("\n"))).transpose()?)
+}
+
+/// Hands decoded logs to the caller, null when there are none.
+pub fn issue_logs(logs: Option<CString>) -> *mut c_char {
+    logs.map_or(null_mut(), CString::into_raw)
+}
+
+/// JSON-RPC 2.0 request body, handed to the caller as a C string.
+pub fn request_body(id: u64, method: &str, params: Value) -> Result<*mut c_char, ExportError> {
+    c_char_ptr_from_string(request_json(id, method, params))
+}
+
+pub fn request_json(id: u64, method: &str, params: Value) -> String {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "method": method,
+        "params": params,
+    }).to_string()
+}
+
+/// Returns the `result` of a JSON-RPC response, or the node's error as `RpcError`.
+pub fn parse_response<T: DeserializeOwned>(json: &str) -> Result<T, ExportError> {
+    let response: RpcResponse<T> = serde_json::from_str(json)?;
+    if let Some(error) = response.error {
+        return Err(error.into());
+    }
+    response.result.ok_or_else(|| ExportError::new(ErrorCode::InvalidJson, "Response has neither a result nor an error"))
+}
+
+/// Wire bytes of a `Transaction` or `VersionedTransaction` handle.
+pub fn serialized_transaction(transaction: Handle) -> Result<Vec<u8>, ExportError> {
+    match handle_table::type_of(transaction)? {
+        HandleType::Transaction => Ok(handle_table::with(transaction, |transaction: &mut Transaction| bincode::serialize(transaction))??),
+        HandleType::VersionedTransaction => Ok(handle_table::with(transaction, |transaction: &mut VersionedTransaction| bincode::serialize(transaction))??),
+        handle_type => Err(ExportError::new(ErrorCode::WrongHandleType,
+            format!("Handle {:#x} is a {:?} handle, not a transaction", transaction, handle_type))),
+    }
+}
+
+/// Decodes `items` into the caller's `out` array, which must have room for all of them.
+/// Every item is decoded before the first one is issued, so no handles or buffers are
+/// handed out for a result that cannot be returned.
+fn write_to_ptr<S, D: Issue>(items: &[S], out: *mut D::Export, out_len: usize, name: &str,
+                             decode: impl Fn(&S) -> Result<D, ExportError>) -> Result<u32, ExportError> {
+    if out.is_null() {
+        return Err(ExportError::null_pointer(name));
+    }
+    if out_len < items.len() {
+        return Err(ExportError::invalid_length(name, items.len(), out_len));
+    }
+    let decoded = items.iter().map(decode).collect::<Result<Vec<_>, _>>()?;
+    let out = unsafe { std::slice::from_raw_parts_mut(out, decoded.len()) };
+    for (slot, item) in out.iter_mut().zip(decoded) {
+        unsafe { std::ptr::write(slot, item.issue()) };
+    }
+    Ok(out.len() as u32)
+}
+
+fn base64_account_config(commitment: u8) -> Result<Value, ExportError> {
+    Ok(config(Commitment::try_from(commitment)?, json!({ "encoding": "base64" })))
+}
+
+#[no_mangle]
+extern "C" fn rpc_get_latest_blockhash_request(id: u64, commitment: u8) -> ResultExport<*mut c_char> {
+    export_result(|| {
+        request_body(id, "getLatestBlockhash", json!([config(Commitment::try_from(commitment)?, Value::Null)]))
+    })
+}
+
+#[no_mangle]
+extern "C" fn rpc_get_block_height_request(id: u64, commitment: u8) -> ResultExport<*mut c_char> {
+    export_result(|| {
+        request_body(id, "getBlockHeight", json!([config(Commitment::try_from(commitment)?, Value::Null)]))
+    })
+}
+
+#[no_mangle]
+extern "C" fn rpc_get_balance_request(id: u64, pubkey: Handle, commitment: u8) -> ResultExport<*mut c_char> {
+    export_result(|| {
+        let pubkey = handle_table::get::<Pubkey>(pubkey)?;
+        request_body(id, "getBalance", json!([pubkey.to_string(), config(Commitment::try_from(commitment)?, Value::Null)]))
+    })
+}
+
+/// Requests the account with base64 encoded data.
+#[no_mangle]
+extern "C" fn rpc_get_account_info_request(id: u64, pubkey: Handle, commitment: u8) -> ResultExport<*mut c_char> {
+    export_result(|| {
+        let pubkey = handle_table::get::<Pubkey>(pubkey)?;
+        request_body(id, "getAccountInfo", json!([pubkey.to_string(), base64_account_config(commitment)?]))
+    })
+}
+
+#[no_mangle]
+extern "C" fn rpc_get_multiple_accounts_request(id: u64, pubkeys: *const Handle, pubkeys_len: size_t,
+                                                commitment: u8) -> ResultExport<*mut c_char> {
+    export_result(|| {
+        let pubkeys: Vec<String> = pubkeys_from_ptr(pubkeys, pubkeys_len)?
+            .iter()
+            .map(Pubkey::to_string)
+            .collect();
+        request_body(id, "getMultipleAccounts", json!([pubkeys, base64_account_config(commitment)?]))
+    })
+}
+
+/// Filters by `mint`, or by `token_program` when `mint` is 0. Exactly one of them must be set.
+#[no_mangle]
+extern "C" fn rpc_get_token_accounts_by_owner_request(id: u64, owner: Handle, mint: Handle, token_program: Handle,
+                                                      commitment: u8) -> ResultExport<*mut c_char> {
+    export_result(|| {
+        let owner = handle_table::get::<Pubkey>(owner)?;
+        let filter = match (mint, token_program) {
+            (mint, 0) if mint != 0 => json!({ "mint": handle_table::get::<Pubkey>(mint)?.to_string() }),
+            (0, token_program) if token_program != 0 => json!({ "programId": handle_table::get::<Pubkey>(token_program)?.to_string() }),
+            _ => return Err(ExportError::invalid_argument("Exactly one of mint and token_program must be set")),
+        };
+        request_body(id, "getTokenAccountsByOwner", json!([owner.to_string(), filter, base64_account_config(commitment)?]))
+    })
+}
+
+/// Sends a `Transaction` or `VersionedTransaction` handle, base64 encoded.
+/// `preflight_commitment` applies to the preflight simulation unless it is skipped.
+#[no_mangle]
+extern "C" fn rpc_send_transaction_request(id: u64, transaction: Handle, skip_preflight: u8,
+                                           preflight_commitment: u8) -> ResultExport<*mut c_char> {
+    export_result(|| {
+        let transaction = base64::encode(serialized_transaction(transaction)?);
+        let mut config = json!({ "encoding": "base64", "skipPreflight": skip_preflight != 0 });
+        if let Some(commitment) = Commitment::try_from(preflight_commitment)?.as_str() {
+            config["preflightCommitment"] = Value::from(commitment);
+        }
+        request_body(id, "sendTransaction", json!([transaction, config]))
+    })
+}
+
+#[no_mangle]
+extern "C" fn rpc_simulate_transaction_request(id: u64, transaction: Handle, sig_verify: u8, replace_recent_blockhash: u8,
+                                               commitment: u8) -> ResultExport<*mut c_char> {
+    export_result(|| {
+        let transaction = base64::encode(serialized_transaction(transaction)?);
+        let config = config(Commitment::try_from(commitment)?, json!({
+            "encoding": "base64",
+            "sigVerify": sig_verify != 0,
+            "replaceRecentBlockhash": replace_recent_blockhash != 0,
+        }));
+        request_body(id, "simulateTransaction", json!([transaction, config]))
+    })
+}
+
+/// Set `search_transaction_history` to also find signatures older than the node's status cache.
+#[no_mangle]
+extern "C" fn rpc_get_signature_statuses_request(id: u64, signatures: *const Handle, signatures_len: size_t,
+                                                 search_transaction_history: u8) -> ResultExport<*mut c_char> {
+    export_result(|| {
+        let signatures = slice_from_ptr(signatures, signatures_len, "signatures")?
+            .iter()
+            .map(|signature| Ok(handle_table::get::<Signature>(*signature)?.to_string()))
+            .collect::<Result<Vec<String>, ExportError>>()?;
+        request_body(id, "getSignatureStatuses",
+                     json!([signatures, { "searchTransactionHistory": search_transaction_history != 0 }]))
+    })
+}
+
+/// Number of entries in the result of a `getMultipleAccounts`, `getTokenAccountsByOwner` or
+/// `getSignatureStatuses` response, to size the array passed to its parser.
+#[no_mangle]
+extern "C" fn rpc_response_len(response: *const c_char) -> ResultExport<u32> {
+    export_result(|| {
+        let result: Value = parse_response(str_from_c_char_ptr(response)?)?;
+        result.get("value").unwrap_or(&result)
+            .as_array()
+            .map(|items| items.len() as u32)
+            .ok_or_else(|| ExportError::new(ErrorCode::InvalidJson, "Response result is not a list"))
+    })
+}
+
+#[no_mangle]
+extern "C" fn rpc_parse_latest_blockhash(response: *const c_char) -> ResultExport<LatestBlockhashExport> {
+    export_result(|| {
+        let result: WithContext<RpcBlockhash> = parse_response(str_from_c_char_ptr(response)?)?;
+        result.to_export()
+    })
+}
+
+#[no_mangle]
+extern "C" fn rpc_parse_block_height(response: *const c_char) -> ResultExport<u64> {
+    export_result(|| parse_response(str_from_c_char_ptr(response)?))
+}
+
+#[no_mangle]
+extern "C" fn rpc_parse_balance(response: *const c_char) -> ResultExport<u64> {
+    export_result(|| {
+        let result: WithContext<u64> = parse_response(str_from_c_char_ptr(response)?)?;
+        Ok(result.value)
+    })
+}
+
+/// Empty when the account does not exist.
+#[no_mangle]
+extern "C" fn rpc_parse_account_info(response: *const c_char) -> ResultExport<OptionExport<RpcAccountExport>> {
+    export_result(|| {
+        let result: WithContext<Option<RpcAccount>> = parse_response(str_from_c_char_ptr(response)?)?;
+        Ok(option_export(result.value.map(|account| account.to_export()).transpose()?))
+    })
+}
+
+/// Writes one entry per requested account to `out`, empty for accounts that do not exist.
+/// Returns the number of entries written.
+#[no_mangle]
+extern "C" fn rpc_parse_multiple_accounts(response: *const c_char, out: *mut OptionExport<RpcAccountExport>,
+                                          out_len: size_t) -> ResultExport<u32> {
+    export_result(|| {
+        let result: WithContext<Vec<Option<RpcAccount>>> = parse_response(str_from_c_char_ptr(response)?)?;
+        write_to_ptr(&result.value, out, out_len, "out", |account| account.as_ref().map(RpcAccount::decode).transpose())
+    })
+}
+
+/// Writes the token accounts to `out`; size it with `rpc_response_len`.
+/// Returns the number of entries written.
+#[no_mangle]
+extern "C" fn rpc_parse_token_accounts_by_owner(response: *const c_char, out: *mut RpcKeyedAccountExport,
+                                                out_len: size_t) -> ResultExport<u32> {
+    export_result(|| {
+        let result: WithContext<Vec<RpcKeyedAccount>> = parse_response(str_from_c_char_ptr(response)?)?;
+        write_to_ptr(&result.value, out, out_len, "out", RpcKeyedAccount::decode)
+    })
+}
+
+/// Signature of the sent transaction.
+#[no_mangle]
+extern "C" fn rpc_parse_send_transaction(response: *const c_char) -> ResultExport<Handle> {
+    export_result(|| {
+        let signature: String = parse_response(str_from_c_char_ptr(response)?)?;
+        Ok(handle_table::insert(Signature::from_str(&signature)?))
+    })
+}
+
+#[no_mangle]
+extern "C" fn rpc_parse_simulate_transaction(response: *const c_char) -> ResultExport<SimulationExport> {
+    export_result(|| {
+        let result: WithContext<RpcSimulation> = parse_response(str_from_c_char_ptr(response)?)?;
+        let err = decode_transaction_error(result.value.err.as_ref())?;
+        let logs = decode_logs(result.value.logs.as_deref())?;
+        Ok(SimulationExport {
+            err: err.issue(),
+            logs: issue_logs(logs),
+            units_consumed: option_export(result.value.units_consumed),
+            context_slot: result.context.slot,
+        })
+    })
+}
+
+/// Writes one entry per requested signature to `out`, empty for signatures the node does
+/// not know. Returns the number of entries written.
+#[no_mangle]
+extern "C" fn rpc_parse_signature_statuses(response: *const c_char, out: *mut OptionExport<SignatureStatusExport>,
+                                           out_len: size_t) -> ResultExport<u32> {
+    export_result(|| {
+        let result: WithContext<Vec<Option<RpcSignatureStatus>>> = parse_response(str_from_c_char_ptr(response)?)?;
+        write_to_ptr(&result.value, out, out_len, "out", |status| status.as_ref().map(RpcSignatureStatus::decode).transpose())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::{CStr, CString};
+
+    use super::*;
+
+    const PUBKEY: &str = "So11111111111111111111111111111111111111112";
+    const OWNER: &str = "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA";
+    const BLOCKHASH: &str = "EkSnNWid2cvwEVnVx9aBqawnmiCNiDgp3gUdkDPTKN1N";
+    const SIGNATURE: &str = "5VERv8NMvzbJMEkV8xnrLkEaWRtSz9CosKDYjCJjBRnbJLgp8uirBgmQpjKhoR4tjF3ZpRzrFmBV6UjKdiSZkQUW";
+    const ERROR_RESPONSE: &str = r#"{"jsonrpc":"2.0","error":{"code":-32602,"message":"Invalid param: WrongSize"},"id":1}"#;
+
+    fn response(result: &str) -> String {
+        format!(r#"{{"jsonrpc":"2.0","result":{},"id":1}}"#, result)
+    }
+
+    fn with_context(value: &str) -> String {
+        response(&format!(r#"{{"context":{{"apiVersion":"1.18.22","slot":287654321}},"value":{}}}"#, value))
+    }
+
+    fn account(data: &str) -> String {
+        format!(r#"{{"data":[{:?},"base64"],"executable":false,"lamports":2039280,"owner":"{}","rentEpoch":18446744073709551615,"space":4}}"#,
+                data, OWNER)
+    }
+
+    fn parse<T>(parser: extern "C" fn(*const c_char) -> ResultExport<T>, json: &str) -> ResultExport<T> {
+        let json = CString::new(json).unwrap();
+        parser(json.as_ptr())
+    }
+
+    fn parse_into<T>(parser: extern "C" fn(*const c_char, *mut T, size_t) -> ResultExport<u32>, json: &str,
+                     out: &mut [T]) -> ResultExport<u32> {
+        let json = CString::new(json).unwrap();
+        parser(json.as_ptr(), out.as_mut_ptr(), out.len())
+    }
+
+    fn assert_error<T>(result: &ResultExport<T>, code: ErrorCode) {
+        assert_eq!(result.is_error, 1);
+        assert_eq!(result.error_code, code);
+    }
+
+    fn assert_rpc_error<T>(result: &ResultExport<T>) {
+        assert_error(result, ErrorCode::RpcError);
+        let message = unsafe { CStr::from_ptr(result.error) }.to_str().unwrap();
+        assert!(message.contains("-32602") && message.contains("Invalid param: WrongSize"), "{}", message);
+    }
+
+    fn string(ptr: *mut c_char) -> String {
+        unsafe { CStr::from_ptr(ptr) }.to_str().unwrap().to_string()
+    }
+
+    fn bytes(parts: &VecRawParts) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(parts.data as *const u8, parts.length as usize) }
+    }
+
+    fn empty_account() -> OptionExport<RpcAccountExport> {
+        OptionExport { has_value: 7, value: RpcAccountExport::export_default() }
+    }
+
+    #[test]
+    fn latest_blockhash() {
+        let value = format!(r#"{{"blockhash":"{}","lastValidBlockHeight":265432198}}"#, BLOCKHASH);
+        let result = parse(rpc_parse_latest_blockhash, &with_context(&value));
+        assert_eq!(result.is_error, 0);
+        assert_eq!(handle_table::get::<Hash>(result.result.blockhash).unwrap().to_string(), BLOCKHASH);
+        assert_eq!(result.result.last_valid_block_height, 265432198);
+        assert_eq!(result.result.context_slot, 287654321);
+
+        assert_rpc_error(&parse(rpc_parse_latest_blockhash, ERROR_RESPONSE));
+        assert_error(&parse(rpc_parse_latest_blockhash, &with_context("null")), ErrorCode::InvalidJson);
+        let value = r#"{"blockhash":"not base58!","lastValidBlockHeight":265432198}"#;
+        assert_error(&parse(rpc_parse_latest_blockhash, &with_context(value)), ErrorCode::InvalidBase58);
+    }
+
+    #[test]
+    fn block_height() {
+        let result = parse(rpc_parse_block_height, &response("265432048"));
+        assert_eq!((result.is_error, result.result), (0, 265432048));
+
+        assert_rpc_error(&parse(rpc_parse_block_height, ERROR_RESPONSE));
+        assert_error(&parse(rpc_parse_block_height, &response("null")), ErrorCode::InvalidJson);
+        assert_error(&parse(rpc_parse_block_height, &response(r#""265432048""#)), ErrorCode::InvalidJson);
+    }
+
+    #[test]
+    fn balance() {
+        let result = parse(rpc_parse_balance, &with_context("1000000000"));
+        assert_eq!((result.is_error, result.result), (0, 1000000000));
+
+        assert_rpc_error(&parse(rpc_parse_balance, ERROR_RESPONSE));
+        assert_error(&parse(rpc_parse_balance, &with_context("null")), ErrorCode::InvalidJson);
+        assert_error(&parse(rpc_parse_balance, &with_context("-1")), ErrorCode::InvalidJson);
+    }
+
+    #[test]
+    fn account_info() {
+        let result = parse(rpc_parse_account_info, &with_context(&account("AQIDBA==")));
+        assert_eq!((result.is_error, result.result.has_value), (0, 1));
+        let export = &result.result.value;
+        assert_eq!(export.lamports, 2039280);
+        assert_eq!(handle_table::get::<Pubkey>(export.owner).unwrap().to_string(), OWNER);
+        assert_eq!(bytes(&export.data), [1, 2, 3, 4]);
+        assert_eq!((export.executable, export.rent_epoch), (0, u64::MAX));
+
+        assert_rpc_error(&parse(rpc_parse_account_info, ERROR_RESPONSE));
+        let result = parse(rpc_parse_account_info, &with_context("null"));
+        assert_eq!((result.is_error, result.result.has_value), (0, 0));
+        assert_error(&parse(rpc_parse_account_info, &with_context(&account("not base64!"))), ErrorCode::InvalidBase64);
+    }
+
+    #[test]
+    fn multiple_accounts() {
+        let value = format!("[{},null]", account("AQIDBA=="));
+        let mut out = [empty_account(), empty_account()];
+        let result = parse_into(rpc_parse_multiple_accounts, &with_context(&value), &mut out);
+        assert_eq!((result.is_error, result.result), (0, 2));
+        assert_eq!(out[0].has_value, 1);
+        assert_eq!(bytes(&out[0].value.data), [1, 2, 3, 4]);
+        assert_eq!(out[1].has_value, 0);
+
+        let mut out = [empty_account()];
+        let result = parse_into(rpc_parse_multiple_accounts, &with_context(&value), &mut out);
+        assert_error(&result, ErrorCode::InvalidLength);
+
+        let mut out = [empty_account(), empty_account()];
+        assert_rpc_error(&parse_into(rpc_parse_multiple_accounts, ERROR_RESPONSE, &mut out));
+        assert_error(&parse_into(rpc_parse_multiple_accounts, &with_context("null"), &mut out), ErrorCode::InvalidJson);
+
+        // Nothing is written when a later entry fails to decode.
+        let value = format!("[{},{}]", account("AQIDBA=="), account("not base64!"));
+        let result = parse_into(rpc_parse_multiple_accounts, &with_context(&value), &mut out);
+        assert_error(&result, ErrorCode::InvalidBase64);
+        assert!(out.iter().all(|account| account.has_value == 7 && account.value.owner == 0));
+    }
+
+    #[test]
+    fn token_accounts_by_owner() {
+        let keyed = |pubkey: &str| format!(r#"{{"pubkey":"{}","account":{}}}"#, pubkey, account("AQIDBA=="));
+        let empty = || RpcKeyedAccountExport { pubkey: 0, account: RpcAccountExport::export_default() };
+
+        let mut out = [empty()];
+        let result = parse_into(rpc_parse_token_accounts_by_owner, &with_context(&format!("[{}]", keyed(PUBKEY))), &mut out);
+        assert_eq!((result.is_error, result.result), (0, 1));
+        assert_eq!(handle_table::get::<Pubkey>(out[0].pubkey).unwrap().to_string(), PUBKEY);
+        assert_eq!(handle_table::get::<Pubkey>(out[0].account.owner).unwrap().to_string(), OWNER);
+
+        assert_rpc_error(&parse_into(rpc_parse_token_accounts_by_owner, ERROR_RESPONSE, &mut out));
+        assert_error(&parse_into(rpc_parse_token_accounts_by_owner, &with_context("null"), &mut out), ErrorCode::InvalidJson);
+
+        let mut out = [empty(), empty()];
+        let value = format!("[{},{}]", keyed(PUBKEY), keyed("not a pubkey"));
+        assert_error(&parse_into(rpc_parse_token_accounts_by_owner, &with_context(&value), &mut out), ErrorCode::InvalidBase58);
+        assert!(out.iter().all(|keyed| keyed.pubkey == 0 && keyed.account.owner == 0));
+    }
+
+    #[test]
+    fn send_transaction() {
+        let result = parse(rpc_parse_send_transaction, &response(&format!("{:?}", SIGNATURE)));
+        assert_eq!(result.is_error, 0);
+        assert_eq!(handle_table::get::<Signature>(result.result).unwrap().to_string(), SIGNATURE);
+
+        assert_rpc_error(&parse(rpc_parse_send_transaction, ERROR_RESPONSE));
+        assert_error(&parse(rpc_parse_send_transaction, &response("null")), ErrorCode::InvalidJson);
+        assert_error(&parse(rpc_parse_send_transaction, &response(r#""not a signature""#)), ErrorCode::InvalidBase58);
+    }
+
+    #[test]
+    fn simulate_transaction() {
+        let value = r#"{"accounts":null,"err":{"InstructionError":[1,{"Custom":6001}]},"logs":["Program 11111111111111111111111111111111 invoke [1]","Program 11111111111111111111111111111111 failed: custom program error: 0x1771"],"returnData":null,"unitsConsumed":2366}"#;
+        let result = parse(rpc_parse_simulate_transaction, &with_context(value));
+        assert_eq!(result.is_error, 0);
+        let simulation = &result.result;
+        assert_eq!(simulation.err.has_value, 1);
+        assert_eq!(string(simulation.err.value.message), "Error processing Instruction 1: custom program error: 0x1771");
+        assert_eq!((simulation.err.value.instruction_index.value, simulation.err.value.custom_code.value), (1, 6001));
+        assert_eq!(string(simulation.logs).lines().count(), 2);
+        assert_eq!((simulation.units_consumed.value, simulation.context_slot), (2366, 287654321));
+
+        let value = r#"{"accounts":null,"err":null,"logs":null,"returnData":null,"unitsConsumed":null}"#;
+        let result = parse(rpc_parse_simulate_transaction, &with_context(value));
+        assert_eq!((result.is_error, result.result.err.has_value, result.result.units_consumed.has_value), (0, 0, 0));
+        assert!(result.result.logs.is_null());
+
+        assert_rpc_error(&parse(rpc_parse_simulate_transaction, ERROR_RESPONSE));
+        assert_error(&parse(rpc_parse_simulate_transaction, &with_context("null")), ErrorCode::InvalidJson);
+        let value = r#"{"err":"NotATransactionError","logs":[]}"#;
+        assert_error(&parse(rpc_parse_simulate_transaction, &with_context(value)), ErrorCode::InvalidJson);
+    }
+
+    #[test]
+    fn signature_statuses() {
+        let empty = || OptionExport { has_value: 7, value: SignatureStatusExport::export_default() };
+        let value = r#"[{"confirmationStatus":"confirmed","confirmations":10,"err":null,"slot":287654300,"status":{"Ok":null}},{"confirmationStatus":"finalized","confirmations":null,"err":{"InstructionError":[0,"InvalidAccountData"]},"slot":287654200,"status":{"Err":{"InstructionError":[0,"InvalidAccountData"]}}},null]"#;
+        let mut out = [empty(), empty(), empty()];
+        let result = parse_into(rpc_parse_signature_statuses, &with_context(value), &mut out);
+        assert_eq!((result.is_error, result.result), (0, 3));
+        assert_eq!(out[0].has_value, 1);
+        assert_eq!((out[0].value.slot, out[0].value.confirmations.value), (287654300, 10));
+        assert_eq!((out[0].value.confirmation_status, out[0].value.err.has_value), (Commitment::Confirmed, 0));
+        assert_eq!((out[1].value.confirmation_status, out[1].value.confirmations.has_value), (Commitment::Finalized, 0));
+        assert_eq!(out[1].value.err.value.instruction_index.value, 0);
+        assert_eq!(out[1].value.err.value.custom_code.has_value, 0);
+        assert_eq!(out[2].has_value, 0);
+
+        assert_rpc_error(&parse_into(rpc_parse_signature_statuses, ERROR_RESPONSE, &mut out));
+        assert_error(&parse_into(rpc_parse_signature_statuses, &with_context("null"), &mut out), ErrorCode::InvalidJson);
+
+        let mut out = [empty(), empty()];
+        let value = r#"[{"confirmationStatus":"processed","confirmations":0,"err":null,"slot":1},{"confirmationStatus":"rooted","confirmations":null,"err":null,"slot":1}]"#;
+        assert_error(&parse_into(rpc_parse_signature_statuses, &with_context(value), &mut out), ErrorCode::InvalidJson);
+        assert!(out.iter().all(|status| status.has_value == 7));
+    }
+
+    #[test]
+    fn response_len() {
+        assert_eq!(parse(rpc_response_len, &with_context("[null,null,null]")).result, 3);
+        assert_eq!(parse(rpc_response_len, &response("[1,2]")).result, 2);
+        assert_rpc_error(&parse(rpc_response_len, ERROR_RESPONSE));
+        assert_error(&parse(rpc_response_len, &with_context("null")), ErrorCode::InvalidJson);
+    }
+}
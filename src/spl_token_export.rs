@@ -0,0 +1,192 @@
+use libc::size_t;
+use solana_program::instruction::Instruction;
+use solana_program::program_error::ProgramError;
+use solana_program::pubkey::Pubkey;
+use spl_associated_token_account::instruction as associated_token_instruction;
+use spl_token::instruction as token_instruction;
+
+use crate::common_types::ResultExport;
+use crate::error_export::{export_result, ExportError};
+use crate::handle_table::{self, Handle};
+use crate::pubkey_export::{find_program_address, ProgramAddress, pubkeys_from_ptr};
+
+pub fn insert_instruction(instruction: Result<Instruction, ProgramError>) -> Result<Handle, ExportError> {
+    Ok(handle_table::insert(instruction?))
+}
+
+/// Multisig signers of a token instruction; empty when the authority signs itself.
+fn multisig_signers(signers: *const Handle, signers_len: size_t) -> Result<Vec<Pubkey>, ExportError> {
+    pubkeys_from_ptr(signers, signers_len)
+}
+
+/// A zero `token_program` handle selects the original SPL Token program.
+pub fn token_program_or_default(token_program: Handle) -> Result<Pubkey, ExportError> {
+    if token_program == 0 {
+        Ok(spl_token::id())
+    } else {
+        handle_table::get::<Pubkey>(token_program)
+    }
+}
+
+#[no_mangle]
+extern "C" fn token_program_id() -> ResultExport<Handle> {
+    export_result(|| Ok(handle_table::insert(spl_token::id())))
+}
+
+#[no_mangle]
+extern "C" fn associated_token_program_id() -> ResultExport<Handle> {
+    export_result(|| Ok(handle_table::insert(spl_associated_token_account::id())))
+}
+
+#[no_mangle]
+extern "C" fn token_transfer_checked(source: Handle, mint: Handle, destination: Handle, authority: Handle,
+                                     signers: *const Handle, signers_len: size_t,
+                                     amount: u64, decimals: u8) -> ResultExport<Handle> {
+    export_result(|| {
+        let source = handle_table::get::<Pubkey>(source)?;
+        let mint = handle_table::get::<Pubkey>(mint)?;
+        let destination = handle_table::get::<Pubkey>(destination)?;
+        let authority = handle_table::get::<Pubkey>(authority)?;
+        let signers = multisig_signers(signers, signers_len)?;
+
+        insert_instruction(token_instruction::transfer_checked(
+            &spl_token::id(), &source, &mint, &destination, &authority,
+            &signers.iter().collect::<Vec<_>>(), amount, decimals))
+    })
+}
+
+#[no_mangle]
+extern "C" fn token_approve(source: Handle, delegate: Handle, owner: Handle,
+                            signers: *const Handle, signers_len: size_t, amount: u64) -> ResultExport<Handle> {
+    export_result(|| {
+        let source = handle_table::get::<Pubkey>(source)?;
+        let delegate = handle_table::get::<Pubkey>(delegate)?;
+        let owner = handle_table::get::<Pubkey>(owner)?;
+        let signers = multisig_signers(signers, signers_len)?;
+
+        insert_instruction(token_instruction::approve(
+            &spl_token::id(), &source, &delegate, &owner, &signers.iter().collect::<Vec<_>>(), amount))
+    })
+}
+
+#[no_mangle]
+extern "C" fn token_revoke(source: Handle, owner: Handle, signers: *const Handle, signers_len: size_t) -> ResultExport<Handle> {
+    export_result(|| {
+        let source = handle_table::get::<Pubkey>(source)?;
+        let owner = handle_table::get::<Pubkey>(owner)?;
+        let signers = multisig_signers(signers, signers_len)?;
+
+        insert_instruction(token_instruction::revoke(
+            &spl_token::id(), &source, &owner, &signers.iter().collect::<Vec<_>>()))
+    })
+}
+
+#[no_mangle]
+extern "C" fn token_mint_to(mint: Handle, destination: Handle, authority: Handle,
+                            signers: *const Handle, signers_len: size_t, amount: u64) -> ResultExport<Handle> {
+    export_result(|| {
+        let mint = handle_table::get::<Pubkey>(mint)?;
+        let destination = handle_table::get::<Pubkey>(destination)?;
+        let authority = handle_table::get::<Pubkey>(authority)?;
+        let signers = multisig_signers(signers, signers_len)?;
+
+        insert_instruction(token_instruction::mint_to(
+            &spl_token::id(), &mint, &destination, &authority, &signers.iter().collect::<Vec<_>>(), amount))
+    })
+}
+
+#[no_mangle]
+extern "C" fn token_burn(account: Handle, mint: Handle, authority: Handle,
+                         signers: *const Handle, signers_len: size_t, amount: u64) -> ResultExport<Handle> {
+    export_result(|| {
+        let account = handle_table::get::<Pubkey>(account)?;
+        let mint = handle_table::get::<Pubkey>(mint)?;
+        let authority = handle_table::get::<Pubkey>(authority)?;
+        let signers = multisig_signers(signers, signers_len)?;
+
+        insert_instruction(token_instruction::burn(
+            &spl_token::id(), &account, &mint, &authority, &signers.iter().collect::<Vec<_>>(), amount))
+    })
+}
+
+#[no_mangle]
+extern "C" fn token_close_account(account: Handle, destination: Handle, owner: Handle,
+                                  signers: *const Handle, signers_len: size_t) -> ResultExport<Handle> {
+    export_result(|| {
+        let account = handle_table::get::<Pubkey>(account)?;
+        let destination = handle_table::get::<Pubkey>(destination)?;
+        let owner = handle_table::get::<Pubkey>(owner)?;
+        let signers = multisig_signers(signers, signers_len)?;
+
+        insert_instruction(token_instruction::close_account(
+            &spl_token::id(), &account, &destination, &owner, &signers.iter().collect::<Vec<_>>()))
+    })
+}
+
+/// A zero `freeze_authority` handle creates a mint without freeze authority.
+#[no_mangle]
+extern "C" fn token_initialize_mint(mint: Handle, mint_authority: Handle, freeze_authority: Handle,
+                                    decimals: u8) -> ResultExport<Handle> {
+    export_result(|| {
+        let mint = handle_table::get::<Pubkey>(mint)?;
+        let mint_authority = handle_table::get::<Pubkey>(mint_authority)?;
+        let freeze_authority = match freeze_authority {
+            0 => None,
+            freeze_authority => Some(handle_table::get::<Pubkey>(freeze_authority)?),
+        };
+
+        insert_instruction(token_instruction::initialize_mint(
+            &spl_token::id(), &mint, &mint_authority, freeze_authority.as_ref(), decimals))
+    })
+}
+
+#[no_mangle]
+extern "C" fn token_sync_native(account: Handle) -> ResultExport<Handle> {
+    export_result(|| {
+        let account = handle_table::get::<Pubkey>(account)?;
+        insert_instruction(token_instruction::sync_native(&spl_token::id(), &account))
+    })
+}
+
+/// Derives the associated token account of `wallet` for `mint`, the same way as
+/// `pubkey_find_program_address` with seeds `[wallet, token program, mint]`.
+#[no_mangle]
+extern "C" fn associated_token_address(wallet: Handle, mint: Handle, token_program: Handle) -> ResultExport<ProgramAddress> {
+    export_result(|| {
+        let wallet = handle_table::get::<Pubkey>(wallet)?;
+        let mint = handle_table::get::<Pubkey>(mint)?;
+        let token_program = token_program_or_default(token_program)?;
+
+        find_program_address(
+            &[wallet.as_ref(), token_program.as_ref(), mint.as_ref()],
+            &spl_associated_token_account::id(),
+        )
+    })
+}
+
+#[no_mangle]
+extern "C" fn associated_token_create(payer: Handle, wallet: Handle, mint: Handle, token_program: Handle) -> ResultExport<Handle> {
+    export_result(|| {
+        let payer = handle_table::get::<Pubkey>(payer)?;
+        let wallet = handle_table::get::<Pubkey>(wallet)?;
+        let mint = handle_table::get::<Pubkey>(mint)?;
+        let token_program = token_program_or_default(token_program)?;
+
+        Ok(handle_table::insert(associated_token_instruction::create_associated_token_account(
+            &payer, &wallet, &mint, &token_program)))
+    })
+}
+
+/// Same as `associated_token_create`, but succeeds if the account already exists.
+#[no_mangle]
+extern "C" fn associated_token_create_idempotent(payer: Handle, wallet: Handle, mint: Handle, token_program: Handle) -> ResultExport<Handle> {
+    export_result(|| {
+        let payer = handle_table::get::<Pubkey>(payer)?;
+        let wallet = handle_table::get::<Pubkey>(wallet)?;
+        let mint = handle_table::get::<Pubkey>(mint)?;
+        let token_program = token_program_or_default(token_program)?;
+
+        Ok(handle_table::insert(associated_token_instruction::create_associated_token_account_idempotent(
+            &payer, &wallet, &mint, &token_program)))
+    })
+}
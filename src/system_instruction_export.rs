@@ -0,0 +1,167 @@
+use std::os::raw::c_char;
+
+use libc::size_t;
+use solana_program::pubkey::Pubkey;
+use solana_program::system_instruction;
+
+use crate::c_sharp_string::str_from_c_char_ptr;
+use crate::common_types::ResultExport;
+use crate::error_export::{ErrorCode, export_result, ExportError};
+use crate::handle_table::{self, Handle};
+use crate::transaction_export::instructions_to_ptr;
+
+/// Number of instructions written by `system_create_nonce_account` and
+/// `system_create_nonce_account_with_seed`.
+pub const CREATE_NONCE_ACCOUNT_INSTRUCTIONS: usize = 2;
+
+/// Checks that `address` is the one `pubkey_create_with_seed` derives from the other arguments.
+fn check_seed_address(address: &Pubkey, base: &Pubkey, seed: &str, owner: &Pubkey) -> Result<(), ExportError> {
+    let expected = Pubkey::create_with_seed(base, seed, owner)?;
+    if *address != expected {
+        return Err(ExportError::new(
+            ErrorCode::SeedAddressMismatch,
+            format!("{} does not match the address derived from base, seed and owner ({})", address, expected),
+        ));
+    }
+    Ok(())
+}
+
+#[no_mangle]
+extern "C" fn system_transfer(from: Handle, to: Handle, lamports: u64) -> ResultExport<Handle> {
+    export_result(|| {
+        let from = handle_table::get::<Pubkey>(from)?;
+        let to = handle_table::get::<Pubkey>(to)?;
+        Ok(handle_table::insert(system_instruction::transfer(&from, &to, lamports)))
+    })
+}
+
+#[no_mangle]
+extern "C" fn system_create_account(from: Handle, to: Handle, lamports: u64, space: u64, owner: Handle) -> ResultExport<Handle> {
+    export_result(|| {
+        let from = handle_table::get::<Pubkey>(from)?;
+        let to = handle_table::get::<Pubkey>(to)?;
+        let owner = handle_table::get::<Pubkey>(owner)?;
+        Ok(handle_table::insert(system_instruction::create_account(&from, &to, lamports, space, &owner)))
+    })
+}
+
+/// `to` must be the address returned by `pubkey_create_with_seed(base, seed, owner)`.
+#[no_mangle]
+extern "C" fn system_create_account_with_seed(from: Handle, to: Handle, base: Handle, seed: *const c_char,
+                                              lamports: u64, space: u64, owner: Handle) -> ResultExport<Handle> {
+    export_result(|| {
+        let from = handle_table::get::<Pubkey>(from)?;
+        let to = handle_table::get::<Pubkey>(to)?;
+        let base = handle_table::get::<Pubkey>(base)?;
+        let seed = str_from_c_char_ptr(seed)?;
+        let owner = handle_table::get::<Pubkey>(owner)?;
+
+        check_seed_address(&to, &base, seed, &owner)?;
+        Ok(handle_table::insert(system_instruction::create_account_with_seed(&from, &to, &base, seed, lamports, space, &owner)))
+    })
+}
+
+#[no_mangle]
+extern "C" fn system_allocate(pubkey: Handle, space: u64) -> ResultExport<Handle> {
+    export_result(|| {
+        let pubkey = handle_table::get::<Pubkey>(pubkey)?;
+        Ok(handle_table::insert(system_instruction::allocate(&pubkey, space)))
+    })
+}
+
+#[no_mangle]
+extern "C" fn system_assign(pubkey: Handle, owner: Handle) -> ResultExport<Handle> {
+    export_result(|| {
+        let pubkey = handle_table::get::<Pubkey>(pubkey)?;
+        let owner = handle_table::get::<Pubkey>(owner)?;
+        Ok(handle_table::insert(system_instruction::assign(&pubkey, &owner)))
+    })
+}
+
+/// `from` must be the address returned by `pubkey_create_with_seed(from_base, from_seed, from_owner)`.
+#[no_mangle]
+extern "C" fn system_transfer_with_seed(from: Handle, from_base: Handle, from_seed: *const c_char, from_owner: Handle,
+                                        to: Handle, lamports: u64) -> ResultExport<Handle> {
+    export_result(|| {
+        let from = handle_table::get::<Pubkey>(from)?;
+        let from_base = handle_table::get::<Pubkey>(from_base)?;
+        let from_seed = str_from_c_char_ptr(from_seed)?;
+        let from_owner = handle_table::get::<Pubkey>(from_owner)?;
+        let to = handle_table::get::<Pubkey>(to)?;
+
+        check_seed_address(&from, &from_base, from_seed, &from_owner)?;
+        Ok(handle_table::insert(system_instruction::transfer_with_seed(
+            &from, &from_base, from_seed.to_string(), &from_owner, &to, lamports)))
+    })
+}
+
+/// Writes the create account and initialize nonce instructions to `out_instructions`,
+/// which must hold `CREATE_NONCE_ACCOUNT_INSTRUCTIONS` handles.
+#[no_mangle]
+extern "C" fn system_create_nonce_account(from: Handle, nonce: Handle, authority: Handle, lamports: u64,
+                                          out_instructions: *mut Handle, out_len: size_t) -> ResultExport<u32> {
+    export_result(|| {
+        let from = handle_table::get::<Pubkey>(from)?;
+        let nonce = handle_table::get::<Pubkey>(nonce)?;
+        let authority = handle_table::get::<Pubkey>(authority)?;
+
+        let instructions = system_instruction::create_nonce_account(&from, &nonce, &authority, lamports);
+        instructions_to_ptr(instructions, out_instructions, out_len)
+    })
+}
+
+/// Seeded variant of `system_create_nonce_account`; `nonce` must be the address
+/// returned by `pubkey_create_with_seed(base, seed, system program id)`.
+#[no_mangle]
+extern "C" fn system_create_nonce_account_with_seed(from: Handle, nonce: Handle, base: Handle, seed: *const c_char,
+                                                    authority: Handle, lamports: u64,
+                                                    out_instructions: *mut Handle, out_len: size_t) -> ResultExport<u32> {
+    export_result(|| {
+        let from = handle_table::get::<Pubkey>(from)?;
+        let nonce = handle_table::get::<Pubkey>(nonce)?;
+        let base = handle_table::get::<Pubkey>(base)?;
+        let seed = str_from_c_char_ptr(seed)?;
+        let authority = handle_table::get::<Pubkey>(authority)?;
+
+        check_seed_address(&nonce, &base, seed, &solana_program::system_program::id())?;
+        let instructions = system_instruction::create_nonce_account_with_seed(&from, &nonce, &base, seed, &authority, lamports);
+        instructions_to_ptr(instructions, out_instructions, out_len)
+    })
+}
+
+#[no_mangle]
+extern "C" fn system_advance_nonce_account(nonce: Handle, authority: Handle) -> ResultExport<Handle> {
+    export_result(|| {
+        let nonce = handle_table::get::<Pubkey>(nonce)?;
+        let authority = handle_table::get::<Pubkey>(authority)?;
+        Ok(handle_table::insert(system_instruction::advance_nonce_account(&nonce, &authority)))
+    })
+}
+
+#[no_mangle]
+extern "C" fn system_withdraw_nonce_account(nonce: Handle, authority: Handle, to: Handle, lamports: u64) -> ResultExport<Handle> {
+    export_result(|| {
+        let nonce = handle_table::get::<Pubkey>(nonce)?;
+        let authority = handle_table::get::<Pubkey>(authority)?;
+        let to = handle_table::get::<Pubkey>(to)?;
+        Ok(handle_table::insert(system_instruction::withdraw_nonce_account(&nonce, &authority, &to, lamports)))
+    })
+}
+
+#[no_mangle]
+extern "C" fn system_authorize_nonce_account(nonce: Handle, authority: Handle, new_authority: Handle) -> ResultExport<Handle> {
+    export_result(|| {
+        let nonce = handle_table::get::<Pubkey>(nonce)?;
+        let authority = handle_table::get::<Pubkey>(authority)?;
+        let new_authority = handle_table::get::<Pubkey>(new_authority)?;
+        Ok(handle_table::insert(system_instruction::authorize_nonce_account(&nonce, &authority, &new_authority)))
+    })
+}
+
+#[no_mangle]
+extern "C" fn system_upgrade_nonce_account(nonce: Handle) -> ResultExport<Handle> {
+    export_result(|| {
+        let nonce = handle_table::get::<Pubkey>(nonce)?;
+        Ok(handle_table::insert(system_instruction::upgrade_nonce_account(nonce)))
+    })
+}
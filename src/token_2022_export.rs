@@ -0,0 +1,367 @@
+use std::convert::TryFrom;
+
+use libc::size_t;
+use solana_program::pubkey::Pubkey;
+use spl_token_2022::extension::{BaseStateWithExtensions, ExtensionType, StateWithExtensions};
+use spl_token_2022::extension::metadata_pointer::{self, MetadataPointer};
+use spl_token_2022::extension::transfer_fee::{self, TransferFee, TransferFeeAmount, TransferFeeConfig};
+use spl_token_2022::instruction as token_instruction;
+use spl_token_2022::state::{Account, Mint};
+
+use crate::common_types::{ExportDefault, OptionExport, ResultExport, slice_from_ptr, to_raw_parts, VecRawParts};
+use crate::error_export::{export_result, ExportError};
+use crate::handle_table::{self, Handle};
+use crate::pubkey_export::pubkeys_from_ptr;
+use crate::spl_token_export::insert_instruction;
+
+/// Base mint state. Absent authorities are returned as a 0 handle.
+#[repr(C)]
+pub struct TokenMintExport {
+    pub mint_authority: Handle,
+    pub supply: u64,
+    pub decimals: u8,
+    pub is_initialized: u8,
+    pub freeze_authority: Handle,
+    /// `ExtensionType` values present on the mint, two little endian bytes each.
+    pub extension_types: VecRawParts,
+}
+
+impl ExportDefault for TokenMintExport {
+    fn export_default() -> Self {
+        TokenMintExport {
+            mint_authority: 0,
+            supply: 0,
+            decimals: 0,
+            is_initialized: 0,
+            freeze_authority: 0,
+            extension_types: VecRawParts::default(),
+        }
+    }
+}
+
+/// Base token account state. Absent delegate and close authority are returned as a 0 handle.
+#[repr(C)]
+pub struct TokenAccountExport {
+    pub mint: Handle,
+    pub owner: Handle,
+    pub amount: u64,
+    pub delegate: Handle,
+    /// 0 uninitialized, 1 initialized, 2 frozen.
+    pub state: u8,
+    /// Rent exempt reserve of a wrapped SOL account.
+    pub is_native: OptionExport<u64>,
+    pub delegated_amount: u64,
+    pub close_authority: Handle,
+    /// `ExtensionType` values present on the account, two little endian bytes each.
+    pub extension_types: VecRawParts,
+}
+
+impl ExportDefault for TokenAccountExport {
+    fn export_default() -> Self {
+        TokenAccountExport {
+            mint: 0,
+            owner: 0,
+            amount: 0,
+            delegate: 0,
+            state: 0,
+            is_native: OptionExport::export_default(),
+            delegated_amount: 0,
+            close_authority: 0,
+            extension_types: VecRawParts::default(),
+        }
+    }
+}
+
+#[repr(C)]
+pub struct TransferFeeExport {
+    pub epoch: u64,
+    pub maximum_fee: u64,
+    pub transfer_fee_basis_points: u16,
+}
+
+impl From<&TransferFee> for TransferFeeExport {
+    fn from(transfer_fee: &TransferFee) -> Self {
+        TransferFeeExport {
+            epoch: transfer_fee.epoch.into(),
+            maximum_fee: transfer_fee.maximum_fee.into(),
+            transfer_fee_basis_points: transfer_fee.transfer_fee_basis_points.into(),
+        }
+    }
+}
+
+#[repr(C)]
+pub struct TransferFeeConfigExport {
+    pub transfer_fee_config_authority: Handle,
+    pub withdraw_withheld_authority: Handle,
+    pub withheld_amount: u64,
+    pub older_transfer_fee: TransferFeeExport,
+    pub newer_transfer_fee: TransferFeeExport,
+}
+
+impl ExportDefault for TransferFeeConfigExport {
+    fn export_default() -> Self {
+        TransferFeeConfigExport {
+            transfer_fee_config_authority: 0,
+            withdraw_withheld_authority: 0,
+            withheld_amount: 0,
+            older_transfer_fee: TransferFeeExport { epoch: 0, maximum_fee: 0, transfer_fee_basis_points: 0 },
+            newer_transfer_fee: TransferFeeExport { epoch: 0, maximum_fee: 0, transfer_fee_basis_points: 0 },
+        }
+    }
+}
+
+#[repr(C)]
+pub struct MetadataPointerExport {
+    pub authority: Handle,
+    pub metadata_address: Handle,
+}
+
+impl ExportDefault for MetadataPointerExport {
+    fn export_default() -> Self {
+        MetadataPointerExport {
+            authority: 0,
+            metadata_address: 0,
+        }
+    }
+}
+
+fn optional_pubkey(pubkey: Handle) -> Result<Option<Pubkey>, ExportError> {
+    match pubkey {
+        0 => Ok(None),
+        pubkey => Ok(Some(handle_table::get::<Pubkey>(pubkey)?)),
+    }
+}
+
+fn optional_pubkey_handle(pubkey: Option<Pubkey>) -> Handle {
+    pubkey.map_or(0, handle_table::insert)
+}
+
+fn extension_types_to_raw_parts(extension_types: Vec<ExtensionType>) -> VecRawParts {
+    to_raw_parts(extension_types
+        .into_iter()
+        .flat_map(|extension_type| u16::from(extension_type).to_le_bytes())
+        .collect::<Vec<u8>>())
+}
+
+fn unpack_mint<'a>(data: *const u8, len: size_t) -> Result<StateWithExtensions<'a, Mint>, ExportError> {
+    Ok(StateWithExtensions::<Mint>::unpack(slice_from_ptr(data, len, "data")?)?)
+}
+
+fn unpack_account<'a>(data: *const u8, len: size_t) -> Result<StateWithExtensions<'a, Account>, ExportError> {
+    Ok(StateWithExtensions::<Account>::unpack(slice_from_ptr(data, len, "data")?)?)
+}
+
+fn transfer_fee_config<'a>(mint: &'a StateWithExtensions<Mint>) -> Result<Option<&'a TransferFeeConfig>, ExportError> {
+    if mint.get_extension_types()?.contains(&ExtensionType::TransferFeeConfig) {
+        Ok(Some(mint.get_extension::<TransferFeeConfig>()?))
+    } else {
+        Ok(None)
+    }
+}
+
+#[no_mangle]
+extern "C" fn token_2022_program_id() -> ResultExport<Handle> {
+    export_result(|| Ok(handle_table::insert(spl_token_2022::id())))
+}
+
+/// Account size of a Token-2022 mint carrying the given `ExtensionType` values,
+/// for the preceding `system_create_account`.
+#[no_mangle]
+extern "C" fn token_2022_mint_len(extension_types: *const u16, extension_types_len: size_t) -> ResultExport<u64> {
+    export_result(|| {
+        let extension_types = slice_from_ptr(extension_types, extension_types_len, "extension_types")?
+            .iter()
+            .map(|extension_type| ExtensionType::try_from(*extension_type)
+                .map_err(|_| ExportError::invalid_argument(format!("Unknown extension type {}", extension_type))))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(ExtensionType::try_calculate_account_len::<Mint>(&extension_types)? as u64)
+    })
+}
+
+/// Must follow the extension initialisation instructions of the mint.
+#[no_mangle]
+extern "C" fn token_2022_initialize_mint(mint: Handle, mint_authority: Handle, freeze_authority: Handle,
+                                         decimals: u8) -> ResultExport<Handle> {
+    export_result(|| {
+        let mint = handle_table::get::<Pubkey>(mint)?;
+        let mint_authority = handle_table::get::<Pubkey>(mint_authority)?;
+        let freeze_authority = optional_pubkey(freeze_authority)?;
+
+        insert_instruction(token_instruction::initialize_mint2(
+            &spl_token_2022::id(), &mint, &mint_authority, freeze_authority.as_ref(), decimals))
+    })
+}
+
+/// A zero authority handle leaves the corresponding authority unset.
+#[no_mangle]
+extern "C" fn token_2022_initialize_transfer_fee_config(mint: Handle, transfer_fee_config_authority: Handle,
+                                                        withdraw_withheld_authority: Handle,
+                                                        transfer_fee_basis_points: u16, maximum_fee: u64) -> ResultExport<Handle> {
+    export_result(|| {
+        let mint = handle_table::get::<Pubkey>(mint)?;
+        let transfer_fee_config_authority = optional_pubkey(transfer_fee_config_authority)?;
+        let withdraw_withheld_authority = optional_pubkey(withdraw_withheld_authority)?;
+
+        insert_instruction(transfer_fee::instruction::initialize_transfer_fee_config(
+            &spl_token_2022::id(), &mint, transfer_fee_config_authority.as_ref(),
+            withdraw_withheld_authority.as_ref(), transfer_fee_basis_points, maximum_fee))
+    })
+}
+
+/// A zero handle leaves the authority or metadata address unset.
+#[no_mangle]
+extern "C" fn token_2022_initialize_metadata_pointer(mint: Handle, authority: Handle, metadata_address: Handle) -> ResultExport<Handle> {
+    export_result(|| {
+        let mint = handle_table::get::<Pubkey>(mint)?;
+        let authority = optional_pubkey(authority)?;
+        let metadata_address = optional_pubkey(metadata_address)?;
+
+        insert_instruction(metadata_pointer::instruction::initialize(
+            &spl_token_2022::id(), &mint, authority, metadata_address))
+    })
+}
+
+#[no_mangle]
+extern "C" fn token_2022_initialize_non_transferable_mint(mint: Handle) -> ResultExport<Handle> {
+    export_result(|| {
+        let mint = handle_table::get::<Pubkey>(mint)?;
+        insert_instruction(token_instruction::initialize_non_transferable_mint(&spl_token_2022::id(), &mint))
+    })
+}
+
+/// `fee` must match the fee the mint charges for `amount`, see `token_2022_calculate_epoch_fee`.
+#[no_mangle]
+extern "C" fn token_2022_transfer_checked_with_fee(source: Handle, mint: Handle, destination: Handle, authority: Handle,
+                                                   signers: *const Handle, signers_len: size_t,
+                                                   amount: u64, decimals: u8, fee: u64) -> ResultExport<Handle> {
+    export_result(|| {
+        let source = handle_table::get::<Pubkey>(source)?;
+        let mint = handle_table::get::<Pubkey>(mint)?;
+        let destination = handle_table::get::<Pubkey>(destination)?;
+        let authority = handle_table::get::<Pubkey>(authority)?;
+        let signers = pubkeys_from_ptr(signers, signers_len)?;
+
+        insert_instruction(transfer_fee::instruction::transfer_checked_with_fee(
+            &spl_token_2022::id(), &source, &mint, &destination, &authority,
+            &signers.iter().collect::<Vec<_>>(), amount, decimals, fee))
+    })
+}
+
+/// Decodes mint account data, of either token program.
+#[no_mangle]
+extern "C" fn token_2022_unpack_mint(data: *const u8, len: size_t) -> ResultExport<TokenMintExport> {
+    export_result(|| {
+        let mint = unpack_mint(data, len)?;
+        let extension_types = mint.get_extension_types()?;
+
+        Ok(TokenMintExport {
+            mint_authority: optional_pubkey_handle(mint.base.mint_authority.into()),
+            supply: mint.base.supply,
+            decimals: mint.base.decimals,
+            is_initialized: mint.base.is_initialized as u8,
+            freeze_authority: optional_pubkey_handle(mint.base.freeze_authority.into()),
+            extension_types: extension_types_to_raw_parts(extension_types),
+        })
+    })
+}
+
+/// Decodes token account data, of either token program.
+#[no_mangle]
+extern "C" fn token_2022_unpack_account(data: *const u8, len: size_t) -> ResultExport<TokenAccountExport> {
+    export_result(|| {
+        let account = unpack_account(data, len)?;
+        let extension_types = account.get_extension_types()?;
+        let is_native: Option<u64> = account.base.is_native.into();
+
+        Ok(TokenAccountExport {
+            mint: handle_table::insert(account.base.mint),
+            owner: handle_table::insert(account.base.owner),
+            amount: account.base.amount,
+            delegate: optional_pubkey_handle(account.base.delegate.into()),
+            state: account.base.state as u8,
+            is_native: OptionExport {
+                has_value: is_native.is_some() as u8,
+                value: is_native.unwrap_or_default(),
+            },
+            delegated_amount: account.base.delegated_amount,
+            close_authority: optional_pubkey_handle(account.base.close_authority.into()),
+            extension_types: extension_types_to_raw_parts(extension_types),
+        })
+    })
+}
+
+#[no_mangle]
+extern "C" fn token_2022_mint_transfer_fee_config(data: *const u8, len: size_t) -> ResultExport<OptionExport<TransferFeeConfigExport>> {
+    export_result(|| {
+        let mint = unpack_mint(data, len)?;
+        Ok(match transfer_fee_config(&mint)? {
+            Some(config) => OptionExport {
+                has_value: true as u8,
+                value: TransferFeeConfigExport {
+                    transfer_fee_config_authority: optional_pubkey_handle(config.transfer_fee_config_authority.into()),
+                    withdraw_withheld_authority: optional_pubkey_handle(config.withdraw_withheld_authority.into()),
+                    withheld_amount: config.withheld_amount.into(),
+                    older_transfer_fee: (&config.older_transfer_fee).into(),
+                    newer_transfer_fee: (&config.newer_transfer_fee).into(),
+                },
+            },
+            None => OptionExport::export_default(),
+        })
+    })
+}
+
+/// Fee charged by the mint for transferring `amount` during `epoch`; 0 for mints without transfer fees.
+#[no_mangle]
+extern "C" fn token_2022_calculate_epoch_fee(data: *const u8, len: size_t, epoch: u64, amount: u64) -> ResultExport<u64> {
+    export_result(|| {
+        let mint = unpack_mint(data, len)?;
+        match transfer_fee_config(&mint)? {
+            Some(config) => config.calculate_epoch_fee(epoch, amount)
+                .ok_or_else(|| ExportError::invalid_argument("Transfer fee calculation overflowed")),
+            None => Ok(0),
+        }
+    })
+}
+
+#[no_mangle]
+extern "C" fn token_2022_mint_metadata_pointer(data: *const u8, len: size_t) -> ResultExport<OptionExport<MetadataPointerExport>> {
+    export_result(|| {
+        let mint = unpack_mint(data, len)?;
+        if !mint.get_extension_types()?.contains(&ExtensionType::MetadataPointer) {
+            return Ok(OptionExport::export_default());
+        }
+
+        let metadata_pointer = mint.get_extension::<MetadataPointer>()?;
+        Ok(OptionExport {
+            has_value: true as u8,
+            value: MetadataPointerExport {
+                authority: optional_pubkey_handle(metadata_pointer.authority.into()),
+                metadata_address: optional_pubkey_handle(metadata_pointer.metadata_address.into()),
+            },
+        })
+    })
+}
+
+#[no_mangle]
+extern "C" fn token_2022_mint_is_non_transferable(data: *const u8, len: size_t) -> ResultExport<u8> {
+    export_result(|| {
+        let mint = unpack_mint(data, len)?;
+        Ok(mint.get_extension_types()?.contains(&ExtensionType::NonTransferable) as u8)
+    })
+}
+
+/// Transfer fees withheld in a token account, awaiting harvest to the mint.
+#[no_mangle]
+extern "C" fn token_2022_account_withheld_amount(data: *const u8, len: size_t) -> ResultExport<OptionExport<u64>> {
+    export_result(|| {
+        let account = unpack_account(data, len)?;
+        if !account.get_extension_types()?.contains(&ExtensionType::TransferFeeAmount) {
+            return Ok(OptionExport::export_default());
+        }
+
+        Ok(OptionExport {
+            has_value: true as u8,
+            value: account.get_extension::<TransferFeeAmount>()?.withheld_amount.into(),
+        })
+    })
+}
@@ -0,0 +1,353 @@
+use libc::size_t;
+use solana_program::hash::Hash;
+use solana_program::instruction::{AccountMeta, Instruction};
+use solana_program::message::Message;
+use solana_program::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signature};
+use solana_sdk::transaction::Transaction;
+
+use crate::common_types::{ResultExport, slice_from_ptr, to_raw_parts, VecRawParts};
+use crate::error_export::{ErrorCode, export_result, ExportError};
+use crate::handle_table::{self, Handle, HandleObject, HandleType};
+
+#[repr(C)]
+pub struct AccountMetaExport {
+    pub pubkey: Handle,
+    pub is_signer: u8,
+    pub is_writable: u8,
+}
+
+impl HandleObject for Instruction {
+    const HANDLE_TYPE: HandleType = HandleType::Instruction;
+}
+
+impl HandleObject for Message {
+    const HANDLE_TYPE: HandleType = HandleType::Message;
+}
+
+impl HandleObject for Transaction {
+    const HANDLE_TYPE: HandleType = HandleType::Transaction;
+}
+
+pub fn account_metas_from_ptr(accounts: *const AccountMetaExport, len: size_t) -> Result<Vec<AccountMeta>, ExportError> {
+    slice_from_ptr(accounts, len, "accounts")?
+        .iter()
+        .map(|account| {
+            Ok(AccountMeta {
+                pubkey: handle_table::get::<Pubkey>(account.pubkey)?,
+                is_signer: account.is_signer != 0,
+                is_writable: account.is_writable != 0,
+            })
+        })
+        .collect()
+}
+
+pub fn instructions_from_ptr(instructions: *const Handle, len: size_t) -> Result<Vec<Instruction>, ExportError> {
+    slice_from_ptr(instructions, len, "instructions")?
+        .iter()
+        .map(|instruction| handle_table::get::<Instruction>(*instruction))
+        .collect()
+}
+
+/// Hands `instructions` to the caller through `out_instructions`, which must have
+/// room for all of them. Returns the number of handles written.
+pub fn instructions_to_ptr(instructions: Vec<Instruction>, out_instructions: *mut Handle, out_len: size_t) -> Result<u32, ExportError> {
+    handle_table::insert_into_ptr(instructions, out_instructions, out_len, "out_instructions")
+}
+
+#[no_mangle]
+extern "C" fn instruction_new(program_id: Handle, accounts: *const AccountMetaExport, accounts_len: size_t,
+                              data: *const u8, data_len: size_t) -> ResultExport<Handle> {
+    export_result(|| {
+        let program_id = handle_table::get::<Pubkey>(program_id)?;
+        let accounts = account_metas_from_ptr(accounts, accounts_len)?;
+        let data = slice_from_ptr(data, data_len, "data")?;
+
+        Ok(handle_table::insert(Instruction::new_with_bytes(program_id, data, accounts)))
+    })
+}
+
+#[no_mangle]
+extern "C" fn instruction_program_id(instruction: Handle) -> ResultExport<Handle> {
+    export_result(|| {
+        let program_id = handle_table::with(instruction, |instruction: &mut Instruction| instruction.program_id)?;
+        Ok(handle_table::insert(program_id))
+    })
+}
+
+#[no_mangle]
+extern "C" fn instruction_data(instruction: Handle) -> ResultExport<VecRawParts> {
+    export_result(|| {
+        handle_table::with(instruction, |instruction: &mut Instruction| to_raw_parts(instruction.data.clone()))
+    })
+}
+
+#[no_mangle]
+extern "C" fn free_instruction(instruction: Handle) -> ResultExport<u8> {
+    export_result(|| {
+        handle_table::remove::<Instruction>(instruction)?;
+        Ok(true as u8)
+    })
+}
+
+/// Compiles `instructions` into a legacy message paid for by `payer`.
+#[no_mangle]
+extern "C" fn message_new_with_blockhash(instructions: *const Handle, instructions_len: size_t,
+                                         payer: Handle, blockhash: Handle) -> ResultExport<Handle> {
+    export_result(|| {
+        let instructions = instructions_from_ptr(instructions, instructions_len)?;
+        let payer = handle_table::get::<Pubkey>(payer)?;
+        let blockhash = handle_table::get::<Hash>(blockhash)?;
+
+        Ok(handle_table::insert(Message::new_with_blockhash(&instructions, Some(&payer), &blockhash)))
+    })
+}
+
+#[no_mangle]
+extern "C" fn message_serialize(message: Handle) -> ResultExport<VecRawParts> {
+    export_result(|| {
+        handle_table::with(message, |message: &mut Message| to_raw_parts(message.serialize()))
+    })
+}
+
+#[no_mangle]
+extern "C" fn message_recent_blockhash(message: Handle) -> ResultExport<Handle> {
+    export_result(|| {
+        let blockhash = handle_table::with(message, |message: &mut Message| message.recent_blockhash)?;
+        Ok(handle_table::insert(blockhash))
+    })
+}
+
+#[no_mangle]
+extern "C" fn free_message(message: Handle) -> ResultExport<u8> {
+    export_result(|| {
+        handle_table::remove::<Message>(message)?;
+        Ok(true as u8)
+    })
+}
+
+/// Creates a transaction without signatures from a compiled message.
+#[no_mangle]
+extern "C" fn transaction_new_unsigned(message: Handle) -> ResultExport<Handle> {
+    export_result(|| {
+        let message = handle_table::get::<Message>(message)?;
+        Ok(handle_table::insert(Transaction::new_unsigned(message)))
+    })
+}
+
+/// Shortcut for `message_new_with_blockhash` followed by `transaction_new_unsigned`.
+#[no_mangle]
+extern "C" fn transaction_new_with_payer(instructions: *const Handle, instructions_len: size_t,
+                                         payer: Handle, blockhash: Handle) -> ResultExport<Handle> {
+    export_result(|| {
+        let instructions = instructions_from_ptr(instructions, instructions_len)?;
+        let payer = handle_table::get::<Pubkey>(payer)?;
+        let blockhash = handle_table::get::<Hash>(blockhash)?;
+
+        let message = Message::new_with_blockhash(&instructions, Some(&payer), &blockhash);
+        Ok(handle_table::insert(Transaction::new_unsigned(message)))
+    })
+}
+
+#[no_mangle]
+extern "C" fn transaction_message(transaction: Handle) -> ResultExport<Handle> {
+    export_result(|| {
+        let message = handle_table::with(transaction, |transaction: &mut Transaction| transaction.message.clone())?;
+        Ok(handle_table::insert(message))
+    })
+}
+
+/// Bytes covered by the transaction signatures.
+#[no_mangle]
+extern "C" fn transaction_message_data(transaction: Handle) -> ResultExport<VecRawParts> {
+    export_result(|| {
+        handle_table::with(transaction, |transaction: &mut Transaction| to_raw_parts(transaction.message_data()))
+    })
+}
+
+pub fn keypairs_from_ptr(keypairs: *const Handle, len: size_t) -> Result<Vec<Keypair>, ExportError> {
+    slice_from_ptr(keypairs, len, "keypairs")?
+        .iter()
+        .map(|keypair| handle_table::with(*keypair, |keypair: &mut Keypair| keypair.insecure_clone()))
+        .collect()
+}
+
+/// A zero `blockhash` handle keeps the blockhash already in the message, which is
+/// what co-signers of an existing transaction want.
+fn blockhash_or_current(blockhash: Handle, transaction: &Transaction) -> Result<Hash, ExportError> {
+    if blockhash == 0 {
+        Ok(transaction.message.recent_blockhash)
+    } else {
+        handle_table::get::<Hash>(blockhash)
+    }
+}
+
+fn missing_signer_indexes(transaction: &Transaction) -> Vec<u8> {
+    transaction
+        .signatures
+        .iter()
+        .enumerate()
+        .filter(|(_, signature)| **signature == Signature::default())
+        .map(|(index, _)| index as u8)
+        .collect()
+}
+
+/// Signs with every required signer, failing with `MissingSigners` if `keypairs` does
+/// not cover all of them. The transaction is left untouched when signing fails.
+#[no_mangle]
+extern "C" fn transaction_sign(transaction: Handle, keypairs: *const Handle, keypairs_len: size_t,
+                               blockhash: Handle) -> ResultExport<u8> {
+    export_result(|| {
+        let keypairs = keypairs_from_ptr(keypairs, keypairs_len)?;
+        handle_table::with(transaction, |transaction: &mut Transaction| {
+            let blockhash = blockhash_or_current(blockhash, transaction)?;
+            let mut signed = transaction.clone();
+            signed.try_sign(&keypairs.iter().collect::<Vec<_>>(), blockhash)?;
+            *transaction = signed;
+            Ok(true as u8)
+        })?
+    })
+}
+
+/// Signs with a subset of the required signers and returns how many are still missing.
+///
+/// Changing the blockhash clears the signatures collected so far.
+#[no_mangle]
+extern "C" fn transaction_partial_sign(transaction: Handle, keypairs: *const Handle, keypairs_len: size_t,
+                                       blockhash: Handle) -> ResultExport<u32> {
+    export_result(|| {
+        let keypairs = keypairs_from_ptr(keypairs, keypairs_len)?;
+        handle_table::with(transaction, |transaction: &mut Transaction| {
+            let blockhash = blockhash_or_current(blockhash, transaction)?;
+            transaction.try_partial_sign(&keypairs.iter().collect::<Vec<_>>(), blockhash)?;
+            Ok(missing_signer_indexes(transaction).len() as u32)
+        })?
+    })
+}
+
+/// Same as `solana_sdk`'s `Transaction::try_sign`: signatures made before a
+/// `MissingSigners` failure are kept, so the remaining signers can be added later.
+#[no_mangle]
+extern "C" fn transaction_try_sign(transaction: Handle, keypairs: *const Handle, keypairs_len: size_t,
+                                   blockhash: Handle) -> ResultExport<u8> {
+    export_result(|| {
+        let keypairs = keypairs_from_ptr(keypairs, keypairs_len)?;
+        handle_table::with(transaction, |transaction: &mut Transaction| {
+            let blockhash = blockhash_or_current(blockhash, transaction)?;
+            transaction.try_sign(&keypairs.iter().collect::<Vec<_>>(), blockhash)?;
+            Ok(true as u8)
+        })?
+    })
+}
+
+#[no_mangle]
+extern "C" fn transaction_is_signed(transaction: Handle) -> ResultExport<u8> {
+    export_result(|| {
+        handle_table::with(transaction, |transaction: &mut Transaction| transaction.is_signed() as u8)
+    })
+}
+
+/// Indexes of the required signers that have not signed yet, one byte per index.
+#[no_mangle]
+extern "C" fn transaction_missing_signers(transaction: Handle) -> ResultExport<VecRawParts> {
+    export_result(|| {
+        handle_table::with(transaction, |transaction: &mut Transaction| to_raw_parts(missing_signer_indexes(transaction)))
+    })
+}
+
+#[no_mangle]
+extern "C" fn transaction_num_required_signers(transaction: Handle) -> ResultExport<u32> {
+    export_result(|| {
+        handle_table::with(transaction, |transaction: &mut Transaction| {
+            transaction.message.header.num_required_signatures as u32
+        })
+    })
+}
+
+/// Pubkey of the required signer at `index`.
+#[no_mangle]
+extern "C" fn transaction_signer_key(transaction: Handle, index: u32) -> ResultExport<Handle> {
+    export_result(|| {
+        let pubkey = handle_table::with(transaction, |transaction: &mut Transaction| {
+            signer_at(transaction, index).map(|index| transaction.message.account_keys[index])
+        })??;
+        Ok(handle_table::insert(pubkey))
+    })
+}
+
+/// Position of `pubkey` among the required signers.
+#[no_mangle]
+extern "C" fn transaction_signer_index(transaction: Handle, pubkey: Handle) -> ResultExport<u32> {
+    export_result(|| {
+        let pubkey = handle_table::get::<Pubkey>(pubkey)?;
+        handle_table::with(transaction, |transaction: &mut Transaction| {
+            transaction.message.signer_keys()
+                .iter()
+                .position(|signer| **signer == pubkey)
+                .map(|index| index as u32)
+                .ok_or_else(|| ExportError::new(
+                    ErrorCode::KeypairPubkeyMismatch,
+                    format!("{} is not a required signer", pubkey),
+                ))
+        })?
+    })
+}
+
+/// Attaches a signature produced elsewhere, e.g. by the player's wallet, to the
+/// required signer at `index`. The signature must verify against the message.
+#[no_mangle]
+extern "C" fn transaction_add_signature(transaction: Handle, index: u32, signature: Handle) -> ResultExport<u8> {
+    export_result(|| {
+        let signature = handle_table::get::<Signature>(signature)?;
+        handle_table::with(transaction, |transaction: &mut Transaction| {
+            let index = signer_at(transaction, index)?;
+            let signer = transaction.message.account_keys[index];
+            if !signature.verify(signer.as_ref(), &transaction.message_data()) {
+                return Err(ExportError::new(
+                    ErrorCode::SignatureVerificationFailed,
+                    format!("Signature does not verify for signer {}", signer),
+                ));
+            }
+            transaction.signatures[index] = signature;
+            Ok(true as u8)
+        })?
+    })
+}
+
+#[no_mangle]
+extern "C" fn transaction_signature(transaction: Handle, index: u32) -> ResultExport<Handle> {
+    export_result(|| {
+        let signature = handle_table::with(transaction, |transaction: &mut Transaction| {
+            signer_at(transaction, index).map(|index| transaction.signatures[index])
+        })??;
+        Ok(handle_table::insert(signature))
+    })
+}
+
+fn signer_at(transaction: &Transaction, index: u32) -> Result<usize, ExportError> {
+    let num_signers = transaction.signatures.len();
+    if (index as usize) < num_signers {
+        Ok(index as usize)
+    } else {
+        Err(ExportError::invalid_argument(format!(
+            "Signer index {} is out of range, the transaction has {} signers", index, num_signers
+        )))
+    }
+}
+
+/// Wire format of the transaction, ready to be base58/base64 encoded and sent.
+#[no_mangle]
+extern "C" fn transaction_serialize(transaction: Handle) -> ResultExport<VecRawParts> {
+    export_result(|| {
+        let bytes = handle_table::with(transaction, |transaction: &mut Transaction| bincode::serialize(transaction))??;
+        Ok(to_raw_parts(bytes))
+    })
+}
+
+#[no_mangle]
+extern "C" fn free_transaction(transaction: Handle) -> ResultExport<u8> {
+    export_result(|| {
+        handle_table::remove::<Transaction>(transaction)?;
+        Ok(true as u8)
+    })
+}
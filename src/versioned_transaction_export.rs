@@ -0,0 +1,408 @@
+use std::os::raw::c_char;
+
+use libc::size_t;
+use solana_program::address_lookup_table::AddressLookupTableAccount;
+use solana_program::hash::Hash;
+use solana_program::instruction::CompiledInstruction;
+use solana_program::message::{v0, VersionedMessage};
+use solana_program::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signature, Signer};
+use solana_sdk::transaction::{Transaction, TransactionVersion, VersionedTransaction};
+
+use crate::c_sharp_string::str_from_c_char_ptr;
+use crate::common_types::{ExportDefault, OptionExport, ResultExport, slice_from_ptr, to_raw_parts, VecRawParts};
+use crate::error_export::{ErrorCode, export_result, ExportError};
+use crate::handle_table::{self, Handle, HandleObject, HandleType};
+use crate::pubkey_export::pubkeys_from_ptr;
+use crate::transaction_export::{AccountMetaExport, instructions_from_ptr, keypairs_from_ptr};
+
+#[repr(C)]
+pub struct CompiledInstructionExport {
+    pub program_id_index: u8,
+    /// Indexes into the message account keys, one byte each.
+    pub accounts: VecRawParts,
+    pub data: VecRawParts,
+}
+
+impl ExportDefault for CompiledInstructionExport {
+    fn export_default() -> Self {
+        CompiledInstructionExport {
+            program_id_index: 0,
+            accounts: VecRawParts::default(),
+            data: VecRawParts::default(),
+        }
+    }
+}
+
+impl ExportDefault for AccountMetaExport {
+    fn export_default() -> Self {
+        AccountMetaExport {
+            pubkey: 0,
+            is_signer: 0,
+            is_writable: 0,
+        }
+    }
+}
+
+#[repr(C)]
+pub struct AddressTableLookupExport {
+    pub account_key: Handle,
+    /// Indexes into the table of writable addresses, one byte each.
+    pub writable_indexes: VecRawParts,
+    /// Indexes into the table of readonly addresses, one byte each.
+    pub readonly_indexes: VecRawParts,
+}
+
+impl ExportDefault for AddressTableLookupExport {
+    fn export_default() -> Self {
+        AddressTableLookupExport {
+            account_key: 0,
+            writable_indexes: VecRawParts::default(),
+            readonly_indexes: VecRawParts::default(),
+        }
+    }
+}
+
+impl HandleObject for VersionedTransaction {
+    const HANDLE_TYPE: HandleType = HandleType::VersionedTransaction;
+}
+
+impl HandleObject for VersionedMessage {
+    const HANDLE_TYPE: HandleType = HandleType::VersionedMessage;
+}
+
+impl HandleObject for AddressLookupTableAccount {
+    const HANDLE_TYPE: HandleType = HandleType::AddressLookupTableAccount;
+}
+
+fn versioned_transaction_from_bytes(bytes: &[u8]) -> Result<Handle, ExportError> {
+    let transaction: VersionedTransaction = bincode::deserialize(bytes)?;
+    transaction.sanitize()?;
+    Ok(handle_table::insert(transaction))
+}
+
+fn check_index(index: u32, len: usize, name: &str) -> Result<usize, ExportError> {
+    if (index as usize) < len {
+        Ok(index as usize)
+    } else {
+        Err(ExportError::invalid_argument(format!(
+            "{} index {} is out of range, the transaction has {}", name, index, len
+        )))
+    }
+}
+
+/// Signs `transaction` with the keypairs that are among its required signers, leaving
+/// other signatures untouched. Returns the number of signers still missing.
+fn partial_sign(transaction: &mut VersionedTransaction, keypairs: &[Keypair]) -> Result<u32, ExportError> {
+    let num_required = transaction.message.header().num_required_signatures as usize;
+    let signer_keys = &transaction.message.static_account_keys()[..num_required];
+    let message_data = transaction.message.serialize();
+
+    let mut signatures = transaction.signatures.clone();
+    signatures.resize(num_required, Signature::default());
+    for keypair in keypairs {
+        let position = signer_keys
+            .iter()
+            .position(|signer| *signer == keypair.pubkey())
+            .ok_or_else(|| ExportError::new(
+                ErrorCode::KeypairPubkeyMismatch,
+                format!("{} is not a required signer", keypair.pubkey()),
+            ))?;
+        signatures[position] = keypair.sign_message(&message_data);
+    }
+    transaction.signatures = signatures;
+
+    Ok(transaction.signatures.iter().filter(|signature| **signature == Signature::default()).count() as u32)
+}
+
+#[no_mangle]
+extern "C" fn address_lookup_table_account_new(key: Handle, addresses: *const Handle, addresses_len: size_t) -> ResultExport<Handle> {
+    export_result(|| {
+        let key = handle_table::get::<Pubkey>(key)?;
+        let addresses = pubkeys_from_ptr(addresses, addresses_len)?;
+        Ok(handle_table::insert(AddressLookupTableAccount { key, addresses }))
+    })
+}
+
+#[no_mangle]
+extern "C" fn free_address_lookup_table_account(table: Handle) -> ResultExport<u8> {
+    export_result(|| {
+        handle_table::remove::<AddressLookupTableAccount>(table)?;
+        Ok(true as u8)
+    })
+}
+
+/// Compiles `instructions` into a v0 message, moving every non-signer account found
+/// in `lookup_tables` out of the static account keys.
+#[no_mangle]
+extern "C" fn v0_message_try_compile(payer: Handle, instructions: *const Handle, instructions_len: size_t,
+                                     lookup_tables: *const Handle, lookup_tables_len: size_t,
+                                     blockhash: Handle) -> ResultExport<Handle> {
+    export_result(|| {
+        let payer = handle_table::get::<Pubkey>(payer)?;
+        let instructions = instructions_from_ptr(instructions, instructions_len)?;
+        let lookup_tables = slice_from_ptr(lookup_tables, lookup_tables_len, "lookup_tables")?
+            .iter()
+            .map(|table| handle_table::get::<AddressLookupTableAccount>(*table))
+            .collect::<Result<Vec<_>, _>>()?;
+        let blockhash = handle_table::get::<Hash>(blockhash)?;
+
+        let message = v0::Message::try_compile(&payer, &instructions, &lookup_tables, blockhash)?;
+        Ok(handle_table::insert(VersionedMessage::V0(message)))
+    })
+}
+
+#[no_mangle]
+extern "C" fn versioned_message_serialize(message: Handle) -> ResultExport<VecRawParts> {
+    export_result(|| {
+        handle_table::with(message, |message: &mut VersionedMessage| to_raw_parts(message.serialize()))
+    })
+}
+
+#[no_mangle]
+extern "C" fn free_versioned_message(message: Handle) -> ResultExport<u8> {
+    export_result(|| {
+        handle_table::remove::<VersionedMessage>(message)?;
+        Ok(true as u8)
+    })
+}
+
+/// Creates a transaction with an empty signature slot for every required signer.
+#[no_mangle]
+extern "C" fn versioned_transaction_new_unsigned(message: Handle) -> ResultExport<Handle> {
+    export_result(|| {
+        let message = handle_table::get::<VersionedMessage>(message)?;
+        let signatures = vec![Signature::default(); message.header().num_required_signatures as usize];
+        Ok(handle_table::insert(VersionedTransaction { signatures, message }))
+    })
+}
+
+/// Creates a transaction signed by all of its required signers.
+#[no_mangle]
+extern "C" fn versioned_transaction_try_new(message: Handle, keypairs: *const Handle, keypairs_len: size_t) -> ResultExport<Handle> {
+    export_result(|| {
+        let message = handle_table::get::<VersionedMessage>(message)?;
+        let keypairs = keypairs_from_ptr(keypairs, keypairs_len)?;
+        let transaction = VersionedTransaction::try_new(message, &keypairs.iter().collect::<Vec<_>>())?;
+        Ok(handle_table::insert(transaction))
+    })
+}
+
+/// Signs with a subset of the required signers and returns how many are still missing.
+#[no_mangle]
+extern "C" fn versioned_transaction_partial_sign(transaction: Handle, keypairs: *const Handle, keypairs_len: size_t) -> ResultExport<u32> {
+    export_result(|| {
+        let keypairs = keypairs_from_ptr(keypairs, keypairs_len)?;
+        handle_table::with(transaction, |transaction: &mut VersionedTransaction| partial_sign(transaction, &keypairs))?
+    })
+}
+
+/// Deserializes a legacy or versioned transaction from its wire format.
+#[no_mangle]
+extern "C" fn versioned_transaction_deserialize(bytes: *const u8, len: size_t) -> ResultExport<Handle> {
+    export_result(|| versioned_transaction_from_bytes(slice_from_ptr(bytes, len, "bytes")?))
+}
+
+#[no_mangle]
+extern "C" fn versioned_transaction_from_base64(s: *const c_char) -> ResultExport<Handle> {
+    export_result(|| {
+        let bytes = base64::decode(str_from_c_char_ptr(s)?)
+            .map_err(|error| ExportError::new(ErrorCode::InvalidBase64, error.to_string()))?;
+        versioned_transaction_from_bytes(&bytes)
+    })
+}
+
+#[no_mangle]
+extern "C" fn versioned_transaction_from_base58(s: *const c_char) -> ResultExport<Handle> {
+    export_result(|| {
+        let bytes = bs58::decode(str_from_c_char_ptr(s)?)
+            .into_vec()
+            .map_err(|error| ExportError::new(ErrorCode::InvalidBase58, error.to_string()))?;
+        versioned_transaction_from_bytes(&bytes)
+    })
+}
+
+/// Wraps a legacy transaction built with `transaction_export`.
+#[no_mangle]
+extern "C" fn versioned_transaction_from_legacy(transaction: Handle) -> ResultExport<Handle> {
+    export_result(|| {
+        let transaction = handle_table::get::<Transaction>(transaction)?;
+        Ok(handle_table::insert(VersionedTransaction::from(transaction)))
+    })
+}
+
+#[no_mangle]
+extern "C" fn versioned_transaction_serialize(transaction: Handle) -> ResultExport<VecRawParts> {
+    export_result(|| {
+        let bytes = handle_table::with(transaction, |transaction: &mut VersionedTransaction| bincode::serialize(transaction))??;
+        Ok(to_raw_parts(bytes))
+    })
+}
+
+/// Bytes covered by the transaction signatures.
+#[no_mangle]
+extern "C" fn versioned_transaction_message_data(transaction: Handle) -> ResultExport<VecRawParts> {
+    export_result(|| {
+        handle_table::with(transaction, |transaction: &mut VersionedTransaction| {
+            to_raw_parts(transaction.message.serialize())
+        })
+    })
+}
+
+#[no_mangle]
+extern "C" fn versioned_transaction_fee_payer(transaction: Handle) -> ResultExport<Handle> {
+    export_result(|| {
+        let fee_payer = handle_table::with(transaction, |transaction: &mut VersionedTransaction| {
+            transaction.message.static_account_keys().first().copied()
+        })?
+        .ok_or_else(|| ExportError::new(ErrorCode::InvalidTransaction, "Transaction has no account keys"))?;
+        Ok(handle_table::insert(fee_payer))
+    })
+}
+
+#[no_mangle]
+extern "C" fn versioned_transaction_recent_blockhash(transaction: Handle) -> ResultExport<Handle> {
+    export_result(|| {
+        let blockhash = handle_table::with(transaction, |transaction: &mut VersionedTransaction| {
+            *transaction.message.recent_blockhash()
+        })?;
+        Ok(handle_table::insert(blockhash))
+    })
+}
+
+/// Number of account keys stored in the message itself; keys loaded from address
+/// lookup tables are not included.
+#[no_mangle]
+extern "C" fn versioned_transaction_num_account_keys(transaction: Handle) -> ResultExport<u32> {
+    export_result(|| {
+        handle_table::with(transaction, |transaction: &mut VersionedTransaction| {
+            transaction.message.static_account_keys().len() as u32
+        })
+    })
+}
+
+/// Account key at `index` with its signer and writable flags.
+#[no_mangle]
+extern "C" fn versioned_transaction_account_key(transaction: Handle, index: u32) -> ResultExport<AccountMetaExport> {
+    export_result(|| {
+        let (pubkey, is_signer, is_writable) = handle_table::with(transaction, |transaction: &mut VersionedTransaction| {
+            let message = &transaction.message;
+            let index = check_index(index, message.static_account_keys().len(), "Account key")?;
+            Ok::<_, ExportError>((
+                message.static_account_keys()[index],
+                message.is_signer(index),
+                message.is_maybe_writable(index),
+            ))
+        })??;
+
+        Ok(AccountMetaExport {
+            pubkey: handle_table::insert(pubkey),
+            is_signer: is_signer as u8,
+            is_writable: is_writable as u8,
+        })
+    })
+}
+
+#[no_mangle]
+extern "C" fn versioned_transaction_num_instructions(transaction: Handle) -> ResultExport<u32> {
+    export_result(|| {
+        handle_table::with(transaction, |transaction: &mut VersionedTransaction| {
+            transaction.message.instructions().len() as u32
+        })
+    })
+}
+
+/// Compiled instruction at `index`; both buffers must be released with `free_vec`.
+#[no_mangle]
+extern "C" fn versioned_transaction_instruction(transaction: Handle, index: u32) -> ResultExport<CompiledInstructionExport> {
+    export_result(|| {
+        let instruction = handle_table::with(transaction, |transaction: &mut VersionedTransaction| {
+            let instructions = transaction.message.instructions();
+            let index = check_index(index, instructions.len(), "Instruction")?;
+            Ok::<CompiledInstruction, ExportError>(instructions[index].clone())
+        })??;
+
+        Ok(CompiledInstructionExport {
+            program_id_index: instruction.program_id_index,
+            accounts: to_raw_parts(instruction.accounts),
+            data: to_raw_parts(instruction.data),
+        })
+    })
+}
+
+#[no_mangle]
+extern "C" fn versioned_transaction_num_signatures(transaction: Handle) -> ResultExport<u32> {
+    export_result(|| {
+        handle_table::with(transaction, |transaction: &mut VersionedTransaction| {
+            transaction.signatures.len() as u32
+        })
+    })
+}
+
+/// Signature at `index`; unsigned slots hold the all-zero signature.
+#[no_mangle]
+extern "C" fn versioned_transaction_signature(transaction: Handle, index: u32) -> ResultExport<Handle> {
+    export_result(|| {
+        let signature = handle_table::with(transaction, |transaction: &mut VersionedTransaction| {
+            let index = check_index(index, transaction.signatures.len(), "Signature")?;
+            Ok::<_, ExportError>(transaction.signatures[index])
+        })??;
+        Ok(handle_table::insert(signature))
+    })
+}
+
+/// Message version: no value for legacy transactions, the version number otherwise.
+#[no_mangle]
+extern "C" fn versioned_transaction_version(transaction: Handle) -> ResultExport<OptionExport<u8>> {
+    export_result(|| {
+        handle_table::with(transaction, |transaction: &mut VersionedTransaction| match transaction.version() {
+            TransactionVersion::Legacy(_) => OptionExport { has_value: 0, value: 0 },
+            TransactionVersion::Number(version) => OptionExport { has_value: 1, value: version },
+        })
+    })
+}
+
+#[no_mangle]
+extern "C" fn versioned_transaction_num_address_table_lookups(transaction: Handle) -> ResultExport<u32> {
+    export_result(|| {
+        handle_table::with(transaction, |transaction: &mut VersionedTransaction| {
+            transaction.message.address_table_lookups().map_or(0, |lookups| lookups.len() as u32)
+        })
+    })
+}
+
+/// Address table lookup at `index`; both buffers must be released with `free_vec`.
+#[no_mangle]
+extern "C" fn versioned_transaction_address_table_lookup(transaction: Handle, index: u32) -> ResultExport<AddressTableLookupExport> {
+    export_result(|| {
+        let lookup = handle_table::with(transaction, |transaction: &mut VersionedTransaction| {
+            let lookups = transaction.message.address_table_lookups().unwrap_or_default();
+            let index = check_index(index, lookups.len(), "Address table lookup")?;
+            Ok::<_, ExportError>(lookups[index].clone())
+        })??;
+
+        Ok(AddressTableLookupExport {
+            account_key: handle_table::insert(lookup.account_key),
+            writable_indexes: to_raw_parts(lookup.writable_indexes),
+            readonly_indexes: to_raw_parts(lookup.readonly_indexes),
+        })
+    })
+}
+
+/// Returns 1 if every signature verifies against its signer, 0 otherwise.
+#[no_mangle]
+extern "C" fn versioned_transaction_verify(transaction: Handle) -> ResultExport<u8> {
+    export_result(|| {
+        handle_table::with(transaction, |transaction: &mut VersionedTransaction| {
+            transaction.verify_with_results().iter().all(|verified| *verified) as u8
+        })
+    })
+}
+
+#[no_mangle]
+extern "C" fn free_versioned_transaction(transaction: Handle) -> ResultExport<u8> {
+    export_result(|| {
+        handle_table::remove::<VersionedTransaction>(transaction)?;
+        Ok(true as u8)
+    })
+}